@@ -1,3 +1,46 @@
+// Команды приложения. Для каждой tauri-build генерирует разрешения
+// allow-<команда>/deny-<команда>, окну доступны только выданные в
+// capabilities/default.json
+const COMMANDS: &[&str] = &[
+    "start",
+    "stop",
+    "current_state",
+    "get_status_details",
+    "get_recent_errors",
+    "update_auth_token",
+    "get_auth_token_info",
+    "reveal_auth_token",
+    "import_auth_token_from_file",
+    "enroll_with_code",
+    "get_connection_history",
+    "start_link",
+    "stop_link",
+    "get_link_status",
+    "list_links",
+    "update_link_auth_token",
+    "get_settings",
+    "update_settings",
+    "get_server_config",
+    "update_server_config",
+    "get_schedule_config",
+    "update_schedule_config",
+    "get_schedule_status",
+    "get_idle_status",
+    "get_bandwidth_status",
+    "keep_secure_link_connected",
+    "get_update_status",
+    "check_for_updates",
+    "restart_to_update",
+    "get_shortcut_status",
+    "get_managed_policy",
+    "check_connection",
+    "get_service_log",
+];
+
+fn attributes() -> tauri_build::Attributes {
+    tauri_build::Attributes::new().app_manifest(tauri_build::AppManifest::new().commands(COMMANDS))
+}
+
 fn main() {
     println!("cargo:rerun-if-env-changed=SECURE_LINK_SERVER_HOST");
     println!("cargo:rerun-if-env-changed=SECURE_LINK_SERVER_PORT");
@@ -12,7 +55,7 @@ fn main() {
 
     #[cfg(not(target_os = "windows"))]
     {
-        tauri_build::build();
+        tauri_build::try_build(attributes()).expect("failed to run build script");
     }
 }
 
@@ -44,7 +87,7 @@ fn build_tauri_with_embed_admin_manifest() {
     "#,
     );
 
-    tauri_build::try_build(tauri_build::Attributes::new().windows_attributes(windows))
+    tauri_build::try_build(attributes().windows_attributes(windows))
         .expect("failed to run build script");
 }
 
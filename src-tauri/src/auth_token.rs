@@ -0,0 +1,146 @@
+// The auth token can only be read through `expose`, so every place that
+// handles the secret itself is easy to find. `Debug` and `Display` print a
+// fingerprint instead, and the memory is wiped on drop.
+
+use serde::{Deserialize, Deserializer, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use zeroize::Zeroize;
+
+const FINGERPRINT_LEN: usize = 8;
+// Shorter tokens are masked entirely
+const MIN_PREVIEW_LEN: usize = 16;
+const PREVIEW_CHARS: usize = 4;
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct AuthToken(String);
+
+impl AuthToken {
+    pub fn new(auth_token: String) -> Self {
+        Self(auth_token)
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    // Hex of the first bytes of the SHA-256, like `sha256:1a2b3c4d5e6f7a8b`.
+    // Tells tokens apart without giving anything away.
+    pub fn fingerprint(&self) -> String {
+        let hash = Sha256::digest(self.0.as_bytes());
+
+        let hex: String = hash[..FINGERPRINT_LEN]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+
+        format!("sha256:{}", hex)
+    }
+
+    // A few characters from both ends, like `eyJh…cret`, enough to recognise
+    // the token without revealing it
+    pub fn masked_preview(&self) -> String {
+        let chars: Vec<char> = self.0.chars().collect();
+
+        if chars.len() < MIN_PREVIEW_LEN {
+            return "••••••••".to_string();
+        }
+
+        let head: String = chars[..PREVIEW_CHARS].iter().collect();
+        let tail: String = chars[chars.len() - PREVIEW_CHARS..].iter().collect();
+
+        format!("{}…{}", head, tail)
+    }
+}
+
+// What the UI gets to know about the stored token
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthTokenInfo {
+    pub present: bool,
+    pub fingerprint: Option<String>,
+    pub masked_preview: Option<String>,
+    // Unix seconds
+    pub stored_at: Option<u64>,
+    pub expires_at: Option<u64>,
+}
+
+impl AuthTokenInfo {
+    pub fn new(auth_token: Option<&AuthToken>, stored_at: Option<u64>) -> Self {
+        let Some(auth_token) = auth_token else {
+            return Self::default();
+        };
+
+        Self {
+            present: true,
+            fingerprint: Some(auth_token.fingerprint()),
+            masked_preview: Some(auth_token.masked_preview()),
+            stored_at,
+            expires_at: crate::auth_token_expiry::auth_token_expires_at(auth_token.expose()),
+        }
+    }
+}
+
+impl Drop for AuthToken {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl fmt::Debug for AuthToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AuthToken({})", self.fingerprint())
+    }
+}
+
+impl fmt::Display for AuthToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.fingerprint())
+    }
+}
+
+// Commands take the token as an argument. There is deliberately no
+// `Serialize`, the token is never sent back.
+impl<'de> Deserialize<'de> for AuthToken {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_only_the_fingerprint() {
+        let auth_token = AuthToken::new("eyJhbGciOiJIUzI1NiJ9.secret".to_string());
+
+        assert_eq!(auth_token.fingerprint(), "sha256:a547b8e15a04efc5");
+        assert_eq!(auth_token.to_string(), auth_token.fingerprint());
+        assert_eq!(
+            format!("{:?}", Some(&auth_token)),
+            "Some(AuthToken(sha256:a547b8e15a04efc5))"
+        );
+        assert_eq!(auth_token.expose(), "eyJhbGciOiJIUzI1NiJ9.secret");
+    }
+
+    #[test]
+    fn info_carries_no_secret() {
+        let auth_token = AuthToken::new("eyJhbGciOiJIUzI1NiJ9.secret".to_string());
+        let info = AuthTokenInfo::new(Some(&auth_token), Some(1700000000));
+
+        assert!(info.present);
+        assert_eq!(info.masked_preview.as_deref(), Some("eyJh…cret"));
+        assert_eq!(info.stored_at, Some(1700000000));
+
+        let json = serde_json::to_string(&info).unwrap();
+        assert!(!json.contains("secret"));
+        assert!(!json.contains("HUzI1NiJ9"));
+
+        assert_eq!(
+            AuthToken::new("short".to_string()).masked_preview(),
+            "••••••••"
+        );
+        assert_eq!(AuthTokenInfo::new(None, None), AuthTokenInfo::default());
+    }
+}
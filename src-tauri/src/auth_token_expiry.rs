@@ -0,0 +1,53 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL_SAFE_NO_PAD;
+use base64::Engine;
+
+// Tokens issued as JWTs carry their expiry in the `exp` claim. It is read
+// without checking the signature, so it is only good for display and
+// monitoring. Returns unix seconds, `None` for opaque tokens.
+pub fn auth_token_expires_at(auth_token: &str) -> Option<u64> {
+    let mut segments = auth_token.trim().split('.');
+
+    let (Some(_header), Some(payload), Some(_signature), None) = (
+        segments.next(),
+        segments.next(),
+        segments.next(),
+        segments.next(),
+    ) else {
+        return None;
+    };
+
+    let payload = BASE64_URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .ok()?;
+    let claims: serde_json::Value = serde_json::from_slice(&payload).ok()?;
+
+    claims.get("exp")?.as_u64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jwt(claims: &str) -> String {
+        format!(
+            "{}.{}.signature",
+            BASE64_URL_SAFE_NO_PAD.encode(r#"{"alg":"HS256","typ":"JWT"}"#),
+            BASE64_URL_SAFE_NO_PAD.encode(claims)
+        )
+    }
+
+    #[test]
+    fn reads_exp_claim() {
+        assert_eq!(
+            auth_token_expires_at(&jwt(r#"{"sub":"device-1","exp":1893456000}"#)),
+            Some(1893456000)
+        );
+    }
+
+    #[test]
+    fn opaque_tokens_have_no_expiry() {
+        assert_eq!(auth_token_expires_at("plain-opaque-token"), None);
+        assert_eq!(auth_token_expires_at(&jwt(r#"{"sub":"device-1"}"#)), None);
+        assert_eq!(auth_token_expires_at("a.!!!.c"), None);
+    }
+}
@@ -0,0 +1,319 @@
+// Auth tokens imported from a file: a plain text token, a JSON enrollment file
+// or a PNG or JPEG image with a QR code holding either. QR codes are decoded
+// locally, the image never leaves the machine.
+
+use crate::auth_token::AuthToken;
+use crate::auth_token_expiry::auth_token_expires_at;
+use crate::settings::EndpointSettings;
+use serde::Deserialize;
+use std::io::Read;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zeroize::Zeroize;
+
+// A photo of an onboarding sheet stays well below this
+pub const MAX_IMPORT_FILE_SIZE: u64 = 20 * 1024 * 1024;
+
+const MAX_AUTH_TOKEN_LEN: usize = 8192;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const JPEG_SIGNATURE: &[u8] = &[0xff, 0xd8, 0xff];
+
+// `{"token": "...", "endpoint": {"host": "...", "port": 443}, "profileName": "..."}`
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EnrollmentFile {
+    token: AuthToken,
+    #[serde(default)]
+    endpoint: Option<EndpointSettings>,
+    #[serde(default)]
+    profile_name: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct ImportedAuthToken {
+    pub auth_token: AuthToken,
+    // Only enrollment files carry these
+    pub endpoint: Option<EndpointSettings>,
+    pub profile_name: Option<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthTokenImportError {
+    #[error("File can not be read: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("File is larger than {MAX_IMPORT_FILE_SIZE} bytes")]
+    TooLarge,
+
+    #[error("Image can not be decoded: {0}")]
+    Image(#[from] image::ImageError),
+
+    #[error("Image has no QR code")]
+    NoQrCode,
+
+    #[error("QR code can not be read: {0}")]
+    QrCode(String),
+
+    #[error("Enrollment file is malformed: {0}")]
+    MalformedEnrollment(#[from] serde_json::Error),
+
+    #[error("Auth token is empty")]
+    EmptyAuthToken,
+
+    #[error("Auth token is longer than {MAX_AUTH_TOKEN_LEN} characters")]
+    AuthTokenTooLong,
+
+    #[error("Auth token has characters other than visible ASCII")]
+    InvalidAuthTokenCharacters,
+
+    #[error("Auth token has expired")]
+    ExpiredAuthToken,
+}
+
+pub fn import_auth_token_file(path: &Path) -> Result<ImportedAuthToken, AuthTokenImportError> {
+    let mut content = Vec::new();
+
+    std::fs::File::open(path)?
+        .take(MAX_IMPORT_FILE_SIZE + 1)
+        .read_to_end(&mut content)?;
+
+    let imported = if content.len() as u64 > MAX_IMPORT_FILE_SIZE {
+        Err(AuthTokenImportError::TooLarge)
+    } else {
+        parse_auth_token_import(&content)
+    };
+
+    content.zeroize();
+
+    imported
+}
+
+// The format is told by the content, not the file extension
+pub fn parse_auth_token_import(content: &[u8]) -> Result<ImportedAuthToken, AuthTokenImportError> {
+    if content.starts_with(PNG_SIGNATURE) || content.starts_with(JPEG_SIGNATURE) {
+        return import_from_qr_code(content);
+    }
+
+    let text = std::str::from_utf8(content)
+        .map_err(|_| AuthTokenImportError::InvalidAuthTokenCharacters)?;
+
+    parse_text(text)
+}
+
+// A sheet may carry other codes too, e.g. a link to the instructions, so the
+// first code that holds a valid token wins
+fn import_from_qr_code(content: &[u8]) -> Result<ImportedAuthToken, AuthTokenImportError> {
+    let image = image::load_from_memory(content)?.to_luma8();
+
+    let mut prepared_image = rqrr::PreparedImage::prepare_from_greyscale(
+        image.width() as usize,
+        image.height() as usize,
+        |x, y| image.get_pixel(x as u32, y as u32).0[0],
+    );
+
+    let mut imported = Err(AuthTokenImportError::NoQrCode);
+
+    for grid in prepared_image.detect_grids() {
+        imported = match grid.decode() {
+            Ok((_, mut text)) => {
+                let imported = parse_text(&text);
+                text.zeroize();
+                imported
+            }
+            Err(e) => Err(AuthTokenImportError::QrCode(e.to_string())),
+        };
+
+        if imported.is_ok() {
+            break;
+        }
+    }
+
+    imported
+}
+
+fn parse_text(text: &str) -> Result<ImportedAuthToken, AuthTokenImportError> {
+    let text = text.trim_start_matches('\u{feff}').trim();
+
+    if text.starts_with('{') {
+        let enrollment: EnrollmentFile = serde_json::from_str(text)?;
+        validate_auth_token(&enrollment.token)?;
+
+        return Ok(ImportedAuthToken {
+            auth_token: enrollment.token,
+            endpoint: enrollment.endpoint,
+            profile_name: enrollment
+                .profile_name
+                .filter(|profile_name| !profile_name.trim().is_empty()),
+        });
+    }
+
+    let auth_token = AuthToken::new(text.to_string());
+    validate_auth_token(&auth_token)?;
+
+    Ok(ImportedAuthToken {
+        auth_token,
+        endpoint: None,
+        profile_name: None,
+    })
+}
+
+// Catches the usual typos of a token copied by hand: stray spaces, line
+// breaks in the middle and look-alike characters from a word processor
+pub fn validate_auth_token(auth_token: &AuthToken) -> Result<(), AuthTokenImportError> {
+    let auth_token = auth_token.expose();
+
+    if auth_token.is_empty() {
+        return Err(AuthTokenImportError::EmptyAuthToken);
+    }
+
+    if auth_token.len() > MAX_AUTH_TOKEN_LEN {
+        return Err(AuthTokenImportError::AuthTokenTooLong);
+    }
+
+    if !auth_token.chars().all(|c| c.is_ascii_graphic()) {
+        return Err(AuthTokenImportError::InvalidAuthTokenCharacters);
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    if auth_token_expires_at(auth_token).is_some_and(|expires_at| expires_at <= now) {
+        return Err(AuthTokenImportError::ExpiredAuthToken);
+    }
+
+    Ok(())
+}
+
+// Id of a profile created for `profile_name`, made of its ASCII letters and
+// digits and unique among the ids `is_taken` knows
+pub fn profile_id_for_name(profile_name: &str, is_taken: impl Fn(&str) -> bool) -> String {
+    let mut base_id = String::new();
+
+    for c in profile_name.chars() {
+        if c.is_ascii_alphanumeric() {
+            base_id.push(c.to_ascii_lowercase());
+        } else if !base_id.is_empty() && !base_id.ends_with('-') {
+            base_id.push('-');
+        }
+    }
+
+    base_id.truncate(24);
+    let base_id = match base_id.trim_end_matches('-') {
+        "" | crate::settings::DEFAULT_PROFILE_ID => "profile".to_string(),
+        base_id => base_id.to_string(),
+    };
+
+    let mut profile_id = base_id.clone();
+    let mut suffix = 2;
+
+    while is_taken(&profile_id) {
+        profile_id = format!("{}-{}", base_id, suffix);
+        suffix += 1;
+    }
+
+    profile_id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use std::io::Cursor;
+
+    fn qr_code_png(text: &str) -> Vec<u8> {
+        const SCALE: u32 = 8;
+        const QUIET_ZONE: u32 = 4;
+
+        let code = qrcode::QrCode::new(text.as_bytes()).unwrap();
+        let width = code.width() as u32;
+        let colors = code.to_colors();
+
+        let size = (width + 2 * QUIET_ZONE) * SCALE;
+        let image = image::GrayImage::from_fn(size, size, |x, y| {
+            let (x, y) = (x / SCALE, y / SCALE);
+            let is_dark = (QUIET_ZONE..QUIET_ZONE + width).contains(&x)
+                && (QUIET_ZONE..QUIET_ZONE + width).contains(&y)
+                && colors[((y - QUIET_ZONE) * width + (x - QUIET_ZONE)) as usize]
+                    == qrcode::Color::Dark;
+
+            image::Luma([if is_dark { 0 } else { 255 }])
+        });
+
+        let mut png = Cursor::new(Vec::new());
+        image.write_to(&mut png, image::ImageFormat::Png).unwrap();
+        png.into_inner()
+    }
+
+    #[test]
+    fn imports_plain_text_and_enrollment_files() {
+        let imported = parse_auth_token_import(b"\xef\xbb\xbftoken-1234\r\n").unwrap();
+        assert_eq!(imported.auth_token.expose(), "token-1234");
+        assert_eq!(imported.endpoint, None);
+
+        let imported = parse_auth_token_import(
+            br#"{"token": "token-5678", "endpoint": {"host": "link.example.com", "port": 443}, "profileName": "Office"}"#,
+        )
+        .unwrap();
+        assert_eq!(imported.auth_token.expose(), "token-5678");
+        assert_eq!(imported.endpoint.unwrap().host, "link.example.com");
+        assert_eq!(imported.profile_name.as_deref(), Some("Office"));
+    }
+
+    #[test]
+    fn imports_qr_codes() {
+        let imported = parse_auth_token_import(&qr_code_png(
+            r#"{"token": "token-qr", "profileName": "Office"}"#,
+        ))
+        .unwrap();
+        assert_eq!(imported.auth_token.expose(), "token-qr");
+        assert_eq!(imported.profile_name.as_deref(), Some("Office"));
+
+        let blank = image::GrayImage::from_pixel(64, 64, image::Luma([255]));
+        let mut png = Cursor::new(Vec::new());
+        blank.write_to(&mut png, image::ImageFormat::Png).unwrap();
+
+        assert!(matches!(
+            parse_auth_token_import(png.get_ref()),
+            Err(AuthTokenImportError::NoQrCode)
+        ));
+    }
+
+    #[test]
+    fn rejects_mistyped_and_expired_tokens() {
+        assert!(matches!(
+            parse_auth_token_import(b"token 1234"),
+            Err(AuthTokenImportError::InvalidAuthTokenCharacters)
+        ));
+        assert!(matches!(
+            parse_auth_token_import(b"  \n"),
+            Err(AuthTokenImportError::EmptyAuthToken)
+        ));
+        assert!(matches!(
+            parse_auth_token_import(br#"{"token": "token-1234", "endpoint": "link"}"#),
+            Err(AuthTokenImportError::MalformedEnrollment(_))
+        ));
+
+        let expired_jwt = format!(
+            "e30.{}.signature",
+            BASE64_URL_SAFE_NO_PAD.encode(r#"{"exp": 1000}"#)
+        );
+        assert!(matches!(
+            parse_auth_token_import(expired_jwt.as_bytes()),
+            Err(AuthTokenImportError::ExpiredAuthToken)
+        ));
+    }
+
+    #[test]
+    fn derives_unique_profile_ids() {
+        assert_eq!(profile_id_for_name("Main Office", |_| false), "main-office");
+        assert_eq!(profile_id_for_name("Офис", |_| false), "profile");
+        assert_eq!(
+            profile_id_for_name("Office", |id| id == "office" || id == "office-2"),
+            "office-3"
+        );
+    }
+}
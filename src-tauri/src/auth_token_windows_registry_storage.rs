@@ -1,6 +1,9 @@
 static REGISTRY_KEY_PATH: &str = "SOFTWARE\\SecureLink";
 static REGISTRY_AUTH_TOKEN_VALUE: &str = "Auth Token";
+static REGISTRY_STORED_AT_SUFFIX: &str = "Stored At";
+static REGISTRY_PROXY_PASSWORD_VALUE: &str = "Proxy Password";
 
+use crate::auth_token::AuthToken;
 use winreg::enums::HKEY_LOCAL_MACHINE;
 use winreg::types::ToRegValue;
 use winreg::RegKey;
@@ -30,13 +33,67 @@ fn load_optional_entry_from_registry<T: winreg::types::FromRegValue>(
         Err(e) => Err(Box::new(e)),
     }
 }
-pub fn load_auth_token() -> Result<Option<String>, Box<dyn std::error::Error>> {
-    Ok(load_optional_entry_from_registry(
-        REGISTRY_AUTH_TOKEN_VALUE,
-    )?)
+// The default link keeps the value the service reads
+fn auth_token_value_name(profile_id: &str) -> String {
+    if profile_id == crate::settings::DEFAULT_PROFILE_ID {
+        REGISTRY_AUTH_TOKEN_VALUE.to_string()
+    } else {
+        format!("{} ({})", REGISTRY_AUTH_TOKEN_VALUE, profile_id)
+    }
+}
+
+fn stored_at_value_name(profile_id: &str) -> String {
+    format!(
+        "{} {}",
+        auth_token_value_name(profile_id),
+        REGISTRY_STORED_AT_SUFFIX
+    )
+}
+
+pub fn load_auth_token(profile_id: &str) -> Result<Option<AuthToken>, Box<dyn std::error::Error>> {
+    Ok(
+        load_optional_entry_from_registry::<String>(&auth_token_value_name(profile_id))?
+            .map(AuthToken::new),
+    )
 }
 
-pub fn store_auth_token(auth_token: &str) -> Result<(), Box<dyn std::error::Error>> {
-    store_entry_in_registry::<String>(REGISTRY_AUTH_TOKEN_VALUE, &auth_token.to_string())?;
+pub fn store_auth_token(
+    profile_id: &str,
+    auth_token: &AuthToken,
+) -> Result<(), Box<dyn std::error::Error>> {
+    store_entry_in_registry::<&str>(&auth_token_value_name(profile_id), &auth_token.expose())?;
+
+    let stored_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    store_entry_in_registry::<u64>(&stored_at_value_name(profile_id), &stored_at)?;
+
     Ok(())
 }
+
+// Unix seconds of the last `store_auth_token`, `None` for tokens stored by
+// older versions
+pub fn load_auth_token_stored_at(
+    profile_id: &str,
+) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+    load_optional_entry_from_registry::<u64>(&stored_at_value_name(profile_id))
+}
+
+// One password for the proxy of all links
+pub fn load_proxy_password() -> Result<Option<String>, Box<dyn std::error::Error>> {
+    load_optional_entry_from_registry::<String>(REGISTRY_PROXY_PASSWORD_VALUE)
+}
+
+// An empty password removes the stored one
+pub fn store_proxy_password(password: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if password.is_empty() {
+        match get_service_reg_key()?.delete_value(REGISTRY_PROXY_PASSWORD_VALUE) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(Box::new(e)),
+            _ => {}
+        }
+
+        return Ok(());
+    }
+
+    store_entry_in_registry::<&str>(REGISTRY_PROXY_PASSWORD_VALUE, &password)
+}
@@ -0,0 +1,268 @@
+use crate::secure_link_client::SecureLinkTraffic;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Throughput is averaged over at least this long
+const MIN_THROUGHPUT_INTERVAL: Duration = Duration::from_millis(500);
+
+// `None` leaves a direction unlimited
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BandwidthLimit {
+    #[serde(default)]
+    pub upload_bytes_per_second: Option<u64>,
+    #[serde(default)]
+    pub download_bytes_per_second: Option<u64>,
+}
+
+impl BandwidthLimit {
+    pub fn is_valid(&self) -> bool {
+        self.upload_bytes_per_second != Some(0) && self.download_bytes_per_second != Some(0)
+    }
+}
+
+// Refills at `rate` bytes per second and holds at most one second worth of
+// bytes, which is the largest burst let through
+#[derive(Debug)]
+struct TokenBucket {
+    rate: Option<u64>,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: Option<u64>) -> Self {
+        Self {
+            rate,
+            tokens: rate.unwrap_or(0) as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn set_rate(&mut self, rate: Option<u64>, now: Instant) {
+        self.refill(now);
+        self.rate = rate;
+        self.tokens = self.tokens.min(rate.unwrap_or(0) as f64);
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if let Some(rate) = self.rate {
+            let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * rate as f64).min(rate as f64);
+        }
+
+        self.last_refill = now;
+    }
+
+    // How many bytes may pass now, or how long until the next one may
+    fn available(&mut self, now: Instant) -> Result<usize, Duration> {
+        let Some(rate) = self.rate else {
+            return Ok(usize::MAX);
+        };
+
+        self.refill(now);
+
+        if self.tokens >= 1.0 {
+            Ok(self.tokens as usize)
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / rate as f64))
+        }
+    }
+
+    fn consume(&mut self, bytes: usize) {
+        if self.rate.is_some() {
+            self.tokens -= bytes as f64;
+        }
+    }
+}
+
+// Shared by the app and the stream under the running link, so changed limits
+// apply without reconnecting
+#[derive(Debug)]
+pub struct BandwidthLimiter {
+    upload: Mutex<TokenBucket>,
+    download: Mutex<TokenBucket>,
+}
+
+impl BandwidthLimiter {
+    pub fn new(limit: &BandwidthLimit) -> Self {
+        Self {
+            upload: Mutex::new(TokenBucket::new(limit.upload_bytes_per_second)),
+            download: Mutex::new(TokenBucket::new(limit.download_bytes_per_second)),
+        }
+    }
+
+    pub fn set_limit(&self, limit: &BandwidthLimit) {
+        let now = Instant::now();
+
+        self.upload
+            .lock()
+            .unwrap()
+            .set_rate(limit.upload_bytes_per_second, now);
+        self.download
+            .lock()
+            .unwrap()
+            .set_rate(limit.download_bytes_per_second, now);
+    }
+
+    pub fn upload_available(&self, now: Instant) -> Result<usize, Duration> {
+        self.upload.lock().unwrap().available(now)
+    }
+
+    pub fn consume_upload(&self, bytes: usize) {
+        self.upload.lock().unwrap().consume(bytes)
+    }
+
+    pub fn download_available(&self, now: Instant) -> Result<usize, Duration> {
+        self.download.lock().unwrap().available(now)
+    }
+
+    pub fn consume_download(&self, bytes: usize) {
+        self.download.lock().unwrap().consume(bytes)
+    }
+}
+
+impl Default for BandwidthLimiter {
+    fn default() -> Self {
+        Self::new(&BandwidthLimit::default())
+    }
+}
+
+// Bytes per second in each direction
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Throughput {
+    pub upload_bytes_per_second: u64,
+    pub download_bytes_per_second: u64,
+}
+
+// Turns samples of the traffic counters into throughput
+#[derive(Default)]
+pub struct ThroughputMeter {
+    last_sample: Option<(Instant, SecureLinkTraffic)>,
+    throughput: Throughput,
+}
+
+impl ThroughputMeter {
+    pub fn observe(&mut self, traffic: Option<SecureLinkTraffic>, now: Instant) -> Throughput {
+        let Some(traffic) = traffic else {
+            *self = Self::default();
+            return self.throughput;
+        };
+
+        let Some((sampled_at, sampled_traffic)) = self.last_sample else {
+            self.last_sample = Some((now, traffic));
+            return self.throughput;
+        };
+
+        let elapsed = now.duration_since(sampled_at);
+
+        if elapsed < MIN_THROUGHPUT_INTERVAL {
+            return self.throughput;
+        }
+
+        // Counters going back mean a new session
+        let bytes_per_second = |bytes: u64, sampled_bytes: u64| {
+            (bytes.saturating_sub(sampled_bytes) as f64 / elapsed.as_secs_f64()) as u64
+        };
+
+        self.throughput = Throughput {
+            upload_bytes_per_second: bytes_per_second(
+                traffic.bytes_sent,
+                sampled_traffic.bytes_sent,
+            ),
+            download_bytes_per_second: bytes_per_second(
+                traffic.bytes_received,
+                sampled_traffic.bytes_received,
+            ),
+        };
+        self.last_sample = Some((now, traffic));
+
+        self.throughput
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_refills_at_the_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(Some(1000));
+        bucket.last_refill = start;
+
+        assert_eq!(bucket.available(start), Ok(1000));
+
+        bucket.consume(1000);
+
+        assert_eq!(bucket.available(start), Err(Duration::from_millis(1)));
+        assert_eq!(
+            bucket.available(start + Duration::from_millis(250)),
+            Ok(250)
+        );
+
+        // Never more than one second worth of bytes
+        assert_eq!(bucket.available(start + Duration::from_secs(10)), Ok(1000));
+    }
+
+    #[test]
+    fn unlimited_bucket_lets_everything_through() {
+        let mut bucket = TokenBucket::new(None);
+
+        bucket.consume(1 << 30);
+
+        assert_eq!(bucket.available(Instant::now()), Ok(usize::MAX));
+    }
+
+    #[test]
+    fn lowering_the_rate_caps_saved_up_bytes() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(Some(10_000));
+        bucket.last_refill = start;
+
+        bucket.set_rate(Some(100), start);
+
+        assert_eq!(bucket.available(start), Ok(100));
+
+        bucket.set_rate(None, start);
+
+        assert_eq!(bucket.available(start), Ok(usize::MAX));
+    }
+
+    #[test]
+    fn throughput_from_counter_samples() {
+        let start = Instant::now();
+        let mut meter = ThroughputMeter::default();
+
+        let traffic = |bytes_sent, bytes_received| {
+            Some(SecureLinkTraffic {
+                bytes_sent,
+                bytes_received,
+            })
+        };
+
+        assert_eq!(meter.observe(traffic(0, 0), start), Throughput::default());
+        assert_eq!(
+            meter.observe(traffic(2_000, 500), start + Duration::from_secs(2)),
+            Throughput {
+                upload_bytes_per_second: 1_000,
+                download_bytes_per_second: 250,
+            }
+        );
+
+        // Too soon for a new average
+        assert_eq!(
+            meter
+                .observe(traffic(9_000, 9_000), start + Duration::from_millis(2_100))
+                .upload_bytes_per_second,
+            1_000
+        );
+
+        assert_eq!(
+            meter.observe(None, start + Duration::from_secs(3)),
+            Throughput::default()
+        );
+    }
+}
@@ -0,0 +1,169 @@
+// Recent failures of a link, so the UI can tell why it disconnected. The log
+// belongs to the link rather than the client, so it survives the client being
+// recreated for a new auth token or server config.
+
+use crate::connection_history::unix_time_millis;
+use crate::secure_link_supervisor::SecureLinkSupervisorError;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+pub const MAX_CLIENT_ERRORS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ClientErrorCategory {
+    // The server ended the session, not an error of the link itself
+    ServerClosed,
+    Network,
+    Unauthorized,
+    CertificatePinMismatch,
+    TrustConfig,
+    // No auth token, or it could not be read
+    AuthToken,
+    Other,
+}
+
+impl ClientErrorCategory {
+    pub fn of(error: &SecureLinkSupervisorError) -> Self {
+        match error {
+            SecureLinkSupervisorError::NoAuthToken
+            | SecureLinkSupervisorError::AuthTokenStorage(_) => ClientErrorCategory::AuthToken,
+            SecureLinkSupervisorError::Unauthorized => ClientErrorCategory::Unauthorized,
+            SecureLinkSupervisorError::CertificatePinMismatch => {
+                ClientErrorCategory::CertificatePinMismatch
+            }
+            SecureLinkSupervisorError::Network(_) => ClientErrorCategory::Network,
+            SecureLinkSupervisorError::TrustConfig(_) => ClientErrorCategory::TrustConfig,
+            #[cfg(feature = "secure-link-windows-service-client")]
+            SecureLinkSupervisorError::Other(_) => ClientErrorCategory::Other,
+            SecureLinkSupervisorError::SupervisorGone => ClientErrorCategory::Other,
+        }
+    }
+}
+
+// What the UI shows next to the category, without the error name the
+// category already tells
+pub fn client_error_message(error: &SecureLinkSupervisorError) -> String {
+    match error {
+        SecureLinkSupervisorError::Network(message)
+        | SecureLinkSupervisorError::TrustConfig(message) => message.clone(),
+        #[cfg(feature = "secure-link-windows-service-client")]
+        SecureLinkSupervisorError::Other(message) => message.clone(),
+        error => error.to_string(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientErrorRecord {
+    // Increasing within the log
+    pub id: u64,
+    // Unix millis
+    pub at: u64,
+    pub category: ClientErrorCategory,
+    pub message: String,
+}
+
+type ClientErrorListener = Box<dyn Fn(&ClientErrorRecord) + Send + Sync>;
+
+pub struct ClientErrorLog {
+    records: Mutex<VecDeque<ClientErrorRecord>>,
+    next_id: Mutex<u64>,
+    // Called for every new record, the app emits an event from it
+    listener: ClientErrorListener,
+}
+
+impl ClientErrorLog {
+    pub fn new(listener: impl Fn(&ClientErrorRecord) + Send + Sync + 'static) -> Self {
+        Self {
+            records: Mutex::new(VecDeque::new()),
+            next_id: Mutex::new(0),
+            listener: Box::new(listener),
+        }
+    }
+
+    pub fn record(&self, category: ClientErrorCategory, message: String) {
+        let record = {
+            let mut next_id = self.next_id.lock().unwrap();
+            *next_id += 1;
+
+            ClientErrorRecord {
+                id: *next_id,
+                at: unix_time_millis(SystemTime::now()),
+                category,
+                message,
+            }
+        };
+
+        {
+            let mut records = self.records.lock().unwrap();
+
+            if records.len() == MAX_CLIENT_ERRORS {
+                records.pop_front();
+            }
+            records.push_back(record.clone());
+        }
+
+        (self.listener)(&record);
+    }
+
+    // Oldest first
+    pub fn recent(&self) -> Vec<ClientErrorRecord> {
+        self.records.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn last(&self) -> Option<ClientErrorRecord> {
+        self.records.lock().unwrap().back().cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn keeps_the_most_recent_errors() {
+        let notified = Arc::new(Mutex::new(Vec::new()));
+        let log = ClientErrorLog::new({
+            let notified = notified.clone();
+            move |record: &ClientErrorRecord| notified.lock().unwrap().push(record.id)
+        });
+
+        for i in 0..MAX_CLIENT_ERRORS + 2 {
+            log.record(ClientErrorCategory::Network, format!("error {}", i));
+        }
+
+        let recent = log.recent();
+        assert_eq!(recent.len(), MAX_CLIENT_ERRORS);
+        assert_eq!(recent[0].message, "error 2");
+        assert_eq!(log.last().unwrap().id, (MAX_CLIENT_ERRORS + 2) as u64);
+        assert_eq!(notified.lock().unwrap().len(), MAX_CLIENT_ERRORS + 2);
+    }
+
+    #[test]
+    fn categorizes_supervisor_errors() {
+        assert_eq!(
+            ClientErrorCategory::of(&SecureLinkSupervisorError::Unauthorized),
+            ClientErrorCategory::Unauthorized
+        );
+        assert_eq!(
+            ClientErrorCategory::of(&SecureLinkSupervisorError::Network(
+                "connection refused".to_string()
+            )),
+            ClientErrorCategory::Network
+        );
+        assert_eq!(
+            ClientErrorCategory::of(&SecureLinkSupervisorError::TrustConfig(
+                "no certificates in ca.pem".to_string()
+            )),
+            ClientErrorCategory::TrustConfig
+        );
+        assert_eq!(
+            ClientErrorCategory::of(&SecureLinkSupervisorError::NoAuthToken),
+            ClientErrorCategory::AuthToken
+        );
+    }
+}
@@ -0,0 +1,97 @@
+// Command line arguments understood by the app. A second launch forwards its
+// arguments to the running instance through the single instance plugin, so
+// these can be bound to desktop shortcuts or management tools:
+//
+//   --connect                  connect the secure link
+//   --disconnect               disconnect the secure link
+//   --toggle                   connect when stopped, disconnect otherwise
+//   --set-token-file <path>    replace the auth token with the file contents
+//   --show                     show the main window
+//   --quit                     close the app
+
+use log::warn;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandLineAction {
+    Connect,
+    Disconnect,
+    Toggle,
+    SetTokenFile(PathBuf),
+    Show,
+    Quit,
+}
+
+// `args` must not contain the executable path. Relative token file paths are
+// resolved against `cwd`, which is the working directory of the launch that
+// passed them, not of the running instance.
+pub fn parse_command_line_actions(args: &[String], cwd: &str) -> Vec<CommandLineAction> {
+    let mut actions = Vec::new();
+    let mut args_iter = args.iter();
+
+    while let Some(arg) = args_iter.next() {
+        match arg.as_str() {
+            "--connect" => actions.push(CommandLineAction::Connect),
+            "--disconnect" => actions.push(CommandLineAction::Disconnect),
+            "--toggle" => actions.push(CommandLineAction::Toggle),
+            "--show" => actions.push(CommandLineAction::Show),
+            "--quit" => actions.push(CommandLineAction::Quit),
+            "--set-token-file" => match args_iter.next() {
+                Some(path) => actions.push(CommandLineAction::SetTokenFile(
+                    std::path::Path::new(cwd).join(path),
+                )),
+                None => warn!("--set-token-file requires a path argument"),
+            },
+            unknown => warn!("ignoring unknown command line argument {unknown}"),
+        }
+    }
+
+    actions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn skips_unknown_flags() {
+        assert_eq!(
+            parse_command_line_actions(&args(&["--connect", "--verbose", "--show"]), "/"),
+            vec![CommandLineAction::Connect, CommandLineAction::Show]
+        );
+    }
+
+    #[test]
+    fn skips_a_token_file_flag_without_a_path() {
+        assert_eq!(
+            parse_command_line_actions(&args(&["--toggle", "--set-token-file"]), "/"),
+            vec![CommandLineAction::Toggle]
+        );
+    }
+
+    #[test]
+    fn resolves_token_files_against_the_forwarded_cwd() {
+        let cwd = std::env::temp_dir().join("launch");
+        let absolute_path = std::env::temp_dir().join("token.txt");
+
+        assert_eq!(
+            parse_command_line_actions(
+                &args(&[
+                    "--set-token-file",
+                    "tokens/token.txt",
+                    "--set-token-file",
+                    absolute_path.to_str().unwrap(),
+                ]),
+                cwd.to_str().unwrap(),
+            ),
+            vec![
+                CommandLineAction::SetTokenFile(cwd.join("tokens/token.txt")),
+                CommandLineAction::SetTokenFile(absolute_path),
+            ]
+        );
+    }
+}
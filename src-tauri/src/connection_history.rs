@@ -0,0 +1,424 @@
+use crate::secure_link_client::SecureLinkTraffic;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ConnectionOutcome {
+    Unauthorized,
+    NetworkError,
+    UserStop,
+    ServerClose,
+    IdleTimeout,
+}
+
+// One connection attempt or session. Timestamps are unix milliseconds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionRecord {
+    pub started_at: u64,
+    pub ended_at: u64,
+    pub duration_ms: u64,
+    pub server: String,
+    pub outcome: ConnectionOutcome,
+    pub bytes_sent: Option<u64>,
+    pub bytes_received: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionHistoryRange {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectionHistoryRetention {
+    pub max_records: usize,
+    pub max_age: Duration,
+}
+
+impl Default for ConnectionHistoryRetention {
+    fn default() -> Self {
+        Self {
+            max_records: 10_000,
+            max_age: Duration::from_secs(90 * 24 * 60 * 60),
+        }
+    }
+}
+
+// Append-only JSON lines file, one record per line. The file is only rewritten
+// when retention limits are enforced.
+pub struct ConnectionHistory {
+    file_path: PathBuf,
+    retention: ConnectionHistoryRetention,
+    records_count: Mutex<usize>,
+}
+
+impl ConnectionHistory {
+    pub fn open(
+        file_path: PathBuf,
+        retention: ConnectionHistoryRetention,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let history = Self {
+            file_path,
+            retention,
+            records_count: Mutex::new(0),
+        };
+
+        let records_count = history.prune()?;
+        *history.records_count.lock().unwrap() = records_count;
+
+        Ok(history)
+    }
+
+    pub fn append(&self, record: &ConnectionRecord) -> Result<(), Box<dyn std::error::Error>> {
+        let mut records_count = self.records_count.lock().unwrap();
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&self.file_path)?;
+
+        // A crash mid-write leaves the last line without its newline, the new
+        // record must not end up on the same line
+        if file.seek(SeekFrom::End(0))? > 0 {
+            let mut last_byte = [0u8];
+            file.seek(SeekFrom::End(-1))?;
+            file.read_exact(&mut last_byte)?;
+
+            if last_byte != *b"\n" {
+                writeln!(file)?;
+            }
+        }
+
+        writeln!(file, "{}", serde_json::to_string(record)?)?;
+
+        *records_count += 1;
+
+        // Prune with some slack so the file is not rewritten on every append
+        if *records_count > self.retention.max_records + self.retention.max_records / 10 {
+            *records_count = self.prune()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn query(
+        &self,
+        range: &ConnectionHistoryRange,
+    ) -> Result<Vec<ConnectionRecord>, Box<dyn std::error::Error>> {
+        let _records_count = self.records_count.lock().unwrap();
+
+        Ok(self
+            .read_records()?
+            .into_iter()
+            .filter(|record| range.from.is_none_or(|from| record.ended_at >= from))
+            .filter(|record| range.to.is_none_or(|to| record.started_at <= to))
+            .collect())
+    }
+
+    // Drops records past the retention limits and returns how many are left.
+    // Callers hold `records_count` locked, except while opening.
+    fn prune(&self) -> Result<usize, Box<dyn std::error::Error>> {
+        let records = self.read_records()?;
+
+        let min_ended_at = unix_time_millis(SystemTime::now())
+            .saturating_sub(self.retention.max_age.as_millis() as u64);
+
+        let mut kept: Vec<ConnectionRecord> = records
+            .iter()
+            .filter(|record| record.ended_at >= min_ended_at)
+            .cloned()
+            .collect();
+
+        if kept.len() > self.retention.max_records {
+            kept.drain(..kept.len() - self.retention.max_records);
+        }
+
+        if kept.len() == records.len() {
+            return Ok(kept.len());
+        }
+
+        // Write to a temporary file first so a crash never leaves a truncated history
+        let temp_file_path = self.file_path.with_extension("jsonl.tmp");
+
+        {
+            let mut temp_file = std::fs::File::create(&temp_file_path)?;
+
+            for record in &kept {
+                writeln!(temp_file, "{}", serde_json::to_string(record)?)?;
+            }
+
+            temp_file.sync_all()?;
+        }
+
+        std::fs::rename(&temp_file_path, &self.file_path)?;
+
+        Ok(kept.len())
+    }
+
+    fn read_records(&self) -> Result<Vec<ConnectionRecord>, Box<dyn std::error::Error>> {
+        let file = match std::fs::File::open(&self.file_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(Box::new(e)),
+        };
+
+        let mut records = Vec::new();
+
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            // A partially written last line must not make the whole history unreadable
+            match serde_json::from_str::<ConnectionRecord>(&line) {
+                Ok(record) => records.push(record),
+                Err(e) => warn!("skipping malformed connection history line: {e}"),
+            }
+        }
+
+        Ok(records)
+    }
+}
+
+struct OpenSession {
+    started_at: SystemTime,
+    server: String,
+    // Set once the client was seen pending or running, so the first poll
+    // after a start request does not mistake the initial `Stopped` for a drop
+    observed_active: bool,
+}
+
+// Turns start/stop requests and polled client states into history records
+#[derive(Default)]
+pub struct ConnectionSessionTracker {
+    open_session: Mutex<Option<OpenSession>>,
+}
+
+impl ConnectionSessionTracker {
+    pub fn attempt_started(&self, server: &str) {
+        let mut open_session = self.open_session.lock().unwrap();
+
+        if open_session.is_none() {
+            *open_session = Some(OpenSession {
+                started_at: SystemTime::now(),
+                server: server.to_string(),
+                observed_active: false,
+            });
+        }
+    }
+
+    pub fn observed_active(&self) {
+        if let Some(open_session) = &mut *self.open_session.lock().unwrap() {
+            open_session.observed_active = true;
+        }
+    }
+
+    pub fn was_observed_active(&self) -> bool {
+        self.open_session
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|open_session| open_session.observed_active)
+    }
+
+    // Closes the open session, if any, and returns the record to store
+    pub fn ended(
+        &self,
+        outcome: ConnectionOutcome,
+        traffic: Option<SecureLinkTraffic>,
+    ) -> Option<ConnectionRecord> {
+        let open_session = self.open_session.lock().unwrap().take()?;
+
+        let ended_at = SystemTime::now();
+        let duration = ended_at
+            .duration_since(open_session.started_at)
+            .unwrap_or_default();
+
+        Some(ConnectionRecord {
+            started_at: unix_time_millis(open_session.started_at),
+            ended_at: unix_time_millis(ended_at),
+            duration_ms: duration.as_millis() as u64,
+            server: open_session.server,
+            outcome,
+            bytes_sent: traffic.map(|traffic| traffic.bytes_sent),
+            bytes_received: traffic.map(|traffic| traffic.bytes_received),
+        })
+    }
+}
+
+pub fn unix_time_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_history_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "secure_link_connection_history_{}_{}.jsonl",
+            name,
+            std::process::id()
+        ))
+    }
+
+    fn record(ended_at: u64) -> ConnectionRecord {
+        ConnectionRecord {
+            started_at: ended_at - 1000,
+            ended_at,
+            duration_ms: 1000,
+            server: "link.example.com:443".to_string(),
+            outcome: ConnectionOutcome::UserStop,
+            bytes_sent: None,
+            bytes_received: None,
+        }
+    }
+
+    #[test]
+    fn session_records_carry_the_traffic_totals() {
+        let tracker = ConnectionSessionTracker::default();
+
+        assert!(tracker.ended(ConnectionOutcome::UserStop, None).is_none());
+
+        tracker.attempt_started("link.example.com:443");
+        tracker.observed_active();
+        assert!(tracker.was_observed_active());
+
+        let record = tracker
+            .ended(
+                ConnectionOutcome::UserStop,
+                Some(SecureLinkTraffic {
+                    bytes_sent: 1200,
+                    bytes_received: 34000,
+                }),
+            )
+            .unwrap();
+
+        assert_eq!(record.server, "link.example.com:443");
+        assert_eq!(record.outcome, ConnectionOutcome::UserStop);
+        assert_eq!(record.bytes_sent, Some(1200));
+        assert_eq!(record.bytes_received, Some(34000));
+        assert!(!tracker.was_observed_active());
+
+        // A failed attempt has no traffic
+        tracker.attempt_started("link.example.com:443");
+        let record = tracker
+            .ended(ConnectionOutcome::NetworkError, None)
+            .unwrap();
+        assert_eq!(record.bytes_sent, None);
+    }
+
+    #[test]
+    fn stores_queries_and_prunes_records() {
+        let file_path = temp_history_path("prune");
+        let _ = std::fs::remove_file(&file_path);
+
+        let history = ConnectionHistory::open(
+            file_path.clone(),
+            ConnectionHistoryRetention {
+                max_records: 2,
+                max_age: Duration::from_secs(24 * 60 * 60),
+            },
+        )
+        .unwrap();
+
+        let now = unix_time_millis(SystemTime::now());
+        let mut with_traffic = record(now - 3000);
+        with_traffic.bytes_sent = Some(10);
+        with_traffic.bytes_received = Some(20);
+
+        history.append(&record(now - 5000)).unwrap();
+        history.append(&with_traffic).unwrap();
+
+        let records = history
+            .query(&ConnectionHistoryRange {
+                from: Some(now - 4000),
+                to: None,
+            })
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].bytes_received, Some(20));
+
+        // Past the limit the oldest records are dropped
+        history.append(&record(now)).unwrap();
+
+        let records = history.query(&Default::default()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].bytes_received, Some(20));
+        assert_eq!(records[1].ended_at, now);
+
+        // And so are the ones past the age limit
+        let history = ConnectionHistory::open(
+            file_path.clone(),
+            ConnectionHistoryRetention {
+                max_records: 2,
+                max_age: Duration::from_secs(2),
+            },
+        )
+        .unwrap();
+        let records = history.query(&Default::default()).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].ended_at, now);
+
+        let _ = std::fs::remove_file(&file_path);
+    }
+
+    #[test]
+    fn skips_malformed_lines() {
+        let file_path = temp_history_path("malformed");
+        let now = unix_time_millis(SystemTime::now());
+
+        std::fs::write(
+            &file_path,
+            format!(
+                "{}\n{{\"startedAt\": 1\n",
+                serde_json::to_string(&record(now)).unwrap()
+            ),
+        )
+        .unwrap();
+
+        let history =
+            ConnectionHistory::open(file_path.clone(), ConnectionHistoryRetention::default())
+                .unwrap();
+        assert_eq!(history.query(&Default::default()).unwrap().len(), 1);
+
+        let _ = std::fs::remove_file(&file_path);
+    }
+    #[test]
+    fn appends_after_a_truncated_record() {
+        let file_path = temp_history_path("truncated");
+        let now = unix_time_millis(SystemTime::now());
+
+        let complete = serde_json::to_string(&record(now - 2000)).unwrap();
+        let truncated = serde_json::to_string(&record(now - 1000)).unwrap();
+        std::fs::write(
+            &file_path,
+            format!("{}\n{}", complete, &truncated[..truncated.len() / 2]),
+        )
+        .unwrap();
+
+        let history =
+            ConnectionHistory::open(file_path.clone(), ConnectionHistoryRetention::default())
+                .unwrap();
+        history.append(&record(now)).unwrap();
+
+        let records = history.query(&Default::default()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].ended_at, now);
+
+        let _ = std::fs::remove_file(&file_path);
+    }
+}
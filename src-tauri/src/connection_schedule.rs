@@ -0,0 +1,459 @@
+use chrono::{DateTime, Datelike, Days, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+// Days looked at around "now" when searching for window edges
+const SCHEDULE_LOOKAROUND_DAYS: u64 = 8;
+
+// Connection windows, e.g. "weekdays 08:00-19:00 Europe/Moscow". Without allow
+// rules every time is allowed, block rules always win over allow rules.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduleConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // IANA name, e.g. `Europe/Moscow`
+    #[serde(default = "default_time_zone")]
+    pub time_zone: String,
+    #[serde(default)]
+    pub rules: Vec<ScheduleRule>,
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            time_zone: default_time_zone(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+fn default_time_zone() -> String {
+    "UTC".to_string()
+}
+
+// `start` and `end` are "HH:MM". Both missing means the whole day, an `end`
+// before `start` runs over midnight into the next day.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduleRule {
+    pub action: ScheduleRuleAction,
+    pub days: Vec<Weekday>,
+    pub start: Option<String>,
+    pub end: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ScheduleRuleAction {
+    Allow,
+    Block,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ScheduleError {
+    #[error("Unknown time zone {0}")]
+    UnknownTimeZone(String),
+
+    #[error("Time {0} is not HH:MM")]
+    InvalidTime(String),
+
+    #[error("Schedule rule has no days")]
+    RuleWithoutDays,
+
+    #[error("Schedule rule has only one of start and end")]
+    HalfOpenRule,
+
+    #[error("Schedule rule starts and ends at the same time")]
+    EmptyRule,
+}
+
+// Times are unix milliseconds, like in the connection history. A missing edge
+// lies further than the schedule looks ahead or back.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduleWindow {
+    pub start: Option<u64>,
+    pub end: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScheduleStatus {
+    pub enabled: bool,
+    pub allowed: bool,
+    pub current_window: Option<ScheduleWindow>,
+    pub next_window: Option<ScheduleWindow>,
+}
+
+pub struct ConnectionSchedule {
+    enabled: bool,
+    time_zone: Tz,
+    rules: Vec<ParsedScheduleRule>,
+}
+
+struct ParsedScheduleRule {
+    action: ScheduleRuleAction,
+    days: Vec<Weekday>,
+    start: NaiveTime,
+    // `None` is the end of the day
+    end: Option<NaiveTime>,
+}
+
+impl ConnectionSchedule {
+    pub fn from_config(config: &ScheduleConfig) -> Result<Self, ScheduleError> {
+        let time_zone = config
+            .time_zone
+            .parse::<Tz>()
+            .map_err(|_| ScheduleError::UnknownTimeZone(config.time_zone.clone()))?;
+
+        let rules = config
+            .rules
+            .iter()
+            .map(ParsedScheduleRule::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            enabled: config.enabled,
+            time_zone,
+            rules,
+        })
+    }
+
+    pub fn is_allowed_at(&self, time: DateTime<Utc>) -> bool {
+        if !self.enabled {
+            return true;
+        }
+
+        let local_time = time.with_timezone(&self.time_zone).naive_local();
+
+        let mut allow_rules = self
+            .rules
+            .iter()
+            .filter(|rule| rule.action == ScheduleRuleAction::Allow)
+            .peekable();
+
+        let is_allowed =
+            allow_rules.peek().is_none() || allow_rules.any(|rule| rule.matches(&local_time));
+
+        is_allowed
+            && !self
+                .rules
+                .iter()
+                .filter(|rule| rule.action == ScheduleRuleAction::Block)
+                .any(|rule| rule.matches(&local_time))
+    }
+
+    pub fn status_at(&self, now: DateTime<Utc>) -> ScheduleStatus {
+        let allowed = self.is_allowed_at(now);
+
+        if !self.enabled {
+            return ScheduleStatus {
+                enabled: false,
+                allowed,
+                current_window: Some(ScheduleWindow {
+                    start: None,
+                    end: None,
+                }),
+                next_window: None,
+            };
+        }
+
+        let transitions = self.transitions_around(now);
+
+        let previous_transition = transitions.iter().rev().find(|&&time| time <= now);
+        let mut next_transitions = transitions.iter().filter(|&&time| time > now);
+
+        let (current_window, next_window_start) = if allowed {
+            let current_window = ScheduleWindow {
+                start: previous_transition.map(|&time| unix_time_millis(time)),
+                end: next_transitions.next().map(|&time| unix_time_millis(time)),
+            };
+
+            (Some(current_window), next_transitions.next())
+        } else {
+            (None, next_transitions.next())
+        };
+
+        let next_window = next_window_start.map(|&start| ScheduleWindow {
+            start: Some(unix_time_millis(start)),
+            end: next_transitions.next().map(|&time| unix_time_millis(time)),
+        });
+
+        ScheduleStatus {
+            enabled: true,
+            allowed,
+            current_window,
+            next_window,
+        }
+    }
+
+    // Wall clock time in the schedule time zone, for the tray
+    pub fn format_local_time(&self, unix_time_millis: u64) -> String {
+        match DateTime::<Utc>::from_timestamp_millis(unix_time_millis as i64) {
+            Some(time) => time
+                .with_timezone(&self.time_zone)
+                .format("%a %H:%M")
+                .to_string(),
+            None => String::new(),
+        }
+    }
+
+    // Points in time around `now` where the schedule flips between allowed and
+    // blocked, in order
+    fn transitions_around(&self, now: DateTime<Utc>) -> Vec<DateTime<Utc>> {
+        let today = now.with_timezone(&self.time_zone).date_naive();
+
+        let (Some(first_day), Some(last_day)) = (
+            today.checked_sub_days(Days::new(SCHEDULE_LOOKAROUND_DAYS)),
+            today.checked_add_days(Days::new(SCHEDULE_LOOKAROUND_DAYS)),
+        ) else {
+            return Vec::new();
+        };
+
+        let mut edges = Vec::new();
+
+        for day in first_day.iter_days().take_while(|day| *day <= last_day) {
+            edges.push(day.and_time(NaiveTime::MIN));
+
+            for rule in &self.rules {
+                edges.push(day.and_time(rule.start));
+                edges.extend(rule.end.map(|end| day.and_time(end)));
+            }
+        }
+
+        let mut edges: Vec<DateTime<Utc>> = edges
+            .into_iter()
+            // Local times skipped by a DST change have no edge
+            .filter_map(|edge| self.time_zone.from_local_datetime(&edge).earliest())
+            .map(|edge| edge.with_timezone(&Utc))
+            .collect();
+
+        edges.sort();
+        edges.dedup();
+
+        edges
+            .into_iter()
+            .filter(|&edge| {
+                self.is_allowed_at(edge) != self.is_allowed_at(edge - chrono::Duration::seconds(1))
+            })
+            .collect()
+    }
+}
+
+impl ParsedScheduleRule {
+    fn parse(rule: &ScheduleRule) -> Result<Self, ScheduleError> {
+        if rule.days.is_empty() {
+            return Err(ScheduleError::RuleWithoutDays);
+        }
+
+        let (start, end) = match (&rule.start, &rule.end) {
+            (None, None) => (NaiveTime::MIN, None),
+            (Some(start), Some(end)) => (parse_time(start)?, Some(parse_time(end)?)),
+            _ => return Err(ScheduleError::HalfOpenRule),
+        };
+
+        if Some(start) == end {
+            return Err(ScheduleError::EmptyRule);
+        }
+
+        Ok(Self {
+            action: rule.action,
+            days: rule.days.clone(),
+            start,
+            end,
+        })
+    }
+
+    fn matches(&self, local_time: &NaiveDateTime) -> bool {
+        let time = local_time.time();
+        let day = local_time.weekday();
+
+        match self.end {
+            None => self.days.contains(&day) && time >= self.start,
+            Some(end) if end > self.start => {
+                self.days.contains(&day) && time >= self.start && time < end
+            }
+            // Runs over midnight, the tail belongs to the previous day
+            Some(end) => {
+                (self.days.contains(&day) && time >= self.start)
+                    || (self.days.contains(&day.pred()) && time < end)
+            }
+        }
+    }
+}
+
+fn parse_time(time: &str) -> Result<NaiveTime, ScheduleError> {
+    NaiveTime::parse_from_str(time.trim(), "%H:%M")
+        .map_err(|_| ScheduleError::InvalidTime(time.to_string()))
+}
+
+fn unix_time_millis(time: DateTime<Utc>) -> u64 {
+    time.timestamp_millis().max(0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn business_hours() -> ScheduleConfig {
+        ScheduleConfig {
+            enabled: true,
+            time_zone: "Europe/Moscow".to_string(),
+            rules: vec![ScheduleRule {
+                action: ScheduleRuleAction::Allow,
+                days: vec![
+                    Weekday::Mon,
+                    Weekday::Tue,
+                    Weekday::Wed,
+                    Weekday::Thu,
+                    Weekday::Fri,
+                ],
+                start: Some("08:00".to_string()),
+                end: Some("19:00".to_string()),
+            }],
+        }
+    }
+
+    // Moscow is UTC+3 all year
+    fn moscow(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 6, day, hour, minute, 0).unwrap() - chrono::Duration::hours(3)
+    }
+
+    #[test]
+    fn disabled_schedule_allows_everything() {
+        let mut config = business_hours();
+        config.enabled = false;
+
+        let schedule = ConnectionSchedule::from_config(&config).unwrap();
+
+        // Saturday night
+        assert!(schedule.is_allowed_at(moscow(7, 23, 0)));
+    }
+
+    #[test]
+    fn allow_rule_limits_to_its_window() {
+        let schedule = ConnectionSchedule::from_config(&business_hours()).unwrap();
+
+        // 2025-06-02 is a Monday
+        assert!(!schedule.is_allowed_at(moscow(2, 7, 59)));
+        assert!(schedule.is_allowed_at(moscow(2, 8, 0)));
+        assert!(schedule.is_allowed_at(moscow(2, 18, 59)));
+        assert!(!schedule.is_allowed_at(moscow(2, 19, 0)));
+        assert!(!schedule.is_allowed_at(moscow(7, 12, 0)));
+    }
+
+    #[test]
+    fn block_rule_wins_over_allow_rules() {
+        let config = ScheduleConfig {
+            enabled: true,
+            time_zone: "UTC".to_string(),
+            rules: vec![ScheduleRule {
+                action: ScheduleRuleAction::Block,
+                days: vec![Weekday::Sat, Weekday::Sun],
+                start: None,
+                end: None,
+            }],
+        };
+
+        let schedule = ConnectionSchedule::from_config(&config).unwrap();
+
+        assert!(schedule.is_allowed_at(Utc.with_ymd_and_hms(2025, 6, 6, 23, 59, 0).unwrap()));
+        assert!(!schedule.is_allowed_at(Utc.with_ymd_and_hms(2025, 6, 7, 0, 0, 0).unwrap()));
+        assert!(!schedule.is_allowed_at(Utc.with_ymd_and_hms(2025, 6, 8, 23, 59, 0).unwrap()));
+        assert!(schedule.is_allowed_at(Utc.with_ymd_and_hms(2025, 6, 9, 0, 0, 0).unwrap()));
+    }
+
+    #[test]
+    fn overnight_rule_continues_into_next_day() {
+        let config = ScheduleConfig {
+            enabled: true,
+            time_zone: "UTC".to_string(),
+            rules: vec![ScheduleRule {
+                action: ScheduleRuleAction::Allow,
+                days: vec![Weekday::Fri],
+                start: Some("22:00".to_string()),
+                end: Some("02:00".to_string()),
+            }],
+        };
+
+        let schedule = ConnectionSchedule::from_config(&config).unwrap();
+
+        assert!(schedule.is_allowed_at(Utc.with_ymd_and_hms(2025, 6, 6, 23, 0, 0).unwrap()));
+        assert!(schedule.is_allowed_at(Utc.with_ymd_and_hms(2025, 6, 7, 1, 0, 0).unwrap()));
+        assert!(!schedule.is_allowed_at(Utc.with_ymd_and_hms(2025, 6, 7, 2, 0, 0).unwrap()));
+    }
+
+    #[test]
+    fn status_inside_window_reports_its_edges_and_the_next_window() {
+        let schedule = ConnectionSchedule::from_config(&business_hours()).unwrap();
+
+        let status = schedule.status_at(moscow(6, 12, 0));
+
+        assert!(status.allowed);
+        assert_eq!(
+            status.current_window,
+            Some(ScheduleWindow {
+                start: Some(unix_time_millis(moscow(6, 8, 0))),
+                end: Some(unix_time_millis(moscow(6, 19, 0))),
+            })
+        );
+        // Friday is followed by the weekend
+        assert_eq!(
+            status.next_window,
+            Some(ScheduleWindow {
+                start: Some(unix_time_millis(moscow(9, 8, 0))),
+                end: Some(unix_time_millis(moscow(9, 19, 0))),
+            })
+        );
+    }
+
+    #[test]
+    fn status_outside_window_reports_only_the_next_window() {
+        let schedule = ConnectionSchedule::from_config(&business_hours()).unwrap();
+
+        let status = schedule.status_at(moscow(2, 20, 0));
+
+        assert!(!status.allowed);
+        assert_eq!(status.current_window, None);
+        assert_eq!(
+            status.next_window,
+            Some(ScheduleWindow {
+                start: Some(unix_time_millis(moscow(3, 8, 0))),
+                end: Some(unix_time_millis(moscow(3, 19, 0))),
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        let mut config = business_hours();
+        config.time_zone = "Mars/Olympus_Mons".to_string();
+        assert!(matches!(
+            ConnectionSchedule::from_config(&config),
+            Err(ScheduleError::UnknownTimeZone(_))
+        ));
+
+        let mut config = business_hours();
+        config.rules[0].end = Some("25:00".to_string());
+        assert!(matches!(
+            ConnectionSchedule::from_config(&config),
+            Err(ScheduleError::InvalidTime(_))
+        ));
+
+        let mut config = business_hours();
+        config.rules[0].end = None;
+        assert!(matches!(
+            ConnectionSchedule::from_config(&config),
+            Err(ScheduleError::HalfOpenRule)
+        ));
+
+        let mut config = business_hours();
+        config.rules[0].days.clear();
+        assert!(matches!(
+            ConnectionSchedule::from_config(&config),
+            Err(ScheduleError::RuleWithoutDays)
+        ));
+    }
+}
@@ -0,0 +1,758 @@
+// Staged diagnostics of the connection to the server, run from the UI when
+// the link does not come up. The stages follow the way the embedded client
+// connects, so the first failed one points at the layer that broke. Stages
+// that depend on a failed one are skipped.
+
+use crate::auth_token::AuthToken;
+use crate::proxy_route::{self, ProxyRoute};
+use crate::proxy_tunnel;
+use crate::server_config::{ProxyConfig, ServerConfig};
+use crate::server_trust;
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{CertificateError, ClientConfig, RootCertStore};
+use secure_link_client::{SecureLink, SecureLinkError};
+use serde::Serialize;
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+pub const STAGE_TIMEOUT: Duration = Duration::from_secs(10);
+// Certificates and tokens start failing validation beyond this
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+const MAX_RESPONSE_HEAD_LEN: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CheckStage {
+    Dns,
+    TcpConnect,
+    TlsHandshake,
+    ClockSkew,
+    TokenAuth,
+    ProxyDetection,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StageStatus {
+    Passed,
+    Failed,
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StageResult {
+    pub stage: CheckStage,
+    pub status: StageStatus,
+    pub detail: String,
+    // What the user can do about a failure
+    pub advice: Option<String>,
+    pub duration_ms: u64,
+}
+
+impl StageResult {
+    fn passed(stage: CheckStage, started: Instant, detail: String) -> Self {
+        Self {
+            stage,
+            status: StageStatus::Passed,
+            detail,
+            advice: None,
+            duration_ms: started.elapsed().as_millis() as u64,
+        }
+    }
+
+    fn failed(stage: CheckStage, started: Instant, detail: String, advice: String) -> Self {
+        Self {
+            stage,
+            status: StageStatus::Failed,
+            detail,
+            advice: Some(advice),
+            duration_ms: started.elapsed().as_millis() as u64,
+        }
+    }
+
+    fn skipped(stage: CheckStage, detail: &str) -> Self {
+        Self {
+            stage,
+            status: StageStatus::Skipped,
+            detail: detail.to_string(),
+            advice: None,
+            duration_ms: 0,
+        }
+    }
+}
+
+pub struct ConnectivityCheckTarget<'a> {
+    pub host: &'a str,
+    pub port: u16,
+    pub server_config: &'a ServerConfig,
+    pub auth_token: Option<&'a AuthToken>,
+    pub stage_timeout: Duration,
+}
+
+// Runs all stages in order and calls `on_stage` as soon as one has finished
+pub async fn run_connectivity_check(
+    target: &ConnectivityCheckTarget<'_>,
+    mut on_stage: impl FnMut(&StageResult),
+) -> Vec<StageResult> {
+    let proxy_route = proxy_route::resolve_proxy_route(&target.server_config.proxy, target.host);
+    let mut results = Vec::new();
+
+    let mut finish = |result: StageResult| {
+        on_stage(&result);
+        let status = result.status;
+        results.push(result);
+        status
+    };
+
+    let dns_status = finish(check_dns(target, &proxy_route).await);
+
+    let (tcp_result, tcp_stream) = if dns_status == StageStatus::Failed {
+        (
+            StageResult::skipped(CheckStage::TcpConnect, "Адрес сервера не найден"),
+            None,
+        )
+    } else {
+        check_tcp_connect(target, &proxy_route).await
+    };
+    let tcp_status = finish(tcp_result);
+
+    // The error is not `Send`, and the config is kept across the stages
+    let tls_config = server_tls_config(target.server_config).map_err(|e| e.to_string());
+
+    let (tls_result, tls_stream) = match (tcp_stream, &tls_config) {
+        (None, _) => (
+            StageResult::skipped(CheckStage::TlsHandshake, "Нет TCP-подключения"),
+            None,
+        ),
+        (Some(tcp_stream), Ok(tls_config)) => {
+            check_tls_handshake(target, tcp_stream, tls_config).await
+        }
+        (Some(_), Err(e)) => (
+            StageResult::failed(
+                CheckStage::TlsHandshake,
+                Instant::now(),
+                format!("Ошибка настроек доверия: {}", e),
+                "Проверьте путь к файлу CA и закрепленные ключи в настройках сервера".to_string(),
+            ),
+            None,
+        ),
+    };
+    let tls_status = finish(tls_result);
+
+    finish(match tls_stream {
+        Some(tls_stream) => check_clock_skew(target, tls_stream).await,
+        None => StageResult::skipped(CheckStage::ClockSkew, "Нет TLS-соединения"),
+    });
+
+    finish(match (tls_status, &tls_config) {
+        (StageStatus::Passed, Ok(tls_config)) => {
+            check_token_auth(target, &proxy_route, tls_config).await
+        }
+        _ => StageResult::skipped(CheckStage::TokenAuth, "Нет TLS-соединения"),
+    });
+
+    finish(check_proxy_detection(target, &proxy_route, tcp_status));
+
+    results
+}
+
+// The trust config of the server, or plain web PKI like the secure link
+// client uses without one
+fn server_tls_config(
+    server_config: &ServerConfig,
+) -> Result<ServerTlsConfig, Box<dyn std::error::Error>> {
+    let Some(server_tls) = server_trust::build_server_tls(&server_config.trust)? else {
+        let root_cert_store =
+            RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+        let tls_config = ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::aws_lc_rs::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(root_cert_store)
+        .with_no_client_auth();
+
+        return Ok(ServerTlsConfig {
+            tls_config: Arc::new(tls_config),
+            server_tls: None,
+        });
+    };
+
+    Ok(ServerTlsConfig {
+        tls_config: server_tls.tls_config.clone(),
+        server_tls: Some(server_tls),
+    })
+}
+
+struct ServerTlsConfig {
+    tls_config: Arc<ClientConfig>,
+    // `None` for the default trust config
+    server_tls: Option<server_trust::ServerTls>,
+}
+
+impl ServerTlsConfig {
+    fn is_pin_mismatch(&self) -> bool {
+        self.server_tls
+            .as_ref()
+            .is_some_and(|server_tls| server_tls.is_pin_mismatch())
+    }
+}
+
+async fn with_timeout<T>(
+    timeout: Duration,
+    future: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    tokio::time::timeout(timeout, future)
+        .await
+        .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+}
+
+// Behind a proxy the server name is resolved by the proxy, so the proxy
+// host is what has to resolve locally
+async fn check_dns(target: &ConnectivityCheckTarget<'_>, proxy_route: &ProxyRoute) -> StageResult {
+    let started = Instant::now();
+
+    let (host, port) = match proxy_route {
+        ProxyRoute::Direct => (target.host, target.port),
+        ProxyRoute::HttpConnect(proxy_server) | ProxyRoute::Socks5(proxy_server) => {
+            (proxy_server.host.as_str(), proxy_server.port)
+        }
+    };
+
+    match with_timeout(target.stage_timeout, tokio::net::lookup_host((host, port))).await {
+        Ok(addresses) => {
+            let addresses: Vec<String> =
+                addresses.map(|address| address.ip().to_string()).collect();
+
+            StageResult::passed(
+                CheckStage::Dns,
+                started,
+                format!("{}: {}", host, addresses.join(", ")),
+            )
+        }
+        Err(e) => StageResult::failed(
+            CheckStage::Dns,
+            started,
+            format!("Не удалось найти адрес {}: {}", host, e),
+            format!(
+                "Проверьте, что имя {} указано верно и DNS-сервер доступен. \
+                 В корпоративной сети может понадобиться прокси.",
+                host
+            ),
+        ),
+    }
+}
+
+async fn check_tcp_connect(
+    target: &ConnectivityCheckTarget<'_>,
+    proxy_route: &ProxyRoute,
+) -> (StageResult, Option<TcpStream>) {
+    let started = Instant::now();
+
+    match with_timeout(
+        target.stage_timeout,
+        proxy_tunnel::open_tunnel(proxy_route, target.host, target.port),
+    )
+    .await
+    {
+        Ok(stream) => {
+            let via = match proxy_route {
+                ProxyRoute::Direct => stream
+                    .peer_addr()
+                    .map(|address| address.to_string())
+                    .unwrap_or_else(|_| format!("{}:{}", target.host, target.port)),
+                ProxyRoute::HttpConnect(proxy_server) | ProxyRoute::Socks5(proxy_server) => {
+                    format!(
+                        "{}:{} через прокси {}:{}",
+                        target.host, target.port, proxy_server.host, proxy_server.port
+                    )
+                }
+            };
+
+            let result = StageResult::passed(
+                CheckStage::TcpConnect,
+                started,
+                format!(
+                    "Подключение к {} за {} мс",
+                    via,
+                    started.elapsed().as_millis()
+                ),
+            );
+
+            (result, Some(stream))
+        }
+        Err(e) => {
+            let advice = match e.kind() {
+                io::ErrorKind::ConnectionRefused => format!(
+                    "Сервер не принимает подключения на порту {}. Проверьте адрес сервера.",
+                    target.port
+                ),
+                io::ErrorKind::TimedOut => format!(
+                    "Подключение не устанавливается, похоже, порт {} закрыт брандмауэром. \
+                     Разрешите исходящие подключения к {}:{}.",
+                    target.port, target.host, target.port
+                ),
+                _ => "Проверьте подключение к сети и настройки брандмауэра.".to_string(),
+            };
+
+            let result = StageResult::failed(
+                CheckStage::TcpConnect,
+                started,
+                format!(
+                    "Не удалось подключиться к {}:{}: {}",
+                    target.host, target.port, e
+                ),
+                advice,
+            );
+
+            (result, None)
+        }
+    }
+}
+
+async fn check_tls_handshake(
+    target: &ConnectivityCheckTarget<'_>,
+    tcp_stream: TcpStream,
+    tls_config: &ServerTlsConfig,
+) -> (StageResult, Option<TlsStream<TcpStream>>) {
+    let started = Instant::now();
+
+    let server_name = match ServerName::try_from(target.host.to_string()) {
+        Ok(server_name) => server_name,
+        Err(e) => {
+            let result = StageResult::failed(
+                CheckStage::TlsHandshake,
+                started,
+                format!("Недопустимое имя сервера {}: {}", target.host, e),
+                "Проверьте адрес сервера в настройках.".to_string(),
+            );
+
+            return (result, None);
+        }
+    };
+
+    let connector = TlsConnector::from(tls_config.tls_config.clone());
+
+    match with_timeout(
+        target.stage_timeout,
+        connector.connect(server_name, tcp_stream),
+    )
+    .await
+    {
+        Ok(tls_stream) => {
+            let (_, connection) = tls_stream.get_ref();
+
+            let protocol = connection
+                .protocol_version()
+                .map(|version| format!("{:?}", version))
+                .unwrap_or_default();
+
+            let chain = connection
+                .peer_certificates()
+                .unwrap_or_default()
+                .iter()
+                .map(describe_certificate)
+                .collect::<Vec<_>>()
+                .join(" ← ");
+
+            let result = StageResult::passed(
+                CheckStage::TlsHandshake,
+                started,
+                format!("{}, цепочка: {}", protocol, chain),
+            );
+
+            (result, Some(tls_stream))
+        }
+        Err(e) => {
+            let advice = if tls_config.is_pin_mismatch() {
+                "Сертификат сервера не совпадает с закрепленным. Соединение может \
+                 перехватывать прокси или антивирус."
+            } else {
+                match tls_error(&e) {
+                    Some(rustls::Error::InvalidCertificate(
+                        CertificateError::Expired | CertificateError::NotValidYet,
+                    )) => "Сертификат сервера не действителен на текущую дату. Проверьте дату и время на компьютере.",
+                    Some(rustls::Error::InvalidCertificate(CertificateError::UnknownIssuer)) => {
+                        "Сертификат выдан неизвестным центром. Соединение может перехватывать \
+                         прокси или антивирус, для своего сервера укажите файл CA в настройках."
+                    }
+                    _ if e.kind() == io::ErrorKind::TimedOut => {
+                        "Сервер не отвечает на TLS. Проверьте, что порт принадлежит серверу \
+                         Secure Link, а не другому приложению."
+                    }
+                    _ => "Проверьте адрес сервера и настройки доверия.",
+                }
+            };
+
+            let result = StageResult::failed(
+                CheckStage::TlsHandshake,
+                started,
+                format!("Ошибка TLS: {}", e),
+                advice.to_string(),
+            );
+
+            (result, None)
+        }
+    }
+}
+
+fn tls_error(error: &io::Error) -> Option<&rustls::Error> {
+    error.get_ref()?.downcast_ref::<rustls::Error>()
+}
+
+fn describe_certificate(certificate: &CertificateDer<'_>) -> String {
+    match X509Certificate::from_der(certificate.as_ref()) {
+        Ok((_, certificate)) => format!(
+            "{} (до {})",
+            certificate.subject(),
+            certificate.validity().not_after
+        ),
+        Err(_) => "нечитаемый сертификат".to_string(),
+    }
+}
+
+// Compares the clock with the `Date` of the server's answer to a request
+// without credentials. Servers that do not send one are checked against the
+// validity of their certificate, which only catches large skews.
+async fn check_clock_skew(
+    target: &ConnectivityCheckTarget<'_>,
+    mut tls_stream: TlsStream<TcpStream>,
+) -> StageResult {
+    let started = Instant::now();
+
+    let server_date = with_timeout(target.stage_timeout, async {
+        let request = format!(
+            "HEAD / HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            target.host
+        );
+        tls_stream.write_all(request.as_bytes()).await?;
+
+        let mut response = Vec::new();
+        let mut buffer = [0u8; 1024];
+
+        while !response.windows(4).any(|window| window == b"\r\n\r\n")
+            && response.len() < MAX_RESPONSE_HEAD_LEN
+        {
+            let read = tls_stream.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            response.extend_from_slice(&buffer[..read]);
+        }
+
+        Ok(response_date(&String::from_utf8_lossy(&response)))
+    })
+    .await
+    .ok()
+    .flatten();
+
+    let now = SystemTime::now();
+
+    if let Some(server_date) = server_date {
+        let skew = now
+            .duration_since(server_date)
+            .or_else(|_| server_date.duration_since(now))
+            .unwrap_or_default();
+
+        let detail = format!("Расхождение с сервером {} с", skew.as_secs());
+
+        return if skew <= MAX_CLOCK_SKEW {
+            StageResult::passed(CheckStage::ClockSkew, started, detail)
+        } else {
+            StageResult::failed(
+                CheckStage::ClockSkew,
+                started,
+                detail,
+                format!(
+                    "Часы компьютера расходятся с сервером на {} мин. Включите синхронизацию времени.",
+                    skew.as_secs() / 60
+                ),
+            )
+        };
+    }
+
+    let (_, connection) = tls_stream.get_ref();
+
+    let leaf_validity = connection
+        .peer_certificates()
+        .and_then(|certificates| certificates.first())
+        .and_then(|certificate| {
+            let (_, certificate) = X509Certificate::from_der(certificate.as_ref()).ok()?;
+            Some(certificate.validity().is_valid())
+        });
+
+    match leaf_validity {
+        Some(false) => StageResult::failed(
+            CheckStage::ClockSkew,
+            started,
+            "Текущая дата вне срока действия сертификата сервера".to_string(),
+            "Проверьте дату и время на компьютере.".to_string(),
+        ),
+        _ => StageResult::passed(
+            CheckStage::ClockSkew,
+            started,
+            "Сервер не сообщает время, дата проверена по сроку действия сертификата".to_string(),
+        ),
+    }
+}
+
+fn response_date(response_head: &str) -> Option<SystemTime> {
+    let date = response_head.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("date")
+            .then(|| value.trim())
+    })?;
+
+    let date = chrono::DateTime::parse_from_rfc2822(date).ok()?;
+
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(date.timestamp()).ok()?))
+}
+
+// The global channel handshake of the secure link client on a connection of
+// its own, closed right after
+async fn check_token_auth(
+    target: &ConnectivityCheckTarget<'_>,
+    proxy_route: &ProxyRoute,
+    tls_config: &ServerTlsConfig,
+) -> StageResult {
+    let started = Instant::now();
+
+    let Some(auth_token) = target.auth_token else {
+        return StageResult::failed(
+            CheckStage::TokenAuth,
+            started,
+            "Токен не задан".to_string(),
+            "Укажите токен подключения.".to_string(),
+        );
+    };
+
+    let result = tokio::time::timeout(target.stage_timeout, async {
+        let stream = proxy_tunnel::open_tunnel(proxy_route, target.host, target.port).await?;
+
+        SecureLink::connect_to_global_channel_over_stream(
+            stream,
+            target.host,
+            auth_token.expose(),
+            tls_config
+                .server_tls
+                .as_ref()
+                .map(|server_tls| server_tls.tls_config.clone()),
+        )
+        .await
+    })
+    .await;
+
+    match result {
+        Ok(Ok(_secure_link)) => StageResult::passed(
+            CheckStage::TokenAuth,
+            started,
+            format!("Токен {} принят", auth_token.fingerprint()),
+        ),
+        Ok(Err(SecureLinkError::UnauthorizedError)) => StageResult::failed(
+            CheckStage::TokenAuth,
+            started,
+            format!("Сервер отклонил токен {}", auth_token.fingerprint()),
+            "Токен отозван или истек. Получите новый токен у администратора.".to_string(),
+        ),
+        Ok(Err(e)) => StageResult::failed(
+            CheckStage::TokenAuth,
+            started,
+            format!("Ошибка подключения: {}", e),
+            "Сервер доступен, но подключение не установилось. Передайте результаты проверки в поддержку."
+                .to_string(),
+        ),
+        Err(_) => StageResult::failed(
+            CheckStage::TokenAuth,
+            started,
+            "Сервер не ответил на подключение".to_string(),
+            "Проверьте, что порт принадлежит серверу Secure Link.".to_string(),
+        ),
+    }
+}
+
+// Explains the proxy route that was used, and points at a system proxy the
+// settings ignore when the direct connection failed
+fn check_proxy_detection(
+    target: &ConnectivityCheckTarget<'_>,
+    proxy_route: &ProxyRoute,
+    tcp_status: StageStatus,
+) -> StageResult {
+    let started = Instant::now();
+
+    let system_route = proxy_route::resolve_proxy_route(&ProxyConfig::System, target.host);
+
+    match (proxy_route, &system_route) {
+        (ProxyRoute::Direct, ProxyRoute::Direct) => StageResult::passed(
+            CheckStage::ProxyDetection,
+            started,
+            "Прокси не используется и не настроен в системе".to_string(),
+        ),
+        (
+            ProxyRoute::Direct,
+            ProxyRoute::HttpConnect(proxy_server) | ProxyRoute::Socks5(proxy_server),
+        ) => {
+            let detail = format!(
+                "Прямое подключение, системный прокси {}:{} не используется",
+                proxy_server.host, proxy_server.port
+            );
+
+            if tcp_status == StageStatus::Failed {
+                StageResult::failed(
+                    CheckStage::ProxyDetection,
+                    started,
+                    detail,
+                    "Сеть может требовать прокси. Включите системный прокси в настройках сервера."
+                        .to_string(),
+                )
+            } else {
+                StageResult::passed(CheckStage::ProxyDetection, started, detail)
+            }
+        }
+        (ProxyRoute::HttpConnect(proxy_server) | ProxyRoute::Socks5(proxy_server), _) => {
+            let kind = match proxy_route {
+                ProxyRoute::Socks5(_) => "SOCKS5",
+                _ => "HTTP CONNECT",
+            };
+            let detail = format!(
+                "Подключение через {} прокси {}:{}",
+                kind, proxy_server.host, proxy_server.port
+            );
+
+            if tcp_status == StageStatus::Failed {
+                StageResult::failed(
+                    CheckStage::ProxyDetection,
+                    started,
+                    detail,
+                    "Проверьте адрес прокси, учетные данные и что прокси разрешает подключения к серверу."
+                        .to_string(),
+                )
+            } else {
+                StageResult::passed(CheckStage::ProxyDetection, started, detail)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server_config::TrustConfig;
+    use crate::stand_in_secure_link_server::{StandInBehavior, StandInSecureLinkServer};
+
+    const VALID_AUTH_TOKEN: &str = "stand-in-valid-token";
+
+    fn stand_in_server_config(server: &StandInSecureLinkServer) -> ServerConfig {
+        ServerConfig {
+            trust: TrustConfig {
+                ca_bundle_path: Some(server.ca_bundle_path().to_path_buf()),
+                spki_sha256_pins: vec![],
+            },
+            ..Default::default()
+        }
+    }
+
+    async fn run(
+        host: &str,
+        port: u16,
+        server_config: &ServerConfig,
+    ) -> Vec<(CheckStage, StageStatus)> {
+        let auth_token = AuthToken::new(VALID_AUTH_TOKEN.to_string());
+        let mut progress = Vec::new();
+
+        let results = run_connectivity_check(
+            &ConnectivityCheckTarget {
+                host,
+                port,
+                server_config,
+                auth_token: Some(&auth_token),
+                stage_timeout: Duration::from_secs(1),
+            },
+            |result| progress.push(result.stage),
+        )
+        .await;
+
+        assert_eq!(
+            progress,
+            results
+                .iter()
+                .map(|result| result.stage)
+                .collect::<Vec<_>>()
+        );
+
+        results
+            .iter()
+            .map(|result| (result.stage, result.status))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn passes_against_a_reachable_server() {
+        let dir =
+            std::env::temp_dir().join(format!("connectivity-check-ok-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let server =
+            StandInSecureLinkServer::start(StandInBehavior::Accept, VALID_AUTH_TOKEN, &dir).await;
+
+        let stages = run("localhost", server.port(), &stand_in_server_config(&server)).await;
+
+        assert_eq!(
+            stages,
+            vec![
+                (CheckStage::Dns, StageStatus::Passed),
+                (CheckStage::TcpConnect, StageStatus::Passed),
+                (CheckStage::TlsHandshake, StageStatus::Passed),
+                (CheckStage::ClockSkew, StageStatus::Passed),
+                (CheckStage::TokenAuth, StageStatus::Passed),
+                (CheckStage::ProxyDetection, StageStatus::Passed),
+            ]
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn skips_the_stages_after_a_stalled_tls_handshake() {
+        let dir =
+            std::env::temp_dir().join(format!("connectivity-check-stall-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let server =
+            StandInSecureLinkServer::start(StandInBehavior::Stall, VALID_AUTH_TOKEN, &dir).await;
+
+        let stages = run("localhost", server.port(), &stand_in_server_config(&server)).await;
+
+        assert_eq!(stages[1], (CheckStage::TcpConnect, StageStatus::Passed));
+        assert_eq!(stages[2], (CheckStage::TlsHandshake, StageStatus::Failed));
+        assert_eq!(stages[3], (CheckStage::ClockSkew, StageStatus::Skipped));
+        assert_eq!(stages[4], (CheckStage::TokenAuth, StageStatus::Skipped));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn reports_unresolvable_hosts() {
+        let stages = run("secure-link.invalid", 443, &ServerConfig::default()).await;
+
+        assert_eq!(stages[0], (CheckStage::Dns, StageStatus::Failed));
+        assert_eq!(stages[1], (CheckStage::TcpConnect, StageStatus::Skipped));
+    }
+
+    #[test]
+    fn reads_the_date_header() {
+        assert_eq!(
+            response_date(
+                "HTTP/1.1 401 Unauthorized\r\ndate: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\n"
+            ),
+            Some(UNIX_EPOCH + Duration::from_secs(784111777))
+        );
+        assert_eq!(response_date("HTTP/1.1 401 Unauthorized\r\n\r\n"), None);
+    }
+}
@@ -0,0 +1,246 @@
+// End to end tests of the embedded client against the local stand-in server,
+// through the public `SecureLinkClient` API only
+#![cfg(feature = "secure-link-embedded-client")]
+
+use crate::auth_token::AuthToken;
+use crate::client_errors::{ClientErrorCategory, ClientErrorLog};
+use crate::secure_link_client::{
+    SecureLinkClient, SecureLinkClientError, SecureLinkClientState, SecureLinkClientStopOutcome,
+    SecureLinkClientStopReason,
+};
+use crate::secure_link_embedded_client::SecureLinkEmbeddedClient;
+use crate::server_config::{ProxyConfig, ServerConfig, TrustConfig};
+use crate::stand_in_secure_link_server::{StandInBehavior, StandInSecureLinkServer};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+const VALID_AUTH_TOKEN: &str = "stand-in-valid-token";
+
+struct TestServer {
+    server: StandInSecureLinkServer,
+    dir: PathBuf,
+    // Shared by the clients of the test
+    error_log: Arc<ClientErrorLog>,
+}
+
+impl TestServer {
+    async fn start(behavior: StandInBehavior) -> Self {
+        static NEXT_ID: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+        let dir = std::env::temp_dir().join(format!(
+            "secure-link-e2e-{}-{}",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let server = StandInSecureLinkServer::start(behavior, VALID_AUTH_TOKEN, &dir).await;
+
+        Self {
+            server,
+            dir,
+            error_log: Arc::new(ClientErrorLog::new(|_| {})),
+        }
+    }
+
+    fn client(&self, auth_token: &str) -> SecureLinkEmbeddedClient {
+        self.client_with_pins(auth_token, vec![])
+    }
+
+    fn client_with_pins(
+        &self,
+        auth_token: &str,
+        spki_sha256_pins: Vec<String>,
+    ) -> SecureLinkEmbeddedClient {
+        SecureLinkEmbeddedClient::new(
+            &AuthToken::new(auth_token.to_string()),
+            "localhost",
+            self.server.port(),
+            &ServerConfig {
+                proxy: ProxyConfig::None,
+                trust: TrustConfig {
+                    ca_bundle_path: Some(self.server.ca_bundle_path().to_path_buf()),
+                    spki_sha256_pins,
+                },
+                ..Default::default()
+            },
+            Default::default(),
+            self.error_log.clone(),
+        )
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+async fn wait_for_state(
+    client: &SecureLinkEmbeddedClient,
+    expected_state: SecureLinkClientState,
+) -> bool {
+    for _ in 0..100 {
+        if client.status().await.unwrap() == expected_state {
+            return true;
+        }
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    false
+}
+
+#[tokio::test]
+async fn connects_and_stops() {
+    let test_server = TestServer::start(StandInBehavior::Accept).await;
+    let client = test_server.client(VALID_AUTH_TOKEN);
+
+    client.start().await.unwrap();
+
+    assert_eq!(
+        client.status().await.unwrap(),
+        SecureLinkClientState::Running
+    );
+    assert_eq!(test_server.server.handshakes(), 1);
+
+    assert_eq!(
+        client.stop().await.unwrap(),
+        SecureLinkClientStopOutcome::Clean
+    );
+
+    // The message loop has exited by the time stop returns
+    assert_eq!(
+        client.status().await.unwrap(),
+        SecureLinkClientState::Stopped
+    );
+    assert_eq!(
+        client.last_stop_reason().await,
+        Some(SecureLinkClientStopReason::UserStop)
+    );
+}
+
+#[tokio::test]
+async fn wrong_token_is_unauthorized() {
+    let test_server = TestServer::start(StandInBehavior::Accept).await;
+    let client = test_server.client("stand-in-wrong-token");
+
+    let result = client.start().await;
+
+    assert!(matches!(
+        result,
+        Err(SecureLinkClientError::UnauthorizedError)
+    ));
+    assert_eq!(
+        client.status().await.unwrap(),
+        SecureLinkClientState::Stopped
+    );
+}
+
+#[tokio::test]
+async fn rejecting_server_is_unauthorized() {
+    let test_server = TestServer::start(StandInBehavior::RejectUnauthorized).await;
+    let client = test_server.client(VALID_AUTH_TOKEN);
+
+    let result = client.start().await;
+
+    assert!(matches!(
+        result,
+        Err(SecureLinkClientError::UnauthorizedError)
+    ));
+    assert_eq!(test_server.server.handshakes(), 1);
+}
+
+#[tokio::test]
+async fn server_drop_stops_the_client() {
+    let test_server =
+        TestServer::start(StandInBehavior::CloseAfter(Duration::from_millis(300))).await;
+    let client = test_server.client(VALID_AUTH_TOKEN);
+
+    client.start().await.unwrap();
+
+    assert_eq!(
+        client.status().await.unwrap(),
+        SecureLinkClientState::Running
+    );
+    assert!(wait_for_state(&client, SecureLinkClientState::Stopped).await);
+    assert!(matches!(
+        client.last_stop_reason().await,
+        Some(SecureLinkClientStopReason::ServerClose | SecureLinkClientStopReason::NetworkError)
+    ));
+    assert!(matches!(
+        test_server.error_log.last().map(|error| error.category),
+        Some(ClientErrorCategory::ServerClosed | ClientErrorCategory::Network)
+    ));
+}
+
+#[tokio::test]
+async fn stop_during_connect_cancels_the_attempt() {
+    let test_server = TestServer::start(StandInBehavior::Stall).await;
+    let client = test_server.client(VALID_AUTH_TOKEN);
+
+    let (start_result, stop_result) = tokio::join!(client.start(), async {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        assert_eq!(
+            client.status().await.unwrap(),
+            SecureLinkClientState::Pending
+        );
+
+        client.stop().await
+    });
+
+    start_result.unwrap();
+    stop_result.unwrap();
+
+    assert_eq!(
+        client.status().await.unwrap(),
+        SecureLinkClientState::Stopped
+    );
+    assert_eq!(
+        client.last_stop_reason().await,
+        Some(SecureLinkClientStopReason::UserStop)
+    );
+    assert_eq!(test_server.server.handshakes(), 0);
+}
+
+#[tokio::test]
+async fn slow_handshake_stays_pending_until_accepted() {
+    let test_server =
+        TestServer::start(StandInBehavior::SlowHandshake(Duration::from_millis(500))).await;
+    let client = test_server.client(VALID_AUTH_TOKEN);
+
+    let (start_result, pending_state) = tokio::join!(client.start(), async {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        client.status().await.unwrap()
+    });
+
+    start_result.unwrap();
+
+    assert_eq!(pending_state, SecureLinkClientState::Pending);
+    assert_eq!(
+        client.status().await.unwrap(),
+        SecureLinkClientState::Running
+    );
+
+    client.stop().await.unwrap();
+}
+
+#[tokio::test]
+async fn unpinned_server_key_is_a_pin_mismatch() {
+    let test_server = TestServer::start(StandInBehavior::Accept).await;
+    let client = test_server.client_with_pins(
+        VALID_AUTH_TOKEN,
+        vec!["sha256/AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=".to_string()],
+    );
+
+    let result = client.start().await;
+
+    assert!(matches!(
+        result,
+        Err(SecureLinkClientError::CertificatePinMismatch)
+    ));
+    assert_eq!(test_server.server.handshakes(), 0);
+}
@@ -0,0 +1,288 @@
+// Picks the server endpoint of a link out of the endpoints in the settings.
+// The lowest priority number wins, endpoints of the same priority share the
+// clients by weight. An endpoint that failed is left alone for a cool-down,
+// so the next connect goes to another one.
+
+use crate::settings::EndpointSettings;
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const ENDPOINT_COOLDOWN: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EndpointStatus {
+    pub host: String,
+    pub port: u16,
+    pub priority: u32,
+    pub weight: u32,
+    pub active: bool,
+    // Millis left of the cool-down after a failure, `None` when healthy
+    pub cooldown_remaining_ms: Option<u64>,
+}
+
+struct EndpointHealth {
+    endpoint: EndpointSettings,
+    unhealthy_until: Option<Instant>,
+}
+
+impl EndpointHealth {
+    fn is_healthy(&self, now: Instant) -> bool {
+        self.unhealthy_until
+            .is_none_or(|unhealthy_until| unhealthy_until <= now)
+    }
+}
+
+#[derive(Default)]
+struct FailoverState {
+    endpoints: Vec<EndpointHealth>,
+    // Index into `endpoints`
+    active: Option<usize>,
+}
+
+#[derive(Default)]
+pub struct EndpointFailover {
+    state: Mutex<FailoverState>,
+}
+
+impl EndpointFailover {
+    // Returns the endpoint to connect to. The active one is kept while it is
+    // healthy, so a new client for a new token stays on the same server.
+    pub fn select(&self, endpoints: &[EndpointSettings]) -> Option<EndpointSettings> {
+        // Without randomness the first endpoint of the best priority gets them all
+        self.select_with_roll(endpoints, getrandom::u64().unwrap_or_default())
+    }
+
+    fn select_with_roll(
+        &self,
+        endpoints: &[EndpointSettings],
+        roll: u64,
+    ) -> Option<EndpointSettings> {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        state.set_endpoints(endpoints);
+
+        if let Some(active) = state.active {
+            if state.endpoints[active].is_healthy(now) {
+                return Some(state.endpoints[active].endpoint.clone());
+            }
+        }
+
+        let healthy: Vec<usize> = (0..state.endpoints.len())
+            .filter(|&index| state.endpoints[index].is_healthy(now))
+            .collect();
+
+        let selected = match healthy
+            .iter()
+            .map(|&index| state.endpoints[index].endpoint.priority)
+            .min()
+        {
+            Some(best_priority) => {
+                let candidates: Vec<usize> = healthy
+                    .into_iter()
+                    .filter(|&index| state.endpoints[index].endpoint.priority == best_priority)
+                    .collect();
+
+                pick_by_weight(&state.endpoints, &candidates, roll)
+            }
+            // All are cooling down, the one that is done first gets a try
+            None => (0..state.endpoints.len())
+                .min_by_key(|&index| state.endpoints[index].unhealthy_until),
+        };
+
+        state.active = selected;
+
+        selected.map(|index| state.endpoints[index].endpoint.clone())
+    }
+
+    pub fn active(&self) -> Option<EndpointSettings> {
+        let state = self.state.lock().unwrap();
+
+        state
+            .active
+            .map(|active| state.endpoints[active].endpoint.clone())
+    }
+
+    // Puts the active endpoint on cool-down. Returns whether another endpoint
+    // is healthy, i.e. whether failing over makes sense. The only endpoint is
+    // never put on cool-down, there is nothing to fail over to.
+    pub fn failed(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        if state.endpoints.len() < 2 {
+            return false;
+        }
+
+        let Some(active) = state.active.take() else {
+            return false;
+        };
+
+        state.endpoints[active].unhealthy_until = Some(now + ENDPOINT_COOLDOWN);
+
+        state
+            .endpoints
+            .iter()
+            .any(|endpoint| endpoint.is_healthy(now))
+    }
+
+    pub fn succeeded(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(active) = state.active {
+            state.endpoints[active].unhealthy_until = None;
+        }
+    }
+
+    pub fn status(&self) -> Vec<EndpointStatus> {
+        let state = self.state.lock().unwrap();
+        let now = Instant::now();
+
+        state
+            .endpoints
+            .iter()
+            .enumerate()
+            .map(|(index, health)| EndpointStatus {
+                host: health.endpoint.host.clone(),
+                port: health.endpoint.port,
+                priority: health.endpoint.priority,
+                weight: health.endpoint.weight,
+                active: state.active == Some(index),
+                cooldown_remaining_ms: health
+                    .unhealthy_until
+                    .filter(|unhealthy_until| *unhealthy_until > now)
+                    .map(|unhealthy_until| (unhealthy_until - now).as_millis() as u64),
+            })
+            .collect()
+    }
+}
+
+impl FailoverState {
+    // Keeps the health and the active endpoint across settings changes, as
+    // long as host and port stay the same
+    fn set_endpoints(&mut self, endpoints: &[EndpointSettings]) {
+        let active = self
+            .active
+            .map(|active| self.endpoints[active].endpoint.clone());
+
+        let previous = std::mem::take(&mut self.endpoints);
+
+        self.endpoints = endpoints
+            .iter()
+            .map(|endpoint| EndpointHealth {
+                endpoint: endpoint.clone(),
+                unhealthy_until: previous
+                    .iter()
+                    .find(|health| is_same_server(&health.endpoint, endpoint))
+                    .and_then(|health| health.unhealthy_until),
+            })
+            .collect();
+
+        self.active = active.and_then(|active| {
+            self.endpoints
+                .iter()
+                .position(|health| is_same_server(&health.endpoint, &active))
+        });
+    }
+}
+
+fn is_same_server(a: &EndpointSettings, b: &EndpointSettings) -> bool {
+    a.host == b.host && a.port == b.port
+}
+
+// `roll` is spread over the total weight, so every candidate is picked with
+// the share of its weight
+fn pick_by_weight(endpoints: &[EndpointHealth], candidates: &[usize], roll: u64) -> Option<usize> {
+    let total_weight: u64 = candidates
+        .iter()
+        .map(|&index| u64::from(endpoints[index].endpoint.weight))
+        .sum();
+
+    if total_weight == 0 {
+        return candidates.first().copied();
+    }
+
+    let mut remaining = roll % total_weight;
+
+    for &index in candidates {
+        let weight = u64::from(endpoints[index].endpoint.weight);
+
+        if remaining < weight {
+            return Some(index);
+        }
+
+        remaining -= weight;
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint(host: &str, priority: u32, weight: u32) -> EndpointSettings {
+        EndpointSettings {
+            host: host.to_string(),
+            port: 443,
+            priority,
+            weight,
+        }
+    }
+
+    #[test]
+    fn fails_over_by_priority_and_keeps_the_active_endpoint() {
+        let failover = EndpointFailover::default();
+        let endpoints = [endpoint("backup", 1, 1), endpoint("primary", 0, 1)];
+
+        assert_eq!(failover.select(&endpoints).unwrap().host, "primary");
+        failover.succeeded();
+        assert_eq!(failover.select(&endpoints).unwrap().host, "primary");
+
+        assert!(failover.failed());
+        assert_eq!(failover.select(&endpoints).unwrap().host, "backup");
+        assert_eq!(failover.active().unwrap().host, "backup");
+
+        let status = failover.status();
+        assert!(status[0].active);
+        assert!(status[1].cooldown_remaining_ms.is_some());
+
+        // Both cooling down, the one that failed first is tried again
+        assert!(!failover.failed());
+        assert_eq!(failover.select(&endpoints).unwrap().host, "primary");
+    }
+
+    #[test]
+    fn never_cools_down_the_only_endpoint() {
+        let failover = EndpointFailover::default();
+        let endpoints = [endpoint("primary", 0, 1)];
+
+        assert_eq!(failover.select(&endpoints).unwrap().host, "primary");
+        assert!(!failover.failed());
+
+        assert_eq!(failover.active().unwrap().host, "primary");
+        assert_eq!(failover.status()[0].cooldown_remaining_ms, None);
+    }
+
+    #[test]
+    fn shares_endpoints_of_one_priority_by_weight() {
+        let endpoints = [
+            endpoint("a", 0, 1),
+            endpoint("b", 0, 3),
+            endpoint("c", 1, 100),
+        ];
+
+        let picks: Vec<String> = (0..4)
+            .map(|roll| {
+                EndpointFailover::default()
+                    .select_with_roll(&endpoints, roll)
+                    .unwrap()
+                    .host
+            })
+            .collect();
+
+        assert_eq!(picks, vec!["a", "b", "b", "b"]);
+    }
+}
@@ -0,0 +1,433 @@
+// Exchanges a short one-time enrollment code for a long-lived auth token, so
+// admins never have to hand out the token itself. The app POSTs
+// `{"code": "...", "deviceId": "...", "platform": "..."}` to the enrollment
+// URL and gets back the token bound to that device, the server endpoint and
+// optionally the profile it is for, the same fields as an enrollment file.
+// A code that is not known answers 404, one that was used 409 and one that
+// expired 410.
+
+use crate::auth_token::AuthToken;
+use crate::auth_token_import::{validate_auth_token, ImportedAuthToken};
+use crate::proxy_route::{self, ProxyRoute};
+use crate::server_config::{ProxyServerConfig, ServerConfig};
+use crate::server_trust::{self, ServerTls};
+use crate::settings::EndpointSettings;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::Duration;
+use zeroize::Zeroize;
+
+const MAX_RESPONSE_LEN: usize = 64 * 1024;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+const DEVICE_ID_FILE_NAME: &str = "device_id.txt";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EnrollmentSettings {
+    // Overrides the enrollment URL the app was built with
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+impl EnrollmentSettings {
+    // The token comes back in the response, so plain HTTP is only good for a
+    // server on the same machine
+    pub fn is_valid(&self) -> bool {
+        match &self.url {
+            Some(url) => reqwest::Url::parse(url).is_ok_and(|url| match url.scheme() {
+                "https" => true,
+                "http" => matches!(url.host_str(), Some("127.0.0.1" | "localhost" | "[::1]")),
+                _ => false,
+            }),
+            None => true,
+        }
+    }
+}
+
+// Serialized for the UI as `{"kind": "expiredCode"}` or, for the ones with a
+// detail, `{"kind": "network", "message": "..."}`
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", content = "message", rename_all = "camelCase")]
+pub enum EnrollmentError {
+    #[error("No enrollment URL is configured")]
+    NotConfigured,
+
+    #[error("Enrollment code is malformed")]
+    MalformedCode,
+
+    #[error("Enrollment code is not known")]
+    UnknownCode,
+
+    #[error("Enrollment code has expired")]
+    ExpiredCode,
+
+    #[error("Enrollment code was already used")]
+    CodeAlreadyUsed,
+
+    #[error("Enrollment server can not be reached: {0}")]
+    Network(String),
+
+    // The server presented none of the pinned keys of the trust config
+    #[error("Enrollment server certificate does not match the pinned keys")]
+    CertificatePinMismatch,
+
+    #[error("Enrollment server answered with status {0}")]
+    UnexpectedStatus(u16),
+
+    #[error("Enrollment response is malformed: {0}")]
+    MalformedResponse(String),
+
+    // Storing the token or applying the endpoint failed
+    #[error("Enrollment can not be applied: {0}")]
+    Apply(String),
+
+    // The managed policy forbids editing the auth token
+    #[error("PolicyViolation: Enrollment is forbidden by the managed policy")]
+    PolicyViolation,
+}
+
+impl From<reqwest::Error> for EnrollmentError {
+    fn from(error: reqwest::Error) -> Self {
+        EnrollmentError::Network(error.to_string())
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EnrollmentRequest<'a> {
+    code: &'a str,
+    device_id: &'a str,
+    platform: &'a str,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EnrollmentResponse {
+    token: AuthToken,
+    #[serde(default)]
+    endpoint: Option<EndpointSettings>,
+    #[serde(default)]
+    profile_name: Option<String>,
+}
+
+// Codes are read off a sheet or a message, so case, spaces and dashes do not
+// matter: `abcd-1234` is `ABCD1234`
+pub fn normalize_enrollment_code(code: &str) -> Result<String, EnrollmentError> {
+    let code: String = code
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    if !(4..=64).contains(&code.len()) || !code.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(EnrollmentError::MalformedCode);
+    }
+
+    Ok(code)
+}
+
+// Random id of this installation the token gets bound to, created on first use
+pub fn load_or_create_device_id(app_data_dir: &Path) -> std::io::Result<String> {
+    let device_id_path = app_data_dir.join(DEVICE_ID_FILE_NAME);
+
+    match std::fs::read_to_string(&device_id_path) {
+        Ok(device_id) if !device_id.trim().is_empty() => return Ok(device_id.trim().to_string()),
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    // 128 bits from the OS random source
+    let mut device_id_bytes = [0u8; 16];
+    getrandom::fill(&mut device_id_bytes)?;
+
+    let device_id: String = device_id_bytes
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    std::fs::write(&device_id_path, &device_id)?;
+
+    Ok(device_id)
+}
+
+// The enrollment server is reached like the secure link server, through the
+// proxy of the server config and trusting its CA bundle and pins
+fn build_http_client(
+    enrollment_url: &str,
+    server_config: &ServerConfig,
+    server_tls: Option<&ServerTls>,
+) -> Result<reqwest::Client, EnrollmentError> {
+    let enrollment_url =
+        reqwest::Url::parse(enrollment_url).map_err(|e| EnrollmentError::Network(e.to_string()))?;
+
+    let mut http_client = reqwest::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .read_timeout(READ_TIMEOUT);
+
+    let proxy_route = proxy_route::resolve_proxy_route(
+        &server_config.proxy,
+        enrollment_url.host_str().unwrap_or_default(),
+    );
+
+    http_client = match proxy_route {
+        ProxyRoute::Direct => http_client.no_proxy(),
+        ProxyRoute::HttpConnect(proxy_server) => {
+            http_client.proxy(enrollment_proxy("http", &proxy_server)?)
+        }
+        ProxyRoute::Socks5(proxy_server) => {
+            http_client.proxy(enrollment_proxy("socks5h", &proxy_server)?)
+        }
+    };
+
+    if let Some(server_tls) = server_tls {
+        http_client = http_client.tls_backend_preconfigured((*server_tls.tls_config).clone());
+    }
+
+    Ok(http_client.build()?)
+}
+
+fn enrollment_proxy(
+    scheme: &str,
+    proxy_server: &ProxyServerConfig,
+) -> Result<reqwest::Proxy, EnrollmentError> {
+    let host = match proxy_server.host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(_)) => format!("[{}]", proxy_server.host),
+        _ => proxy_server.host.clone(),
+    };
+
+    let proxy = reqwest::Proxy::all(format!("{scheme}://{host}:{}", proxy_server.port))?;
+
+    Ok(match &proxy_server.username {
+        Some(username) => proxy.basic_auth(
+            username,
+            proxy_server.password.as_deref().unwrap_or_default(),
+        ),
+        None => proxy,
+    })
+}
+
+// `code` as normalized by `normalize_enrollment_code`
+pub async fn exchange_enrollment_code(
+    enrollment_url: &str,
+    server_config: &ServerConfig,
+    code: &str,
+    device_id: &str,
+) -> Result<ImportedAuthToken, EnrollmentError> {
+    let server_tls = server_trust::build_server_tls(&server_config.trust)
+        .map_err(|e| EnrollmentError::Network(e.to_string()))?;
+    let http_client = build_http_client(enrollment_url, server_config, server_tls.as_ref())?;
+
+    let request = EnrollmentRequest {
+        code,
+        device_id,
+        platform: &crate::updater::current_platform(),
+    };
+
+    let mut response = http_client
+        .post(enrollment_url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(serde_json::to_vec(&request).expect("the request serializes"))
+        .send()
+        .await
+        .map_err(|e| {
+            if server_tls.as_ref().is_some_and(ServerTls::is_pin_mismatch) {
+                EnrollmentError::CertificatePinMismatch
+            } else {
+                e.into()
+            }
+        })?;
+
+    match response.status().as_u16() {
+        200..=299 => {}
+        404 => return Err(EnrollmentError::UnknownCode),
+        409 => return Err(EnrollmentError::CodeAlreadyUsed),
+        410 => return Err(EnrollmentError::ExpiredCode),
+        status => return Err(EnrollmentError::UnexpectedStatus(status)),
+    }
+
+    let mut body = Vec::new();
+
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > MAX_RESPONSE_LEN {
+            return Err(EnrollmentError::MalformedResponse(
+                "response is too large".to_string(),
+            ));
+        }
+
+        body.extend_from_slice(&chunk);
+    }
+
+    let enrollment: Result<EnrollmentResponse, _> = serde_json::from_slice(&body);
+    body.zeroize();
+
+    let enrollment = enrollment.map_err(|e| EnrollmentError::MalformedResponse(e.to_string()))?;
+
+    validate_auth_token(&enrollment.token)
+        .map_err(|e| EnrollmentError::MalformedResponse(e.to_string()))?;
+
+    Ok(ImportedAuthToken {
+        auth_token: enrollment.token,
+        endpoint: enrollment.endpoint,
+        profile_name: enrollment
+            .profile_name
+            .filter(|profile_name| !profile_name.trim().is_empty()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server_config::ProxyConfig;
+    use crate::stand_in_enrollment_server::{StandInEnrollmentServer, StandInEnrollmentState};
+
+    fn enrollment() -> serde_json::Value {
+        serde_json::json!({
+            "token": "device-token-1234",
+            "endpoint": {"host": "link.example.com", "port": 443},
+        })
+    }
+
+    #[tokio::test]
+    async fn exchanges_a_code_once() {
+        let server = StandInEnrollmentServer::start().await;
+        server.add_code("ABCD1234", StandInEnrollmentState::Valid(enrollment()));
+
+        let enrolled = exchange_enrollment_code(
+            &server.url(),
+            &ServerConfig::default(),
+            "ABCD1234",
+            "device-1",
+        )
+        .await
+        .unwrap();
+        assert_eq!(enrolled.auth_token.expose(), "device-token-1234");
+        assert_eq!(enrolled.endpoint.unwrap().host, "link.example.com");
+        assert_eq!(server.device_ids(), vec!["device-1"]);
+
+        assert_eq!(
+            exchange_enrollment_code(
+                &server.url(),
+                &ServerConfig::default(),
+                "ABCD1234",
+                "device-1"
+            )
+            .await
+            .unwrap_err(),
+            EnrollmentError::CodeAlreadyUsed
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_expired_unknown_and_unreachable() {
+        let server = StandInEnrollmentServer::start().await;
+        server.add_code("EXPIRED1", StandInEnrollmentState::Expired);
+
+        assert_eq!(
+            exchange_enrollment_code(
+                &server.url(),
+                &ServerConfig::default(),
+                "EXPIRED1",
+                "device-1"
+            )
+            .await
+            .unwrap_err(),
+            EnrollmentError::ExpiredCode
+        );
+        assert_eq!(
+            exchange_enrollment_code(
+                &server.url(),
+                &ServerConfig::default(),
+                "UNKNOWN1",
+                "device-1"
+            )
+            .await
+            .unwrap_err(),
+            EnrollmentError::UnknownCode
+        );
+
+        // Nothing listens on a port that was just released
+        let closed_port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        assert!(matches!(
+            exchange_enrollment_code(
+                &format!("http://127.0.0.1:{}/enroll", closed_port),
+                &ServerConfig::default(),
+                "ABCD1234",
+                "device-1"
+            )
+            .await,
+            Err(EnrollmentError::Network(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn goes_through_the_proxy_of_the_server_config() {
+        let server = StandInEnrollmentServer::start().await;
+        server.add_code("ABCD1234", StandInEnrollmentState::Valid(enrollment()));
+
+        let server_config = ServerConfig {
+            proxy: ProxyConfig::HttpConnect(ProxyServerConfig {
+                host: "127.0.0.1".to_string(),
+                port: server.port(),
+                username: None,
+                password: None,
+            }),
+            ..ServerConfig::default()
+        };
+
+        // `.invalid` never resolves, only the proxy can reach it
+        let enrolled = exchange_enrollment_code(
+            "http://enroll.invalid/enroll",
+            &server_config,
+            "ABCD1234",
+            "device-1",
+        )
+        .await
+        .unwrap();
+        assert_eq!(enrolled.auth_token.expose(), "device-token-1234");
+    }
+
+    #[test]
+    fn creates_a_random_device_id_once() {
+        let app_data_dir =
+            std::env::temp_dir().join(format!("secure-link-device-id-{}", std::process::id()));
+        std::fs::create_dir_all(&app_data_dir).unwrap();
+        let _ = std::fs::remove_file(app_data_dir.join(DEVICE_ID_FILE_NAME));
+
+        let device_id = load_or_create_device_id(&app_data_dir).unwrap();
+        assert_eq!(device_id.len(), 32);
+        assert!(device_id.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(load_or_create_device_id(&app_data_dir).unwrap(), device_id);
+
+        std::fs::remove_dir_all(&app_data_dir).unwrap();
+    }
+
+    #[test]
+    fn normalizes_codes_and_urls() {
+        assert_eq!(
+            normalize_enrollment_code(" abcd-1234 ").unwrap(),
+            "ABCD1234"
+        );
+        assert_eq!(
+            normalize_enrollment_code("ab"),
+            Err(EnrollmentError::MalformedCode)
+        );
+        assert_eq!(
+            normalize_enrollment_code("abcd_1234"),
+            Err(EnrollmentError::MalformedCode)
+        );
+
+        let settings = |url: &str| EnrollmentSettings {
+            url: Some(url.to_string()),
+        };
+        assert!(settings("https://enroll.example.com/v1/enroll").is_valid());
+        assert!(settings("http://127.0.0.1:8080/enroll").is_valid());
+        assert!(!settings("http://enroll.example.com/enroll").is_valid());
+    }
+}
@@ -0,0 +1,159 @@
+// System-wide keyboard shortcuts, configured in the settings as accelerator
+// strings like `CmdOrCtrl+Shift+L`. A shortcut that can not be parsed, is set
+// for both actions or is taken by another application is reported in the
+// `ShortcutStatus` instead of being dropped silently.
+
+use serde::{Deserialize, Serialize};
+use tauri_plugin_global_shortcut::Shortcut;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShortcutSettings {
+    // Connects the default link when stopped and disconnects it otherwise
+    #[serde(default)]
+    pub toggle_link: Option<String>,
+    #[serde(default)]
+    pub show_window: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShortcutAction {
+    ToggleLink,
+    ShowWindow,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum ShortcutRegistration {
+    Unset,
+    Registered,
+    Invalid { error: String },
+    // Set for the other action too, or taken by another application
+    Conflict { error: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShortcutStatus {
+    pub toggle_link: ShortcutRegistration,
+    pub show_window: ShortcutRegistration,
+}
+
+impl Default for ShortcutStatus {
+    fn default() -> Self {
+        Self {
+            toggle_link: ShortcutRegistration::Unset,
+            show_window: ShortcutRegistration::Unset,
+        }
+    }
+}
+
+impl ShortcutStatus {
+    pub fn registration_mut(&mut self, action: ShortcutAction) -> &mut ShortcutRegistration {
+        match action {
+            ShortcutAction::ToggleLink => &mut self.toggle_link,
+            ShortcutAction::ShowWindow => &mut self.show_window,
+        }
+    }
+
+    // The first problem, as returned from a settings update
+    pub fn error(&self) -> Option<String> {
+        [&self.toggle_link, &self.show_window]
+            .into_iter()
+            .find_map(|registration| match registration {
+                ShortcutRegistration::Invalid { error } => {
+                    Some(format!("InvalidShortcut: {}", error))
+                }
+                ShortcutRegistration::Conflict { error } => {
+                    Some(format!("ShortcutConflict: {}", error))
+                }
+                ShortcutRegistration::Unset | ShortcutRegistration::Registered => None,
+            })
+    }
+}
+
+// Returns the shortcuts to register, and a status where those are marked as
+// registered. The caller marks the ones the system refuses as conflicts.
+pub fn parse_shortcut_settings(
+    settings: &ShortcutSettings,
+) -> (Vec<(ShortcutAction, Shortcut)>, ShortcutStatus) {
+    let mut shortcuts: Vec<(ShortcutAction, Shortcut)> = Vec::new();
+    let mut status = ShortcutStatus::default();
+
+    for (action, accelerator) in [
+        (ShortcutAction::ToggleLink, &settings.toggle_link),
+        (ShortcutAction::ShowWindow, &settings.show_window),
+    ] {
+        let Some(accelerator) = accelerator else {
+            continue;
+        };
+
+        let registration = match accelerator.parse::<Shortcut>() {
+            Err(e) => ShortcutRegistration::Invalid {
+                error: e.to_string(),
+            },
+            // Different spellings of one key combination are the same shortcut
+            Ok(shortcut) if shortcuts.iter().any(|(_, other)| *other == shortcut) => {
+                ShortcutRegistration::Conflict {
+                    error: format!("{} is set for another action", accelerator),
+                }
+            }
+            Ok(shortcut) => {
+                shortcuts.push((action, shortcut));
+                ShortcutRegistration::Registered
+            }
+        };
+
+        *status.registration_mut(action) = registration;
+    }
+
+    (shortcuts, status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_configured_shortcuts() {
+        let (shortcuts, status) = parse_shortcut_settings(&ShortcutSettings {
+            toggle_link: Some("CmdOrCtrl+Shift+L".to_string()),
+            show_window: None,
+        });
+
+        assert_eq!(shortcuts.len(), 1);
+        assert_eq!(shortcuts[0].0, ShortcutAction::ToggleLink);
+        assert_eq!(status.toggle_link, ShortcutRegistration::Registered);
+        assert_eq!(status.show_window, ShortcutRegistration::Unset);
+        assert_eq!(status.error(), None);
+    }
+
+    #[test]
+    fn reports_invalid_and_conflicting_shortcuts() {
+        let (shortcuts, status) = parse_shortcut_settings(&ShortcutSettings {
+            toggle_link: Some("Ctrl+Shift+Nope".to_string()),
+            show_window: Some("Alt+KeyS".to_string()),
+        });
+
+        assert_eq!(shortcuts.len(), 1);
+        assert!(matches!(
+            status.toggle_link,
+            ShortcutRegistration::Invalid { .. }
+        ));
+        assert_eq!(status.show_window, ShortcutRegistration::Registered);
+        assert!(status.error().unwrap().starts_with("InvalidShortcut: "));
+
+        let (shortcuts, status) = parse_shortcut_settings(&ShortcutSettings {
+            toggle_link: Some("Shift+Control+KeyL".to_string()),
+            show_window: Some("ctrl+shift+l".to_string()),
+        });
+
+        assert_eq!(shortcuts.len(), 1);
+        assert_eq!(status.toggle_link, ShortcutRegistration::Registered);
+        assert!(matches!(
+            status.show_window,
+            ShortcutRegistration::Conflict { .. }
+        ));
+        assert!(status.error().unwrap().starts_with("ShortcutConflict: "));
+    }
+}
@@ -0,0 +1,220 @@
+use crate::secure_link_client::SecureLinkTraffic;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+// Heartbeats and other background chatter stay below this many bytes per
+// activity window, so they do not keep an unused link alive
+const ACTIVITY_THRESHOLD_BYTES: u64 = 4 * 1024;
+const ACTIVITY_WINDOW: Duration = Duration::from_secs(60);
+
+// How long before the disconnect the user is warned, at most half the timeout
+const IDLE_WARNING_LEAD: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum IdleCheck {
+    Active,
+    #[serde(rename_all = "camelCase")]
+    Warning {
+        disconnect_in_ms: u64,
+    },
+    TimedOut,
+}
+
+struct IdleSession {
+    last_activity: Instant,
+    window_started_at: Instant,
+    window_start_bytes: u64,
+}
+
+// Tracks how long the running session went without traffic, from periodic
+// samples of the client traffic counters
+#[derive(Default)]
+pub struct IdleMonitor {
+    session: Option<IdleSession>,
+}
+
+impl IdleMonitor {
+    // Called with the traffic of the running session, `None` for backends
+    // that do not count traffic, which are never considered idle
+    pub fn observe(
+        &mut self,
+        traffic: Option<SecureLinkTraffic>,
+        idle_timeout: Option<Duration>,
+        now: Instant,
+    ) -> IdleCheck {
+        let (Some(traffic), Some(idle_timeout)) = (traffic, idle_timeout) else {
+            self.session = None;
+            return IdleCheck::Active;
+        };
+
+        let total_bytes = traffic.bytes_sent + traffic.bytes_received;
+
+        let session = match &mut self.session {
+            // Counters going back mean a new session
+            Some(session) if total_bytes >= session.window_start_bytes => session,
+            _ => self.session.insert(IdleSession {
+                last_activity: now,
+                window_started_at: now,
+                window_start_bytes: total_bytes,
+            }),
+        };
+
+        if total_bytes - session.window_start_bytes >= ACTIVITY_THRESHOLD_BYTES {
+            session.last_activity = now;
+            session.window_started_at = now;
+            session.window_start_bytes = total_bytes;
+        } else if now.duration_since(session.window_started_at) >= ACTIVITY_WINDOW {
+            session.window_started_at = now;
+            session.window_start_bytes = total_bytes;
+        }
+
+        let idle_for = now.duration_since(session.last_activity);
+
+        if idle_for >= idle_timeout {
+            return IdleCheck::TimedOut;
+        }
+
+        let disconnect_in = idle_timeout - idle_for;
+
+        if disconnect_in <= IDLE_WARNING_LEAD.min(idle_timeout / 2) {
+            IdleCheck::Warning {
+                disconnect_in_ms: disconnect_in.as_millis() as u64,
+            }
+        } else {
+            IdleCheck::Active
+        }
+    }
+
+    // The user asked to keep the link up, the idle time starts over
+    pub fn keep_alive(&mut self, now: Instant) {
+        if let Some(session) = &mut self.session {
+            session.last_activity = now;
+        }
+    }
+
+    // The session ended
+    pub fn reset(&mut self) {
+        self.session = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+    fn traffic(bytes: u64) -> Option<SecureLinkTraffic> {
+        Some(SecureLinkTraffic {
+            bytes_sent: bytes,
+            bytes_received: 0,
+        })
+    }
+
+    fn minutes(minutes: u64) -> Duration {
+        Duration::from_secs(minutes * 60)
+    }
+
+    #[test]
+    fn times_out_without_traffic() {
+        let mut monitor = IdleMonitor::default();
+        let start = Instant::now();
+
+        assert_eq!(
+            monitor.observe(traffic(0), Some(IDLE_TIMEOUT), start),
+            IdleCheck::Active
+        );
+        assert_eq!(
+            monitor.observe(traffic(0), Some(IDLE_TIMEOUT), start + minutes(29)),
+            IdleCheck::Warning {
+                disconnect_in_ms: 60_000
+            }
+        );
+        assert_eq!(
+            monitor.observe(traffic(0), Some(IDLE_TIMEOUT), start + minutes(30)),
+            IdleCheck::TimedOut
+        );
+    }
+
+    #[test]
+    fn heartbeats_do_not_count_as_activity() {
+        let mut monitor = IdleMonitor::default();
+        let start = Instant::now();
+
+        // A 100 byte heartbeat every 30 seconds
+        for step in 0..60 {
+            let check = monitor.observe(
+                traffic(step * 100),
+                Some(IDLE_TIMEOUT),
+                start + Duration::from_secs(step * 30),
+            );
+
+            assert_ne!(check, IdleCheck::TimedOut);
+        }
+
+        assert_eq!(
+            monitor.observe(traffic(6_000), Some(IDLE_TIMEOUT), start + minutes(30)),
+            IdleCheck::TimedOut
+        );
+    }
+
+    #[test]
+    fn traffic_restarts_the_idle_time() {
+        let mut monitor = IdleMonitor::default();
+        let start = Instant::now();
+
+        monitor.observe(traffic(0), Some(IDLE_TIMEOUT), start);
+        monitor.observe(traffic(100_000), Some(IDLE_TIMEOUT), start + minutes(20));
+
+        assert_eq!(
+            monitor.observe(traffic(100_000), Some(IDLE_TIMEOUT), start + minutes(40)),
+            IdleCheck::Active
+        );
+    }
+
+    #[test]
+    fn keep_alive_restarts_the_idle_time() {
+        let mut monitor = IdleMonitor::default();
+        let start = Instant::now();
+
+        monitor.observe(traffic(0), Some(IDLE_TIMEOUT), start);
+        monitor.keep_alive(start + minutes(29));
+
+        assert_eq!(
+            monitor.observe(traffic(0), Some(IDLE_TIMEOUT), start + minutes(30)),
+            IdleCheck::Active
+        );
+    }
+
+    #[test]
+    fn new_session_starts_over() {
+        let mut monitor = IdleMonitor::default();
+        let start = Instant::now();
+
+        monitor.observe(traffic(50_000), Some(IDLE_TIMEOUT), start);
+
+        // Counters were reset by a reconnect
+        monitor.observe(traffic(0), Some(IDLE_TIMEOUT), start + minutes(29));
+
+        assert_eq!(
+            monitor.observe(traffic(0), Some(IDLE_TIMEOUT), start + minutes(30)),
+            IdleCheck::Active
+        );
+    }
+
+    #[test]
+    fn disabled_without_timeout_or_traffic_counters() {
+        let mut monitor = IdleMonitor::default();
+        let start = Instant::now();
+
+        assert_eq!(
+            monitor.observe(traffic(0), None, start + minutes(600)),
+            IdleCheck::Active
+        );
+        assert_eq!(
+            monitor.observe(None, Some(IDLE_TIMEOUT), start + minutes(600)),
+            IdleCheck::Active
+        );
+    }
+}
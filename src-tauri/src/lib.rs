@@ -1,270 +1,2424 @@
-use crate::secure_link_client::{SecureLinkClient, SecureLinkClientError, SecureLinkClientState};
-use log::warn;
+use crate::auth_token::{AuthToken, AuthTokenInfo};
+use crate::auth_token_import::ImportedAuthToken;
+use crate::bandwidth_limit::{BandwidthLimit, BandwidthLimiter, Throughput, ThroughputMeter};
+use crate::client_errors::{
+    client_error_message, ClientErrorCategory, ClientErrorLog, ClientErrorRecord,
+};
+use crate::command_line_actions::CommandLineAction;
+use crate::connection_history::{
+    ConnectionHistory, ConnectionHistoryRange, ConnectionOutcome, ConnectionRecord,
+    ConnectionSessionTracker,
+};
+use crate::connection_schedule::{ConnectionSchedule, ScheduleConfig, ScheduleStatus};
+use crate::endpoint_failover::EndpointFailover;
+use crate::enrollment::EnrollmentError;
+use crate::global_shortcuts::{
+    ShortcutAction, ShortcutRegistration, ShortcutSettings, ShortcutStatus,
+};
+use crate::idle_monitor::{IdleCheck, IdleMonitor};
+use crate::managed_policy::{ManagedPolicy, ManagedPolicyStatus, PolicyViolation};
+use crate::metrics::{MetricsSettings, MetricsSnapshot, SecureLinkMetrics};
+use crate::secure_link_client::{
+    SecureLinkClient, SecureLinkClientFactory, SecureLinkClientParams, SecureLinkClientState,
+    SecureLinkClientStopOutcome, SecureLinkClientStopReason, SecureLinkTraffic,
+};
+use crate::secure_link_supervisor::{
+    SecureLinkClientStatus, SecureLinkSupervisor, SecureLinkSupervisorError, SupervisorContext,
+};
+use crate::server_config::ServerConfig;
+use crate::settings::{
+    EndpointSettings, LinkProfile, Settings, SettingsChange, SettingsError, SettingsStore,
+    DEFAULT_PROFILE_ID,
+};
+use crate::status_details::{LinkStatusTracker, StatusDetails};
+use crate::updater::{DownloadedUpdate, InstalledUpdateCheck, UpdateStatus, Updater};
+use log::{error, info, warn};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tauri::{
-    menu::{Menu, MenuItem},
+    menu::{Menu, MenuItem, Submenu},
     tray::TrayIconBuilder,
 };
-use tauri::{AppHandle, Manager, State};
-
+use tauri::{AppHandle, Emitter, Manager, State};
+use tauri_plugin_dialog::{DialogExt, MessageDialogButtons, MessageDialogKind};
+use tauri_plugin_global_shortcut::{GlobalShortcut, Shortcut, ShortcutState};
+use tauri_plugin_notification::NotificationExt;
+use zeroize::Zeroize;
+
+mod auth_token;
+mod auth_token_expiry;
+mod auth_token_import;
+mod bandwidth_limit;
+mod client_errors;
+mod command_line_actions;
+mod connection_history;
+mod connection_schedule;
+mod endpoint_failover;
+mod enrollment;
+mod global_shortcuts;
+mod idle_monitor;
+mod managed_policy;
+mod metrics;
+mod proxy_route;
 mod secure_link_client;
+mod secure_link_supervisor;
 #[cfg(feature = "secure-link-windows-service-client")]
 mod secure_link_windows_service_client;
+mod server_config;
+mod server_trust;
+mod settings;
+mod status_details;
+mod updater;
 
 #[cfg(feature = "secure-link-embedded-client")]
 mod secure_link_embedded_client;
 
+#[cfg(feature = "secure-link-embedded-client")]
+mod connectivity_check;
+
+#[cfg(feature = "secure-link-embedded-client")]
+mod proxy_tunnel;
+
+#[cfg(feature = "secure-link-embedded-client")]
+mod traffic_meter;
+
 #[cfg(feature = "windows-registry")]
 mod auth_token_windows_registry_storage;
 
+#[cfg(feature = "windows-registry")]
+mod managed_policy_windows_registry;
+
+#[cfg(test)]
+mod end_to_end_tests;
+
+#[cfg(test)]
+mod mock_secure_link_client;
+
+#[cfg(all(test, feature = "secure-link-embedded-client"))]
+mod stand_in_secure_link_server;
+
+#[cfg(test)]
+mod stand_in_release_server;
+
+#[cfg(test)]
+mod stand_in_enrollment_server;
+
+#[cfg(test)]
+mod tests;
+
 pub static SECURE_LINK_APP_AUTH_TOKEN_KEY: &str = "secure-link-app:auth-token-key";
 
+static TRAY_ID: &str = "main";
+
+#[cfg(not(feature = "windows-registry"))]
+static PROXY_PASSWORD_FILE_NAME: &str = "proxy_password_file.txt";
+
 // Store menu items for direct updates
 struct TrayMenuItems {
+    schedule_item: MenuItem<tauri::Wry>,
+    // Connect and disconnect of the default link
+    connect_item: MenuItem<tauri::Wry>,
+    disconnect_item: MenuItem<tauri::Wry>,
+    keep_connected_item: MenuItem<tauri::Wry>,
+    // One submenu per profile, in the order of the settings
+    profile_items: Vec<TrayProfileItems>,
+}
+
+struct TrayProfileItems {
+    profile: LinkProfile,
+    submenu: Submenu<tauri::Wry>,
     connect_item: MenuItem<tauri::Wry>,
     disconnect_item: MenuItem<tauri::Wry>,
 }
 
 struct AppData {
-    secure_link_client: Mutex<Option<Arc<dyn SecureLinkClient + Send + Sync>>>,
+    // Keyed by profile id, the default link is always there
+    links: Mutex<BTreeMap<String, Arc<SecureLink>>>,
+    secure_link_client_factory: SecureLinkClientFactory,
     tray_menu_items: Mutex<Option<TrayMenuItems>>,
-    #[cfg(feature = "secure-link-windows-service-client")]
-    secure_link_service_log_file_path: std::path::PathBuf,
-    #[cfg(not(feature = "windows-registry"))]
-    auth_token_file_path: std::path::PathBuf,
+    app_data_dir: PathBuf,
+    // The server the app was built for, the settings may override it
     secure_link_server_host: String,
     secure_link_server_port: u16,
+    settings: SettingsStore,
+    // Serves the metrics while enabled in the settings
+    metrics_server: Mutex<Option<tauri::async_runtime::JoinHandle<()>>>,
+    // `None` when the app was built without a release key
+    updater: Option<Updater>,
+    update_status: Mutex<UpdateStatus>,
+    downloaded_update: Mutex<Option<DownloadedUpdate>>,
+    // The shortcuts from the settings that were registered
+    global_shortcuts: Mutex<Vec<(ShortcutAction, Shortcut)>>,
+    shortcut_status: Mutex<ShortcutStatus>,
+    // Read once at startup, the default policy locks nothing
+    policy: ManagedPolicy,
+    policy_source: Option<String>,
+    policy_error: Option<String>,
+    // Asks before the auth token is shown or sent somewhere else
+    native_confirm: NativeConfirm,
+}
+
+// Asks the user with a native dialog, which a script running in the webview
+// can not answer. Takes the question and the label of the confirm button.
+type NativeConfirm =
+    Box<dyn Fn(String, String) -> tokio::sync::oneshot::Receiver<bool> + Send + Sync>;
+
+// One link with its own client, auth token, connection history and statistics
+struct SecureLink {
+    profile_id: String,
+    supervisor: SecureLinkSupervisor,
+    connection_history: ConnectionHistory,
+    connection_session_tracker: ConnectionSessionTracker,
+    #[cfg(feature = "secure-link-windows-service-client")]
+    service_log_file_path: PathBuf,
+    #[cfg(not(feature = "windows-registry"))]
+    auth_token_file_path: PathBuf,
+    idle_monitor: Mutex<IdleMonitor>,
+    idle_check: Mutex<IdleCheck>,
+    throughput_meter: Mutex<ThroughputMeter>,
+    // Shared with the clients of the link, every link is limited on its own
+    bandwidth_limiter: Arc<BandwidthLimiter>,
+    metrics: SecureLinkMetrics,
+    status_tracker: LinkStatusTracker,
+    // Shared with the clients of the link
+    error_log: Arc<ClientErrorLog>,
+    // Which of the endpoints of the link the client connects to
+    endpoint_failover: EndpointFailover,
+}
+
+impl AppData {
+    fn link(&self, profile_id: &str) -> Option<Arc<SecureLink>> {
+        self.links.lock().unwrap().get(profile_id).cloned()
+    }
+
+    fn default_link(&self) -> Arc<SecureLink> {
+        self.link(DEFAULT_PROFILE_ID)
+            .expect("the default link is never removed")
+    }
+
+    // The default link first
+    fn all_links(&self) -> Vec<Arc<SecureLink>> {
+        let links = self.links.lock().unwrap();
+
+        let (default_link, profile_links): (Vec<_>, Vec<_>) = links
+            .values()
+            .cloned()
+            .partition(|link| link.profile_id == DEFAULT_PROFILE_ID);
+
+        default_link.into_iter().chain(profile_links).collect()
+    }
+}
+
+// The default link keeps the file names from before profiles existed
+fn link_file_name(profile_id: &str, stem: &str, extension: &str) -> String {
+    if profile_id == DEFAULT_PROFILE_ID {
+        format!("{}.{}", stem, extension)
+    } else {
+        format!("{}_{}.{}", stem, profile_id, extension)
+    }
+}
+
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LinkErrorEvent<'a> {
+    profile_id: &'a str,
+    error: &'a ClientErrorRecord,
+}
+
+// Creates the link of `profile_id` and runs its supervisor
+fn spawn_secure_link<R: tauri::Runtime>(
+    app: &AppHandle<R>,
+    app_data_dir: &Path,
+    profile_id: &str,
+    bandwidth_limit: &BandwidthLimit,
+) -> Result<Arc<SecureLink>, Box<dyn std::error::Error>> {
+    let (supervisor, supervisor_receiver) = SecureLinkSupervisor::new();
+
+    let link = Arc::new(SecureLink {
+        profile_id: profile_id.to_string(),
+        supervisor,
+        connection_history: ConnectionHistory::open(
+            app_data_dir.join(link_file_name(profile_id, "connection_history", "jsonl")),
+            Default::default(),
+        )?,
+        connection_session_tracker: ConnectionSessionTracker::default(),
+        #[cfg(feature = "secure-link-windows-service-client")]
+        service_log_file_path: app_data_dir.join(link_file_name(
+            profile_id,
+            "secure_link_service",
+            "log",
+        )),
+        #[cfg(not(feature = "windows-registry"))]
+        auth_token_file_path: app_data_dir.join(link_file_name(
+            profile_id,
+            "auth_token_file",
+            "txt",
+        )),
+        idle_monitor: Mutex::new(IdleMonitor::default()),
+        idle_check: Mutex::new(IdleCheck::Active),
+        throughput_meter: Mutex::new(ThroughputMeter::default()),
+        bandwidth_limiter: Arc::new(BandwidthLimiter::new(bandwidth_limit)),
+        metrics: SecureLinkMetrics::default(),
+        status_tracker: LinkStatusTracker::default(),
+        error_log: Arc::new(ClientErrorLog::new({
+            let app = app.clone();
+            let profile_id = profile_id.to_string();
+
+            move |error: &ClientErrorRecord| {
+                let event = LinkErrorEvent {
+                    profile_id: &profile_id,
+                    error,
+                };
+
+                if let Err(e) = app.emit("link-error", &event) {
+                    warn!("Failed to emit link error: {}", e);
+                }
+            }
+        })),
+        endpoint_failover: EndpointFailover::default(),
+    });
+
+    tauri::async_runtime::spawn(secure_link_supervisor::run_secure_link_supervisor(
+        supervisor_receiver,
+        AppSupervisorContext {
+            app: app.clone(),
+            profile_id: profile_id.to_string(),
+        },
+    ));
+
+    Ok(link)
+}
+
+fn unknown_profile_error(profile_id: &str) -> String {
+    format!("UnknownProfile: {}", profile_id)
 }
 
 #[tauri::command]
 async fn current_state(state: State<'_, AppData>) -> Result<String, String> {
-    let maybe_client = ensure_secure_link_client_created(&state)
+    let status = state
+        .default_link()
+        .supervisor
+        .status()
         .await
-        .map_err(|e| format!("{:?}", e))?;
+        .map_err(|e| e.to_string())?;
+
+    Ok(client_state_name(&status.state).to_string())
+}
+
+// Of the default link unless `profile_id` is given
+#[tauri::command]
+async fn get_status_details<R: tauri::Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, AppData>,
+    profile_id: Option<String>,
+) -> Result<StatusDetails, String> {
+    let profile_id = profile_id.as_deref().unwrap_or(DEFAULT_PROFILE_ID);
+    let link = state
+        .link(profile_id)
+        .ok_or_else(|| unknown_profile_error(profile_id))?;
+
+    let client_status = link.supervisor.status().await.map_err(|e| e.to_string())?;
+    let state_entered_at = link.status_tracker.observe(&client_status.state);
+
+    let (server_host, server_port) = secure_link_server_endpoint(&state, profile_id);
+
+    let token_fingerprint = load_auth_token(&link)
+        .map_err(|e| e.to_string())?
+        .map(|auth_token| auth_token.fingerprint());
+
+    Ok(StatusDetails {
+        state: client_state_name(&client_status.state).to_string(),
+        state_entered_at: connection_history::unix_time_millis(state_entered_at),
+        backend: status_details::backend_kind(),
+        server_host,
+        server_port,
+        token_fingerprint,
+        last_error: link.error_log.last(),
+        endpoints: link.endpoint_failover.status(),
+        app_version: app.package_info().version.to_string(),
+        features: status_details::enabled_features(),
+    })
+}
+
+// Oldest first, of the default link unless `profile_id` is given. New ones
+// are also emitted as `link-error` events.
+#[tauri::command]
+async fn get_recent_errors(
+    state: State<'_, AppData>,
+    profile_id: Option<String>,
+) -> Result<Vec<ClientErrorRecord>, String> {
+    let profile_id = profile_id.as_deref().unwrap_or(DEFAULT_PROFILE_ID);
+    let link = state
+        .link(profile_id)
+        .ok_or_else(|| unknown_profile_error(profile_id))?;
+
+    Ok(link.error_log.recent())
+}
+
+#[cfg(feature = "secure-link-embedded-client")]
+#[derive(Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ConnectivityCheckProgress<'a> {
+    profile_id: &'a str,
+    result: &'a connectivity_check::StageResult,
+}
+
+// Runs the connectivity check against the server of the link, every finished
+// stage is also emitted as a `connectivity-check-progress` event
+#[cfg(feature = "secure-link-embedded-client")]
+#[tauri::command]
+async fn check_connection<R: tauri::Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, AppData>,
+    profile_id: Option<String>,
+) -> Result<Vec<connectivity_check::StageResult>, String> {
+    let profile_id = profile_id.as_deref().unwrap_or(DEFAULT_PROFILE_ID);
+    let link = state
+        .link(profile_id)
+        .ok_or_else(|| unknown_profile_error(profile_id))?;
+
+    let auth_token = load_auth_token(&link).map_err(|e| e.to_string())?;
+    let (host, port) = secure_link_server_endpoint(&state, profile_id);
+    let server_config = client_server_config(&state);
+
+    let target = connectivity_check::ConnectivityCheckTarget {
+        host: &host,
+        port,
+        server_config: &server_config,
+        auth_token: auth_token.as_ref(),
+        stage_timeout: connectivity_check::STAGE_TIMEOUT,
+    };
+
+    let results = connectivity_check::run_connectivity_check(&target, |result| {
+        let progress = ConnectivityCheckProgress { profile_id, result };
+
+        if let Err(e) = app.emit("connectivity-check-progress", &progress) {
+            warn!("Failed to emit connectivity check progress: {}", e);
+        }
+    })
+    .await;
+
+    info!(
+        "Connectivity check of {}: {:?}",
+        profile_id,
+        results
+            .iter()
+            .map(|result| (result.stage, result.status))
+            .collect::<Vec<_>>()
+    );
+
+    Ok(results)
+}
+
+#[cfg(feature = "secure-link-windows-service-client")]
+#[tauri::command]
+async fn get_service_log(
+    state: State<'_, AppData>,
+    profile_id: Option<String>,
+) -> Result<String, String> {
+    let profile_id = profile_id.as_deref().unwrap_or(DEFAULT_PROFILE_ID);
+    let link = state
+        .link(profile_id)
+        .ok_or_else(|| unknown_profile_error(profile_id))?;
+    let log_file_path = link.service_log_file_path.clone();
+
+    if !log_file_path.exists() {
+        return Ok("".to_string());
+    }
+
+    match std::fs::read_to_string(log_file_path) {
+        Ok(content) => Ok(content),
+        Err(e) => Err(format!("Не удалось прочитать файл логов: {}", e)),
+    }
+}
+
+// Hooks the supervisor of one link uses to create clients from the stored
+// settings and to keep the connection history
+struct AppSupervisorContext<R: tauri::Runtime> {
+    app: AppHandle<R>,
+    profile_id: String,
+}
+
+impl<R: tauri::Runtime> AppSupervisorContext<R> {
+    // `None` once the profile was removed
+    fn link(&self) -> Option<Arc<SecureLink>> {
+        self.app.state::<AppData>().link(&self.profile_id)
+    }
+}
+
+impl<R: tauri::Runtime> SupervisorContext for AppSupervisorContext<R> {
+    fn create_client(
+        &self,
+    ) -> Result<Option<Arc<dyn SecureLinkClient>>, SecureLinkSupervisorError> {
+        let state = self.app.state::<AppData>();
+
+        let Some(link) = self.link() else {
+            return Ok(None);
+        };
+
+        let auth_token = match load_auth_token(&link)
+            .map_err(|e| SecureLinkSupervisorError::AuthTokenStorage(e.to_string()))?
+        {
+            None => return Ok(None),
+            Some(auth_token) => auth_token,
+        };
+
+        link.endpoint_failover
+            .select(&link_endpoints(&state, &self.profile_id));
+
+        let (secure_link_server_host, secure_link_server_port) =
+            secure_link_server_endpoint(&state, &self.profile_id);
+
+        let client = (state.secure_link_client_factory)(SecureLinkClientParams {
+            auth_token: &auth_token,
+            secure_link_server_host: &secure_link_server_host,
+            secure_link_server_port,
+            server_config: &client_server_config(&state),
+            bandwidth_limiter: &link.bandwidth_limiter,
+            error_log: &link.error_log,
+            #[cfg(feature = "secure-link-windows-service-client")]
+            service_log_file_path: &link.service_log_file_path,
+            #[cfg(feature = "secure-link-windows-service-client")]
+            profile_id: &self.profile_id,
+        });
+
+        Ok(Some(client))
+    }
+
+    fn start_attempted(&self) {
+        let state = self.app.state::<AppData>();
+
+        if let Some(link) = self.link() {
+            link.metrics.connect_attempted();
+            link.connection_session_tracker
+                .attempt_started(&secure_link_server_address(&state, &self.profile_id));
+        }
+    }
+
+    fn start_finished(&self, result: &Result<(), SecureLinkSupervisorError>) {
+        if let Some(link) = self.link() {
+            record_start_result(&link, result);
+        }
+    }
+
+    // Only an unreachable server is worth trying another endpoint for, a
+    // rejected auth token fails on all of them
+    fn fail_over(&self, error: &SecureLinkSupervisorError) -> bool {
+        if ClientErrorCategory::of(error) != ClientErrorCategory::Network {
+            return false;
+        }
+
+        let Some(link) = self.link() else {
+            return false;
+        };
+
+        let failed_endpoint = link.endpoint_failover.active();
+        let can_fail_over = link.endpoint_failover.failed();
+
+        if can_fail_over {
+            info!(
+                "Link {} failed to connect to {:?}, trying the next endpoint",
+                self.profile_id,
+                failed_endpoint.map(|endpoint| format!("{}:{}", endpoint.host, endpoint.port))
+            );
+        }
+
+        can_fail_over
+    }
+
+    fn stopped(&self, reason: SecureLinkClientStopReason, traffic: Option<SecureLinkTraffic>) {
+        if let Some(link) = self.link() {
+            record_disconnect(
+                &link,
+                connection_outcome_for_stop_reason(Some(reason)),
+                traffic,
+            );
+        }
+    }
+}
+
+// Backend selected at compile time, tests inject their own factory instead
+fn default_secure_link_client_factory() -> SecureLinkClientFactory {
+    Box::new(move |params| {
+        #[cfg(feature = "secure-link-embedded-client")]
+        let client = {
+            secure_link_embedded_client::SecureLinkEmbeddedClient::new(
+                params.auth_token,
+                params.secure_link_server_host,
+                params.secure_link_server_port,
+                params.server_config,
+                params.bandwidth_limiter.clone(),
+                params.error_log.clone(),
+            )
+        };
+
+        #[cfg(feature = "secure-link-windows-service-client")]
+        let client = {
+            secure_link_windows_service_client::SecureLinkWindowsServiceClient::new(
+                params.secure_link_server_host,
+                params.secure_link_server_port,
+                params.auth_token,
+                params.service_log_file_path.to_str().unwrap(),
+                params.profile_id,
+            )
+        };
+
+        Arc::new(client)
+    })
+}
+
+// Stops the current client of the link and creates a new one from the stored
+// auth token and server config
+async fn reinitialize_secure_link_client(
+    link: &SecureLink,
+) -> Result<(), SecureLinkSupervisorError> {
+    link.supervisor.reconfigure().await
+}
+
+// Outside of the schedule windows the UI asks for confirmation and retries
+// with `override_schedule`
+#[tauri::command]
+async fn start(state: State<'_, AppData>, override_schedule: Option<bool>) -> Result<(), String> {
+    start_secure_link(&state, &state.default_link(), override_schedule).await
+}
+
+// Resolves once the session has ended, `forced` when it had to be aborted
+#[tauri::command]
+async fn stop(state: State<'_, AppData>) -> Result<SecureLinkClientStopOutcome, String> {
+    state.policy.check_disconnect().map_err(|e| e.to_string())?;
+
+    state
+        .default_link()
+        .supervisor
+        .stop()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+async fn start_secure_link(
+    state: &State<'_, AppData>,
+    link: &SecureLink,
+    override_schedule: Option<bool>,
+) -> Result<(), String> {
+    if !override_schedule.unwrap_or(false) && !is_connect_allowed_by_schedule(state) {
+        return Err("OutsideScheduleWindow".to_string());
+    }
+
+    // Errors display as "UnauthorizedError", "CertificatePinMismatch",
+    // "No auth token" or the client error
+    link.supervisor.start().await.map_err(|e| e.to_string())
+}
+
+// Same as `start` for the link of `profile_id`
+#[tauri::command]
+async fn start_link(
+    state: State<'_, AppData>,
+    profile_id: String,
+    override_schedule: Option<bool>,
+) -> Result<(), String> {
+    let link = state
+        .link(&profile_id)
+        .ok_or_else(|| unknown_profile_error(&profile_id))?;
+
+    start_secure_link(&state, &link, override_schedule).await
+}
+
+#[tauri::command]
+async fn stop_link(
+    state: State<'_, AppData>,
+    profile_id: String,
+) -> Result<SecureLinkClientStopOutcome, String> {
+    state.policy.check_disconnect().map_err(|e| e.to_string())?;
+
+    let link = state
+        .link(&profile_id)
+        .ok_or_else(|| unknown_profile_error(&profile_id))?;
+
+    link.supervisor.stop().await.map_err(|e| e.to_string())
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LinkStatus {
+    profile_id: String,
+    name: String,
+    address: String,
+    // Same values as `current_state`
+    state: String,
+    traffic: Option<SecureLinkTraffic>,
+}
+
+async fn link_status(state: &State<'_, AppData>, link: &SecureLink) -> LinkStatus {
+    let client_status = get_client_status(link).await;
+
+    LinkStatus {
+        profile_id: link.profile_id.clone(),
+        name: profile_name(state, &link.profile_id),
+        address: secure_link_server_address(state, &link.profile_id),
+        state: client_state_name(&client_status.state).to_string(),
+        traffic: client_status.traffic,
+    }
+}
+
+#[tauri::command]
+async fn get_link_status(
+    state: State<'_, AppData>,
+    profile_id: String,
+) -> Result<LinkStatus, String> {
+    let link = state
+        .link(&profile_id)
+        .ok_or_else(|| unknown_profile_error(&profile_id))?;
+
+    Ok(link_status(&state, &link).await)
+}
+
+// The default link first, then the profiles
+#[tauri::command]
+async fn list_links(state: State<'_, AppData>) -> Result<Vec<LinkStatus>, String> {
+    let mut statuses = Vec::new();
+
+    for link in state.all_links() {
+        statuses.push(link_status(&state, &link).await);
+    }
+
+    Ok(statuses)
+}
+
+fn client_state_name(client_state: &SecureLinkClientState) -> &'static str {
+    match client_state {
+        SecureLinkClientState::Running => "Running",
+        SecureLinkClientState::Pending => "Pending",
+        SecureLinkClientState::Stopping => "Stopping",
+        SecureLinkClientState::Stopped => "Stopped",
+    }
+}
+
+// Separate tray-specific start function
+async fn tray_start(
+    state: &State<'_, AppData>,
+    link: &SecureLink,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !is_connect_allowed_by_schedule(state) {
+        warn!("Refusing to start from tray outside of the connection schedule");
+        return Err("Outside of the connection schedule window".into());
+    }
+
+    match link.supervisor.start().await {
+        Ok(()) => Ok(()),
+        Err(SecureLinkSupervisorError::Unauthorized) => {
+            eprintln!("Unauthorized error when starting from tray");
+            Err("Unauthorized error".into())
+        }
+        Err(SecureLinkSupervisorError::NoAuthToken) => {
+            eprintln!("No auth token available for tray start");
+            Err("No auth token".into())
+        }
+        Err(err) => {
+            eprintln!("Error starting from tray: {}", err);
+            Err(err.into())
+        }
+    }
+}
+
+// Separate tray-specific stop function
+async fn tray_stop(
+    state: &State<'_, AppData>,
+    link: &SecureLink,
+) -> Result<(), Box<dyn std::error::Error>> {
+    state.policy.check_disconnect()?;
+
+    if link.supervisor.stop().await? == SecureLinkClientStopOutcome::Forced {
+        warn!("Secure link did not stop in time and was aborted");
+    }
+
+    Ok(())
+}
+
+// Profiles have their own endpoints. The default link uses the ones from the
+// settings, or the one the app was built for. An endpoint pinned by the
+// managed policy overrides all of them.
+fn link_endpoints(state: &State<'_, AppData>, profile_id: &str) -> Vec<EndpointSettings> {
+    if let Some(endpoint) = &state.policy.endpoint {
+        return vec![endpoint.clone()];
+    }
+
+    let settings = state.settings.get();
+
+    if let Some(profile) = settings
+        .profiles
+        .into_iter()
+        .find(|profile| profile.id == profile_id)
+    {
+        return std::iter::once(profile.endpoint)
+            .chain(profile.endpoints)
+            .collect();
+    }
+
+    if !settings.endpoints.is_empty() {
+        return settings.endpoints;
+    }
+
+    vec![settings.endpoint.unwrap_or_else(|| EndpointSettings {
+        host: state.secure_link_server_host.clone(),
+        port: state.secure_link_server_port,
+        priority: 0,
+        weight: 1,
+    })]
+}
+
+// The endpoint the link is connected or connecting to, the first one before
+// the link had a client
+fn secure_link_server_endpoint(state: &State<'_, AppData>, profile_id: &str) -> (String, u16) {
+    let mut endpoints = link_endpoints(state, profile_id);
+
+    let active_endpoint = state
+        .link(profile_id)
+        .and_then(|link| link.endpoint_failover.active())
+        .filter(|active_endpoint| endpoints.contains(active_endpoint));
+
+    let endpoint = active_endpoint.unwrap_or_else(|| endpoints.swap_remove(0));
+
+    (endpoint.host, endpoint.port)
+}
+
+fn secure_link_server_address(state: &State<'_, AppData>, profile_id: &str) -> String {
+    let (secure_link_server_host, secure_link_server_port) =
+        secure_link_server_endpoint(state, profile_id);
+
+    format!("{}:{}", secure_link_server_host, secure_link_server_port)
+}
+
+fn profile_name(state: &State<'_, AppData>, profile_id: &str) -> String {
+    state
+        .settings
+        .get()
+        .profiles
+        .into_iter()
+        .find(|profile| profile.id == profile_id)
+        .map(|profile| profile.name)
+        .unwrap_or_else(|| "Основное подключение".to_string())
+}
+
+// Failed attempts are recorded right away, successful ones stay open until stopped
+fn record_start_result(link: &SecureLink, start_result: &Result<(), SecureLinkSupervisorError>) {
+    link.metrics.connect_finished(start_result);
+
+    if start_result.is_ok() {
+        link.endpoint_failover.succeeded();
+    }
+
+    if let Err(e) = start_result {
+        link.error_log
+            .record(ClientErrorCategory::of(e), client_error_message(e));
+    }
+
+    let outcome = match start_result {
+        Ok(()) => return link.connection_session_tracker.observed_active(),
+        Err(SecureLinkSupervisorError::Unauthorized) => ConnectionOutcome::Unauthorized,
+        Err(_) => ConnectionOutcome::NetworkError,
+    };
+
+    record_session_end(link, outcome, None);
+}
+
+// Returns whether a session was open. `traffic` is `None` for failed attempts
+// and backends that can not see the link traffic.
+fn record_session_end(
+    link: &SecureLink,
+    outcome: ConnectionOutcome,
+    traffic: Option<SecureLinkTraffic>,
+) -> bool {
+    let Some(record) = link.connection_session_tracker.ended(outcome, traffic) else {
+        return false;
+    };
+
+    if let Err(e) = link.connection_history.append(&record) {
+        warn!(
+            "Failed to append connection history record of {}: {}",
+            link.profile_id, e
+        );
+    }
+
+    true
+}
+
+// Failed attempts are closed by `record_start_result`, so only established
+// sessions count as disconnects
+fn record_disconnect(
+    link: &SecureLink,
+    outcome: ConnectionOutcome,
+    traffic: Option<SecureLinkTraffic>,
+) {
+    if record_session_end(link, outcome, traffic) {
+        link.metrics.disconnected(outcome);
+    }
+}
+
+// Closes the open session when the client stopped on its own, e.g. the server
+// dropped the connection. Returns the outcome of the closed session.
+fn track_connection_session(
+    link: &SecureLink,
+    client_status: &SecureLinkClientStatus,
+) -> Option<ConnectionOutcome> {
+    link.status_tracker.observe(&client_status.state);
+
+    match client_status.state {
+        SecureLinkClientState::Pending
+        | SecureLinkClientState::Running
+        | SecureLinkClientState::Stopping => {
+            link.connection_session_tracker.observed_active();
+            None
+        }
+        SecureLinkClientState::Stopped => {
+            if !link.connection_session_tracker.was_observed_active() {
+                return None;
+            }
+
+            let outcome =
+                connection_outcome_for_stop_reason(client_status.last_stop_reason.clone());
+            record_disconnect(link, outcome, client_status.traffic);
+
+            Some(outcome)
+        }
+    }
+}
+
+// Reconnects a link that dropped mid-session to the next healthy endpoint
+async fn fail_over_secure_link<R: tauri::Runtime>(app: AppHandle<R>, link: Arc<SecureLink>) {
+    let state = app.state::<AppData>();
+
+    info!(
+        "Link {} dropped, reconnecting to the next endpoint",
+        link.profile_id
+    );
+
+    if let Err(e) = reinitialize_secure_link_client(&link).await {
+        warn!(
+            "Failed to recreate the client of {}: {}",
+            link.profile_id, e
+        );
+        return;
+    }
+
+    if let Err(e) = start_secure_link(&state, &link, None).await {
+        warn!("Failed to fail over link {}: {}", link.profile_id, e);
+    }
+}
+
+fn connection_outcome_for_stop_reason(
+    stop_reason: Option<SecureLinkClientStopReason>,
+) -> ConnectionOutcome {
+    match stop_reason {
+        Some(SecureLinkClientStopReason::UserStop) => ConnectionOutcome::UserStop,
+        Some(SecureLinkClientStopReason::NetworkError) => ConnectionOutcome::NetworkError,
+        Some(SecureLinkClientStopReason::IdleTimeout) => ConnectionOutcome::IdleTimeout,
+        Some(SecureLinkClientStopReason::ServerClose) | None => ConnectionOutcome::ServerClose,
+    }
+}
+
+// Of the default link unless `profile_id` is given
+#[tauri::command]
+async fn get_connection_history(
+    state: State<'_, AppData>,
+    range: ConnectionHistoryRange,
+    profile_id: Option<String>,
+) -> Result<Vec<ConnectionRecord>, String> {
+    let profile_id = profile_id.as_deref().unwrap_or(DEFAULT_PROFILE_ID);
+    let link = state
+        .link(profile_id)
+        .ok_or_else(|| unknown_profile_error(profile_id))?;
+
+    link.connection_history
+        .query(&range)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn update_auth_token(state: State<'_, AppData>, auth_token: AuthToken) -> Result<(), String> {
+    state
+        .policy
+        .check_auth_token_editing()
+        .map_err(|e| e.to_string())?;
+
+    replace_auth_token(&state.default_link(), auth_token)
+        .await
+        .map_err(|e| format!("{:?}", e))
+}
+
+#[tauri::command]
+async fn update_link_auth_token(
+    state: State<'_, AppData>,
+    profile_id: String,
+    auth_token: AuthToken,
+) -> Result<(), String> {
+    state
+        .policy
+        .check_auth_token_editing()
+        .map_err(|e| e.to_string())?;
+
+    let link = state
+        .link(&profile_id)
+        .ok_or_else(|| unknown_profile_error(&profile_id))?;
+
+    replace_auth_token(&link, auth_token)
+        .await
+        .map_err(|e| format!("{:?}", e))
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ImportedAuthTokenSummary {
+    // The link the token was stored for
+    profile_id: String,
+    token: AuthTokenInfo,
+}
+
+// Imports the token of a text, enrollment or QR code image file, see
+// `auth_token_import`. Without `path` a native file picker asks for it.
+// Enrollment files may also set the endpoint and name the profile the token
+// is for, a profile that does not exist yet is created.
+#[tauri::command]
+async fn import_auth_token_from_file<R: tauri::Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, AppData>,
+    path: Option<PathBuf>,
+) -> Result<ImportedAuthTokenSummary, String> {
+    state
+        .policy
+        .check_auth_token_editing()
+        .map_err(|e| e.to_string())?;
+
+    let path = match path {
+        Some(path) => path,
+        None => pick_auth_token_file(&app).await?,
+    };
+
+    let imported = auth_token_import::import_auth_token_file(&path).map_err(|e| e.to_string())?;
+
+    info!("Importing auth token from {}", path.display());
+
+    store_imported_auth_token(&app, &state, imported).await
+}
+
+// Exchanges a one-time enrollment code with the enrollment server for a token
+// bound to this device, see `enrollment`. The result is applied like an
+// enrollment file.
+#[tauri::command]
+async fn enroll_with_code<R: tauri::Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, AppData>,
+    code: String,
+) -> Result<ImportedAuthTokenSummary, EnrollmentError> {
+    state
+        .policy
+        .check_auth_token_editing()
+        .map_err(|_| EnrollmentError::PolicyViolation)?;
+
+    let code = enrollment::normalize_enrollment_code(&code)?;
+    let enrollment_url = enrollment_url(&state).ok_or(EnrollmentError::NotConfigured)?;
+
+    let device_id = enrollment::load_or_create_device_id(&state.app_data_dir)
+        .map_err(|e| EnrollmentError::Apply(e.to_string()))?;
+
+    let enrolled = enrollment::exchange_enrollment_code(
+        &enrollment_url,
+        &client_server_config(&state),
+        &code,
+        &device_id,
+    )
+    .await
+    .inspect_err(|e| warn!("Enrollment failed: {}", e))?;
+
+    info!("Enrolled device {} with an enrollment code", device_id);
+
+    store_imported_auth_token(&app, &state, enrolled)
+        .await
+        .map_err(EnrollmentError::Apply)
+}
+
+fn enrollment_url(state: &State<'_, AppData>) -> Option<String> {
+    state
+        .settings
+        .get()
+        .enrollment
+        .url
+        .or_else(|| option_env!("SECURE_LINK_ENROLLMENT_URL").map(str::to_string))
+}
+
+// Applies the endpoint and profile of an enrollment, then stores the token
+// like `update_auth_token` does
+async fn store_imported_auth_token<R: tauri::Runtime>(
+    app: &AppHandle<R>,
+    state: &State<'_, AppData>,
+    imported: ImportedAuthToken,
+) -> Result<ImportedAuthTokenSummary, String> {
+    let profile_id = apply_enrollment_settings(app, state, &imported).await?;
+    let link = state
+        .link(&profile_id)
+        .ok_or_else(|| unknown_profile_error(&profile_id))?;
+
+    replace_auth_token(&link, imported.auth_token)
+        .await
+        .map_err(|e| format!("{:?}", e))?;
+
+    let auth_token = load_auth_token(&link).map_err(|e| e.to_string())?;
+    let stored_at = load_auth_token_stored_at(&link).map_err(|e| e.to_string())?;
+
+    Ok(ImportedAuthTokenSummary {
+        profile_id,
+        token: AuthTokenInfo::new(auth_token.as_ref(), stored_at),
+    })
+}
+
+async fn pick_auth_token_file<R: tauri::Runtime>(app: &AppHandle<R>) -> Result<PathBuf, String> {
+    let (path_sender, path_receiver) = tokio::sync::oneshot::channel();
+
+    app.dialog()
+        .file()
+        .set_title("Импорт токена")
+        .add_filter("Токен или QR-код", &["txt", "json", "png", "jpg", "jpeg"])
+        .pick_file(move |path| {
+            let _ = path_sender.send(path);
+        });
+
+    path_receiver
+        .await
+        .ok()
+        .flatten()
+        .ok_or_else(|| "ImportCancelled".to_string())?
+        .into_path()
+        .map_err(|e| e.to_string())
+}
+
+// Points the link of the enrollment at its endpoint and returns the id of
+// the link. The endpoint replaces any failover endpoints of the link.
+async fn apply_enrollment_settings<R: tauri::Runtime>(
+    app: &AppHandle<R>,
+    state: &State<'_, AppData>,
+    imported: &ImportedAuthToken,
+) -> Result<String, String> {
+    let profiles = state.settings.get().profiles;
+
+    let profile_id = match &imported.profile_name {
+        None => DEFAULT_PROFILE_ID.to_string(),
+        Some(profile_name) => match profiles
+            .iter()
+            .find(|profile| profile.name == *profile_name)
+        {
+            Some(profile) => profile.id.clone(),
+            None => auth_token_import::profile_id_for_name(profile_name, |profile_id| {
+                profiles.iter().any(|profile| profile.id == profile_id)
+            }),
+        },
+    };
+
+    let is_new_profile = profile_id != DEFAULT_PROFILE_ID
+        && !profiles.iter().any(|profile| profile.id == profile_id);
+
+    let Some(endpoint) = &imported.endpoint else {
+        if is_new_profile {
+            return Err("EnrollmentWithoutEndpoint".to_string());
+        }
+
+        return Ok(profile_id);
+    };
+
+    let change = state
+        .settings
+        .update_with(|settings| {
+            match settings
+                .profiles
+                .iter_mut()
+                .find(|profile| profile.id == profile_id)
+            {
+                Some(profile) => {
+                    profile.endpoint = endpoint.clone();
+                    profile.endpoints.clear();
+                }
+                None if profile_id == DEFAULT_PROFILE_ID => {
+                    settings.endpoint = Some(endpoint.clone());
+                    settings.endpoints.clear();
+                }
+                None => settings.profiles.push(LinkProfile {
+                    id: profile_id.clone(),
+                    name: imported.profile_name.clone().unwrap_or_default(),
+                    endpoint: endpoint.clone(),
+                    endpoints: Vec::new(),
+                }),
+            }
+        })
+        .map_err(|e| e.to_string())?;
+
+    if let Some(change) = change {
+        apply_settings_change(app, state, &change).await?;
+    }
+
+    Ok(profile_id)
+}
+
+async fn replace_auth_token(
+    link: &SecureLink,
+    auth_token: AuthToken,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(current_auth_token) = load_auth_token(link)? {
+        if current_auth_token == auth_token {
+            return Ok(());
+        }
+    }
+
+    store_auth_token(link, &auth_token)?;
+
+    info!(
+        "Auth token of link {} replaced with {}",
+        link.profile_id, auth_token
+    );
+
+    reinitialize_secure_link_client(link).await?;
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_settings(state: State<'_, AppData>) -> Result<Settings, String> {
+    Ok(state.settings.get())
+}
+
+// `patch` is a JSON merge patch of the settings, see `SettingsStore::update`.
+// Returns the settings after the update.
+#[tauri::command]
+async fn update_settings<R: tauri::Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, AppData>,
+    patch: serde_json::Value,
+) -> Result<Settings, String> {
+    // `null` removes the stored password like any other key
+    let mut patch = patch;
+    let proxy_password = patch
+        .get_mut("server")
+        .and_then(|server| server.get_mut("proxy"))
+        .and_then(serde_json::Value::as_object_mut)
+        .and_then(|proxy| proxy.remove("password"))
+        .map(|password| password.as_str().unwrap_or_default().to_string());
+
+    let updated_settings = state.settings.patched(&patch).map_err(|e| e.to_string())?;
+    check_settings_policy(&state, &updated_settings)?;
+    confirm_token_destination_change(&state, &updated_settings).await?;
+
+    let change = state.settings.update(&patch).map_err(|e| e.to_string())?;
+
+    if let Some(change) = &change {
+        apply_settings_change(&app, &state, change).await?;
+    }
+
+    apply_proxy_password_update(&state, change.as_ref(), proxy_password).await?;
+
+    Ok(state.settings.get())
+}
+
+// The endpoints, the proxy, the trust config and the enrollment URL decide
+// where the auth tokens are sent. A change of them is confirmed natively, so a
+// script in the webview can not point a link at its own server.
+async fn confirm_token_destination_change(
+    state: &AppData,
+    updated_settings: &Settings,
+) -> Result<(), String> {
+    if !changes_token_destination(&state.settings.get(), updated_settings) {
+        return Ok(());
+    }
+
+    let question = "Изменить адрес сервера, прокси или доверенные сертификаты? \
+                    Токены будут отправляться по новым настройкам."
+        .to_string();
+
+    if !confirm_natively(state, question, "Изменить").await {
+        return Err("ChangeCancelled".to_string());
+    }
+
+    Ok(())
+}
+
+// Added profiles have no token yet, only the endpoints of existing ones count
+fn changes_token_destination(settings: &Settings, updated_settings: &Settings) -> bool {
+    let profile_endpoints_changed = updated_settings.profiles.iter().any(|profile| {
+        settings.profiles.iter().any(|previous_profile| {
+            previous_profile.id == profile.id
+                && (previous_profile.endpoint != profile.endpoint
+                    || previous_profile.endpoints != profile.endpoints)
+        })
+    });
+
+    updated_settings.endpoint != settings.endpoint
+        || updated_settings.endpoints != settings.endpoints
+        || updated_settings.server.proxy != settings.server.proxy
+        || updated_settings.server.trust != settings.server.trust
+        || updated_settings.enrollment != settings.enrollment
+        || profile_endpoints_changed
+}
+
+// Refuses settings the managed policy forbids before they are stored. An idle
+// timeout or a schedule stored before the policy came does not block other
+// changes, only turning one on or changing it does.
+fn check_settings_policy(state: &AppData, updated_settings: &Settings) -> Result<(), String> {
+    let settings = state.settings.get();
+
+    state
+        .policy
+        .check_endpoints_change(
+            updated_settings.endpoint != settings.endpoint
+                || updated_settings.endpoints != settings.endpoints
+                || updated_settings.server.proxy != settings.server.proxy
+                || updated_settings.server.trust != settings.server.trust,
+        )
+        .map_err(|e| e.to_string())?;
+
+    let idle_timeout_minutes = updated_settings.server.idle_timeout_minutes;
+    let schedule = &updated_settings.schedule;
+
+    state
+        .policy
+        .check_auto_disconnect_change(
+            idle_timeout_minutes.is_some()
+                && idle_timeout_minutes != settings.server.idle_timeout_minutes
+                || schedule.enabled && *schedule != settings.schedule,
+        )
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_managed_policy(state: State<'_, AppData>) -> Result<ManagedPolicyStatus, String> {
+    Ok(ManagedPolicyStatus {
+        source: state.policy_source.clone(),
+        locked_settings: state.policy.locked_settings(),
+        policy: state.policy.clone(),
+        error: state.policy_error.clone(),
+    })
+}
+
+// Applies what changed and tells the windows with a `settings-changed` event
+async fn apply_settings_change<R: tauri::Runtime>(
+    app: &AppHandle<R>,
+    state: &State<'_, AppData>,
+    change: &SettingsChange,
+) -> Result<(), String> {
+    if change.current.autostart != change.previous.autostart {
+        apply_autostart(app, change.current.autostart);
+    }
+
+    if change.current.bandwidth != change.previous.bandwidth {
+        for link in state.all_links() {
+            link.bandwidth_limiter.set_limit(&change.current.bandwidth);
+        }
+    }
+
+    if change.current.metrics != change.previous.metrics {
+        restart_metrics_server(app, state, &change.current.metrics).await?;
+    }
+
+    if change.current.shortcuts != change.previous.shortcuts {
+        register_global_shortcuts(app, state, &change.current.shortcuts)?;
+    }
+
+    if let Err(e) = app.emit("settings-changed", &change.current) {
+        warn!("Failed to emit settings change: {}", e);
+    }
+
+    if change.current.profiles != change.previous.profiles {
+        sync_profile_links(app, state, change).await?;
+    }
+
+    // The server config applies to all links
+    for link in state.all_links() {
+        let endpoint_changed = if link.profile_id == DEFAULT_PROFILE_ID {
+            change.current.endpoint != change.previous.endpoint
+                || change.current.endpoints != change.previous.endpoints
+        } else {
+            false
+        };
+
+        if change.current.server != change.previous.server || endpoint_changed {
+            reinitialize_secure_link_client(&link)
+                .await
+                .map_err(|e| format!("{:?}", e))?;
+        }
+    }
+
+    Ok(())
+}
+
+// Creates links for added profiles and stops and drops the ones of removed
+// profiles. Links of profiles with a new endpoint get a new client.
+async fn sync_profile_links<R: tauri::Runtime>(
+    app: &AppHandle<R>,
+    state: &State<'_, AppData>,
+    change: &SettingsChange,
+) -> Result<(), String> {
+    for previous_profile in &change.previous.profiles {
+        if change
+            .current
+            .profiles
+            .iter()
+            .any(|profile| profile.id == previous_profile.id)
+        {
+            continue;
+        }
+
+        let Some(link) = state.link(&previous_profile.id) else {
+            continue;
+        };
+
+        // Stopped before it is removed, so the session still gets recorded
+        if let Err(e) = link.supervisor.stop().await {
+            warn!("Failed to stop link {}: {}", link.profile_id, e);
+        }
+
+        state.links.lock().unwrap().remove(&previous_profile.id);
+    }
+
+    for profile in &change.current.profiles {
+        let previous_profile = change
+            .previous
+            .profiles
+            .iter()
+            .find(|previous_profile| previous_profile.id == profile.id);
+
+        match (previous_profile, state.link(&profile.id)) {
+            (Some(previous_profile), Some(link)) => {
+                if previous_profile.endpoint != profile.endpoint
+                    || previous_profile.endpoints != profile.endpoints
+                {
+                    reinitialize_secure_link_client(&link)
+                        .await
+                        .map_err(|e| format!("{:?}", e))?;
+                }
+            }
+            _ => {
+                let link = spawn_secure_link(
+                    app,
+                    &state.app_data_dir,
+                    &profile.id,
+                    &change.current.bandwidth,
+                )
+                .map_err(|e| e.to_string())?;
+
+                state.links.lock().unwrap().insert(profile.id.clone(), link);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn apply_autostart<R: tauri::Runtime>(app: &AppHandle<R>, enabled: bool) {
+    // Not registered in tests
+    let Some(autolaunch) = app.try_state::<tauri_plugin_autostart::AutoLaunchManager>() else {
+        return;
+    };
+
+    let result = if enabled {
+        autolaunch.enable()
+    } else {
+        autolaunch.disable()
+    };
+
+    if let Err(e) = result {
+        warn!("Failed to update autostart: {}", e);
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BandwidthStatus {
+    limit: BandwidthLimit,
+    // `None` when the backend does not count traffic
+    throughput: Option<Throughput>,
+}
+
+// Throughput is averaged between calls, the UI polls this about once a second
+#[tauri::command]
+async fn get_bandwidth_status(
+    state: State<'_, AppData>,
+    profile_id: Option<String>,
+) -> Result<BandwidthStatus, String> {
+    let profile_id = profile_id.as_deref().unwrap_or(DEFAULT_PROFILE_ID);
+    let link = state
+        .link(profile_id)
+        .ok_or_else(|| unknown_profile_error(profile_id))?;
+
+    let client_status = get_client_status(&link).await;
+
+    let throughput = link
+        .throughput_meter
+        .lock()
+        .unwrap()
+        .observe(client_status.traffic, Instant::now());
+
+    Ok(BandwidthStatus {
+        limit: state.settings.get().bandwidth,
+        throughput: client_status.traffic.map(|_| throughput),
+    })
+}
+
+async fn metrics_snapshot(link: &SecureLink) -> MetricsSnapshot {
+    let client_status = get_client_status(link).await;
+
+    let auth_token_expires_at = load_auth_token(link)
+        .ok()
+        .flatten()
+        .and_then(|auth_token| auth_token_expiry::auth_token_expires_at(auth_token.expose()));
+
+    MetricsSnapshot {
+        profile_id: link.profile_id.clone(),
+        state: client_status.state,
+        counters: link.metrics.counters(),
+        traffic: client_status.traffic,
+        auth_token_expires_at,
+    }
+}
+
+async fn render_all_metrics(state: &State<'_, AppData>) -> String {
+    let mut snapshots = Vec::new();
+
+    for link in state.all_links() {
+        snapshots.push(metrics_snapshot(&link).await);
+    }
+
+    metrics::render_metrics(&snapshots)
+}
+
+// Stops the running metrics server and starts one for `metrics_settings` if
+// enabled. Fails when the port is taken.
+async fn restart_metrics_server<R: tauri::Runtime>(
+    app: &AppHandle<R>,
+    state: &State<'_, AppData>,
+    metrics_settings: &MetricsSettings,
+) -> Result<(), String> {
+    if let Some(metrics_server) = state.metrics_server.lock().unwrap().take() {
+        metrics_server.abort();
+    }
+
+    if !metrics_settings.enabled {
+        return Ok(());
+    }
+
+    let listener = metrics::bind_metrics_listener(metrics_settings.port)
+        .await
+        .map_err(|e| {
+            format!(
+                "Failed to listen on metrics port {}: {}",
+                metrics_settings.port, e
+            )
+        })?;
+
+    let app = app.clone();
+    let metrics_server =
+        tauri::async_runtime::spawn(metrics::run_metrics_server(listener, move || {
+            let app = app.clone();
+            async move { render_all_metrics(&app.state::<AppData>()).await }
+        }));
+
+    *state.metrics_server.lock().unwrap() = Some(metrics_server);
+
+    Ok(())
+}
+
+const UPDATE_CHECK_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+// The settings override the manifest URL the app was built with
+fn update_manifest_url(state: &State<'_, AppData>) -> Option<String> {
+    state
+        .settings
+        .get()
+        .updates
+        .manifest_url
+        .or_else(|| option_env!("SECURE_LINK_UPDATE_MANIFEST_URL").map(str::to_string))
+}
+
+// Looks for a newer release and downloads it. A downloaded update is kept
+// until it is installed on restart.
+async fn check_for_update(app: &AppHandle) -> UpdateStatus {
+    let state = app.state::<AppData>();
+
+    let (Some(updater), Some(manifest_url)) = (&state.updater, update_manifest_url(&state)) else {
+        return UpdateStatus::NotConfigured;
+    };
+
+    {
+        let mut update_status = state.update_status.lock().unwrap();
+
+        if matches!(
+            *update_status,
+            UpdateStatus::Checking | UpdateStatus::Downloading { .. } | UpdateStatus::Ready { .. }
+        ) {
+            return update_status.clone();
+        }
+
+        *update_status = UpdateStatus::Checking;
+    }
+
+    let check_result = updater
+        .check(
+            &manifest_url,
+            &app.package_info().version,
+            &updater::current_platform(),
+        )
+        .await;
+
+    let update_status = match check_result {
+        Ok(None) => UpdateStatus::UpToDate,
+        Ok(Some(update))
+            if state.settings.get().updates.skipped_version.as_deref()
+                == Some(update.version.to_string().as_str()) =>
+        {
+            UpdateStatus::RolledBack {
+                version: update.version.to_string(),
+            }
+        }
+        Ok(Some(update)) => {
+            *state.update_status.lock().unwrap() = UpdateStatus::Downloading {
+                version: update.version.to_string(),
+            };
+
+            match updater.download(&update).await {
+                Ok(downloaded_update) => {
+                    *state.downloaded_update.lock().unwrap() = Some(downloaded_update);
+
+                    show_notification(
+                        app,
+                        &format!(
+                            "Загружена версия {}, перезапустите приложение для обновления",
+                            update.version
+                        ),
+                    );
+
+                    UpdateStatus::Ready {
+                        version: update.version.to_string(),
+                        notes: update.notes,
+                    }
+                }
+                Err(e) => UpdateStatus::Failed {
+                    error: e.to_string(),
+                },
+            }
+        }
+        Err(e) => UpdateStatus::Failed {
+            error: e.to_string(),
+        },
+    };
+
+    if let UpdateStatus::Failed { error } = &update_status {
+        warn!("Update check failed: {}", error);
+    }
+
+    *state.update_status.lock().unwrap() = update_status.clone();
+
+    update_status
+}
+
+async fn update_check_task(app: AppHandle) {
+    let mut interval = tokio::time::interval(UPDATE_CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        check_for_update(&app).await;
+    }
+}
+
+#[tauri::command]
+async fn get_update_status(state: State<'_, AppData>) -> Result<UpdateStatus, String> {
+    Ok(state.update_status.lock().unwrap().clone())
+}
+
+#[tauri::command]
+async fn check_for_updates(app: AppHandle) -> Result<UpdateStatus, String> {
+    Ok(check_for_update(&app).await)
+}
+
+// Puts the downloaded version in place and restarts into it
+#[tauri::command]
+async fn restart_to_update(app: AppHandle, state: State<'_, AppData>) -> Result<(), String> {
+    let downloaded_update = state
+        .downloaded_update
+        .lock()
+        .unwrap()
+        .clone()
+        .ok_or_else(|| updater::UpdateError::NothingToInstall.to_string())?;
+
+    let exe_path = std::env::current_exe().map_err(|e| e.to_string())?;
+
+    updater::install_update(
+        &downloaded_update,
+        &exe_path,
+        &state.app_data_dir,
+        &app.package_info().version,
+    )
+    .map_err(|e| e.to_string())?;
+
+    app.restart()
+}
+
+// The server config without the proxy password, only whether one is stored
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ServerConfigView {
+    #[serde(flatten)]
+    server_config: ServerConfig,
+    proxy_password_set: bool,
+}
+
+#[tauri::command]
+async fn get_server_config(state: State<'_, AppData>) -> Result<ServerConfigView, String> {
+    let proxy_password = load_proxy_password(&state.app_data_dir).map_err(|e| e.to_string())?;
+
+    Ok(ServerConfigView {
+        server_config: state.settings.get().server,
+        proxy_password_set: proxy_password.is_some(),
+    })
+}
+
+// A proxy password in `server_config` replaces the stored one, an empty one
+// removes it and without one the stored password is kept
+#[tauri::command]
+async fn update_server_config<R: tauri::Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, AppData>,
+    server_config: ServerConfig,
+) -> Result<(), String> {
+    let mut server_config = server_config;
+    let proxy_password = server_config.proxy.take_password();
+
+    let mut updated_settings = state.settings.get();
+    updated_settings.server = server_config.clone();
+    check_settings_policy(&state, &updated_settings)?;
+    confirm_token_destination_change(&state, &updated_settings).await?;
+
+    let change = state
+        .settings
+        .update_with(|settings| settings.server = server_config)
+        .map_err(|e| e.to_string())?;
+
+    if let Some(change) = &change {
+        apply_settings_change(&app, &state, change).await?;
+    }
+
+    apply_proxy_password_update(&state, change.as_ref(), proxy_password).await
+}
+
+// Stores the proxy password of an update. The links get a new client unless
+// the settings change already gave them one.
+async fn apply_proxy_password_update(
+    state: &State<'_, AppData>,
+    change: Option<&SettingsChange>,
+    proxy_password: Option<String>,
+) -> Result<(), String> {
+    let Some(proxy_password) = proxy_password else {
+        return Ok(());
+    };
+
+    let stored_proxy_password =
+        load_proxy_password(&state.app_data_dir).map_err(|e| e.to_string())?;
+
+    if stored_proxy_password.unwrap_or_default() == proxy_password {
+        return Ok(());
+    }
+
+    let has_username = state
+        .settings
+        .get()
+        .server
+        .proxy
+        .server()
+        .is_some_and(|proxy_server| proxy_server.username.is_some());
+
+    if !proxy_password.is_empty() && !has_username {
+        return Err(server_config::ServerConfigError::ProxyPasswordWithoutUsername.to_string());
+    }
+
+    store_proxy_password(&state.app_data_dir, &proxy_password).map_err(|e| e.to_string())?;
+
+    info!("Proxy password replaced");
+
+    if change.is_some_and(|change| change.current.server != change.previous.server) {
+        return Ok(());
+    }
+
+    for link in state.all_links() {
+        reinitialize_secure_link_client(&link)
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+    }
+
+    Ok(())
+}
+
+// The server config of new clients, with the stored proxy password
+fn client_server_config(state: &AppData) -> ServerConfig {
+    let mut server_config = state.settings.get().server;
+
+    let proxy_password = load_proxy_password(&state.app_data_dir).unwrap_or_else(|e| {
+        warn!("Failed to load the proxy password: {}", e);
+        None
+    });
+
+    server_config.proxy = server_config.proxy.with_password(proxy_password);
+
+    server_config
+}
+
+#[tauri::command]
+async fn get_schedule_config(state: State<'_, AppData>) -> Result<ScheduleConfig, String> {
+    Ok(state.settings.get().schedule)
+}
+
+#[tauri::command]
+async fn update_schedule_config<R: tauri::Runtime>(
+    app: AppHandle<R>,
+    state: State<'_, AppData>,
+    schedule_config: ScheduleConfig,
+) -> Result<(), String> {
+    let mut updated_settings = state.settings.get();
+    updated_settings.schedule = schedule_config.clone();
+    check_settings_policy(&state, &updated_settings)?;
+
+    let change = state
+        .settings
+        .update_with(|settings| settings.schedule = schedule_config)
+        .map_err(|e| e.to_string())?;
+
+    if let Some(change) = change {
+        apply_settings_change(&app, &state, &change).await?;
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_schedule_status(state: State<'_, AppData>) -> Result<ScheduleStatus, String> {
+    let schedule = current_connection_schedule(&state).map_err(|e| e.to_string())?;
+
+    Ok(schedule.status_at(chrono::Utc::now()))
+}
+
+fn current_connection_schedule(
+    state: &State<'_, AppData>,
+) -> Result<ConnectionSchedule, connection_schedule::ScheduleError> {
+    ConnectionSchedule::from_config(&state.settings.get().schedule)
+}
+
+// A broken schedule never locks the user out
+fn is_connect_allowed_by_schedule(state: &State<'_, AppData>) -> bool {
+    match current_connection_schedule(state) {
+        Ok(schedule) => schedule.is_allowed_at(chrono::Utc::now()),
+        Err(e) => {
+            warn!("Ignoring invalid connection schedule: {}", e);
+            true
+        }
+    }
+}
+
+// Connects and disconnects the default link at the edges of the schedule
+// windows, and keeps the tray schedule line current. Turning the schedule on or
+// off is not an edge.
+async fn connection_schedule_task(app: AppHandle) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    let mut was_allowed: Option<bool> = None;
+
+    loop {
+        interval.tick().await;
+
+        let state = app.state::<AppData>();
+
+        let schedule = match current_connection_schedule(&state) {
+            Ok(schedule) => schedule,
+            Err(_) => {
+                was_allowed = None;
+                continue;
+            }
+        };
+
+        let status = schedule.status_at(chrono::Utc::now());
+
+        if let Err(e) = update_tray_schedule_item(&app, &tray_schedule_text(&schedule, &status)) {
+            warn!("Failed to update tray schedule item: {}", e);
+        }
+
+        if !status.enabled {
+            was_allowed = None;
+            continue;
+        }
+
+        let edge_result = match was_allowed {
+            Some(false) if status.allowed => tray_start(&state, &state.default_link()).await,
+            Some(true) if !status.allowed => tray_stop(&state, &state.default_link()).await,
+            _ => Ok(()),
+        };
+
+        if let Err(e) = edge_result {
+            warn!("Failed to follow connection schedule: {}", e);
+        }
+
+        was_allowed = Some(status.allowed);
+    }
+}
+
+fn tray_schedule_text(schedule: &ConnectionSchedule, status: &ScheduleStatus) -> String {
+    if !status.enabled {
+        return "Расписание не задано".to_string();
+    }
+
+    let window_end = status.current_window.as_ref().and_then(|window| window.end);
+    let next_window_start = status.next_window.as_ref().and_then(|window| window.start);
+
+    match (status.allowed, window_end, next_window_start) {
+        (true, Some(end), _) => format!("Окно подключения до {}", schedule.format_local_time(end)),
+        (true, None, _) => "Окно подключения открыто".to_string(),
+        (false, _, Some(start)) => {
+            format!("Следующее окно: {}", schedule.format_local_time(start))
+        }
+        (false, _, None) => "Нет окон подключения".to_string(),
+    }
+}
+
+// Disconnects running sessions once no traffic passed for the configured idle
+// timeout. The user is warned shortly before and can keep the links up from
+// the tray or the window.
+async fn idle_disconnect_task(app: AppHandle) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        interval.tick().await;
+
+        let state = app.state::<AppData>();
+
+        for link in state.all_links() {
+            check_idle_link(&app, &state, &link).await;
+        }
+
+        if let Err(e) = update_tray_keep_connected_item(&app) {
+            warn!("Failed to update tray keep connected item: {}", e);
+        }
+    }
+}
+
+async fn check_idle_link(app: &AppHandle, state: &State<'_, AppData>, link: &SecureLink) {
+    let client_status = get_client_status(link).await;
+
+    // A link the managed policy keeps up is never idle
+    let check = if client_status.state == SecureLinkClientState::Running
+        && state.policy.check_disconnect().is_ok()
+    {
+        let idle_timeout = state.settings.get().server.idle_timeout();
+
+        link.idle_monitor.lock().unwrap().observe(
+            client_status.traffic,
+            idle_timeout,
+            Instant::now(),
+        )
+    } else {
+        link.idle_monitor.lock().unwrap().reset();
+        IdleCheck::Active
+    };
+
+    let previous_check = std::mem::replace(&mut *link.idle_check.lock().unwrap(), check.clone());
+
+    let name = profile_name(state, &link.profile_id);
+
+    match check {
+        IdleCheck::Warning { disconnect_in_ms } => {
+            // Desktop notifications can not carry actions or report clicks,
+            // so the window with the keep connected button comes up instead
+            if !matches!(previous_check, IdleCheck::Warning { .. }) {
+                show_main_window(app);
+                show_notification(
+                    app,
+                    &format!(
+                        "{}: нет трафика. Соединение будет разорвано через {} с. Нажмите «Оставаться подключённым» в окне Secure Link, чтобы сохранить его.",
+                        name,
+                        disconnect_in_ms.div_ceil(1000)
+                    ),
+                );
+            }
+        }
+        IdleCheck::TimedOut => {
+            match link
+                .supervisor
+                .stop_with_reason(SecureLinkClientStopReason::IdleTimeout)
+                .await
+            {
+                Ok(_) => show_notification(
+                    app,
+                    &format!("{}: соединение разорвано из-за отсутствия трафика", name),
+                ),
+                Err(e) => warn!("Failed to stop idle secure link {}: {}", link.profile_id, e),
+            }
+
+            link.idle_monitor.lock().unwrap().reset();
+            *link.idle_check.lock().unwrap() = IdleCheck::Active;
+        }
+        IdleCheck::Active => {}
+    }
+}
+
+fn show_notification(app: &AppHandle, body: &str) {
+    if !app.state::<AppData>().settings.get().notifications {
+        return;
+    }
+
+    if let Err(e) = app
+        .notification()
+        .builder()
+        .title("Secure Link")
+        .body(body)
+        .show()
+    {
+        warn!("Failed to show notification: {}", e);
+    }
+}
+
+// Replaces the registered shortcuts with the ones of `shortcut_settings`.
+// Every shortcut that can be registered is, the first problem is returned.
+fn register_global_shortcuts<R: tauri::Runtime>(
+    app: &AppHandle<R>,
+    state: &State<'_, AppData>,
+    shortcut_settings: &ShortcutSettings,
+) -> Result<(), String> {
+    // Not registered in tests
+    let Some(global_shortcut) = app.try_state::<GlobalShortcut<R>>() else {
+        return Ok(());
+    };
+
+    let previous_shortcuts = std::mem::take(&mut *state.global_shortcuts.lock().unwrap());
+
+    for (_, shortcut) in previous_shortcuts {
+        if let Err(e) = global_shortcut.unregister(shortcut) {
+            warn!("Failed to unregister shortcut {}: {}", shortcut, e);
+        }
+    }
 
-    if let Some(secure_link_client_locked) = maybe_client {
-        let status = secure_link_client_locked
-            .status()
-            .await
-            .map_err(|e| format!("{:?}", e))?;
+    let (shortcuts, mut shortcut_status) =
+        global_shortcuts::parse_shortcut_settings(shortcut_settings);
+    let mut registered_shortcuts = Vec::new();
 
-        match status {
-            SecureLinkClientState::Running => Ok("Running".to_string()),
-            SecureLinkClientState::Pending => Ok("Pending".to_string()),
-            SecureLinkClientState::Stopped => Ok("Stopped".to_string()),
+    for (action, shortcut) in shortcuts {
+        match global_shortcut.register(shortcut) {
+            Ok(()) => registered_shortcuts.push((action, shortcut)),
+            Err(e) => {
+                *shortcut_status.registration_mut(action) = ShortcutRegistration::Conflict {
+                    error: format!("{}: {}", shortcut, e),
+                }
+            }
         }
-    } else {
-        Ok("Stopped".to_string())
+    }
+
+    *state.global_shortcuts.lock().unwrap() = registered_shortcuts;
+    *state.shortcut_status.lock().unwrap() = shortcut_status.clone();
+
+    match shortcut_status.error() {
+        Some(error) => Err(error),
+        None => Ok(()),
     }
 }
 
-#[cfg(feature = "secure-link-windows-service-client")]
 #[tauri::command]
-async fn get_service_log(state: State<'_, AppData>) -> Result<String, String> {
-    let log_file_path = state.secure_link_service_log_file_path.clone();
+async fn get_shortcut_status(state: State<'_, AppData>) -> Result<ShortcutStatus, String> {
+    Ok(state.shortcut_status.lock().unwrap().clone())
+}
 
-    if !log_file_path.exists() {
-        return Ok("".to_string());
+// Shortcuts act on the default link the same way the tray does
+async fn run_shortcut_action(app: AppHandle, action: ShortcutAction) {
+    match action {
+        ShortcutAction::ToggleLink => {
+            let state = app.state::<AppData>();
+            let link = state.default_link();
+
+            let body = match get_client_status(&link).await.state {
+                SecureLinkClientState::Stopped => match tray_start(&state, &link).await {
+                    Ok(()) => "Подключено".to_string(),
+                    Err(e) => format!("Не удалось подключиться: {}", e),
+                },
+                SecureLinkClientState::Pending
+                | SecureLinkClientState::Running
+                | SecureLinkClientState::Stopping => match tray_stop(&state, &link).await {
+                    Ok(()) => "Отключено".to_string(),
+                    Err(e) => format!("Не удалось отключиться: {}", e),
+                },
+            };
+
+            show_notification(&app, &body);
+        }
+        ShortcutAction::ShowWindow => show_main_window(&app),
     }
+}
 
-    match std::fs::read_to_string(log_file_path) {
-        Ok(content) => Ok(content),
-        Err(e) => Err(format!("Не удалось прочитать файл логов: {}", e)),
+fn show_main_window<R: tauri::Runtime>(app: &AppHandle<R>) {
+    if let Some(window) = app.get_webview_window("main") {
+        if let Err(e) = window
+            .unminimize()
+            .and_then(|_| window.show())
+            .and_then(|_| window.set_focus())
+        {
+            warn!("Failed to show the window: {}", e);
+        }
     }
 }
 
-async fn ensure_secure_link_client_created(
-    state: &State<'_, AppData>,
-) -> Result<Option<Arc<dyn SecureLinkClient>>, Box<dyn std::error::Error>> {
-    let auth_token = match load_auth_token(state)? {
-        None => return Ok(None),
-        Some(auth_token) => auth_token,
-    };
+// Restarts the idle time of the running session
+fn keep_connected(link: &SecureLink) {
+    link.idle_monitor.lock().unwrap().keep_alive(Instant::now());
+    *link.idle_check.lock().unwrap() = IdleCheck::Active;
+}
 
-    let secure_link_client = {
-        let mut secure_link_client_locked = state.secure_link_client.lock().unwrap();
-
-        match &mut *secure_link_client_locked {
-            Some(secure_link_client) => secure_link_client.clone(),
-            None => {
-                #[cfg(feature = "secure-link-embedded-client")]
-                let client = {
-                    secure_link_embedded_client::SecureLinkEmbeddedClient::new(
-                        &auth_token,
-                        &state.secure_link_server_host,
-                        state.secure_link_server_port,
-                    )
-                };
+// Keeps all links up unless `profile_id` is given, like the tray item
+#[tauri::command]
+async fn keep_secure_link_connected(
+    state: State<'_, AppData>,
+    profile_id: Option<String>,
+) -> Result<(), String> {
+    let links = match profile_id {
+        Some(profile_id) => vec![state
+            .link(&profile_id)
+            .ok_or_else(|| unknown_profile_error(&profile_id))?],
+        None => state.all_links(),
+    };
 
-                #[cfg(feature = "secure-link-windows-service-client")]
-                let client = {
-                    secure_link_windows_service_client::SecureLinkWindowsServiceClient::new(
-                        &state.secure_link_server_host,
-                        state.secure_link_server_port,
-                        &auth_token,
-                        &state.secure_link_service_log_file_path.to_str().unwrap(),
-                    )
-                };
+    for link in links {
+        keep_connected(&link);
+    }
 
-                let client_arc = Arc::new(client);
-                *secure_link_client_locked = Some(client_arc.clone());
-                client_arc
-            }
-        }
-    };
+    Ok(())
+}
 
-    Ok(Some(secure_link_client))
+// The warning of the link that is disconnected first, if any link is idle
+#[tauri::command]
+async fn get_idle_status(state: State<'_, AppData>) -> Result<IdleCheck, String> {
+    Ok(state
+        .all_links()
+        .iter()
+        .map(|link| link.idle_check.lock().unwrap().clone())
+        .filter_map(|idle_check| match idle_check {
+            IdleCheck::Warning { disconnect_in_ms } => Some(disconnect_in_ms),
+            _ => None,
+        })
+        .min()
+        .map_or(IdleCheck::Active, |disconnect_in_ms| IdleCheck::Warning {
+            disconnect_in_ms,
+        }))
 }
 
-async fn reinitialize_secure_link_client(
-    state: &State<'_, AppData>,
-) -> Result<Option<Arc<dyn SecureLinkClient>>, Box<dyn std::error::Error>> {
-    let current_client = { state.secure_link_client.lock().unwrap().clone() };
+// Only metadata, the token itself never reaches the webview this way
+#[tauri::command]
+async fn get_auth_token_info(state: State<'_, AppData>) -> Result<AuthTokenInfo, String> {
+    let link = state.default_link();
+    let auth_token = load_auth_token(&link).map_err(|e| e.to_string())?;
+    let stored_at = load_auth_token_stored_at(&link).map_err(|e| e.to_string())?;
+
+    Ok(AuthTokenInfo::new(auth_token.as_ref(), stored_at))
+}
 
-    if let Some(client) = current_client {
-        client.stop().await?;
+// Returns the token only after the user confirmed a native dialog. Granted
+// separately from the rest of the commands, see capabilities/default.json.
+#[tauri::command]
+async fn reveal_auth_token(state: State<'_, AppData>) -> Result<String, String> {
+    let auth_token = load_auth_token(&state.default_link())
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "NoAuthToken".to_string())?;
+
+    let question = format!(
+        "Показать токен {} в окне приложения?",
+        auth_token.fingerprint()
+    );
+
+    if !confirm_natively(&state, question, "Показать").await {
+        return Err("RevealCancelled".to_string());
     }
 
-    {
-        *state.secure_link_client.lock().unwrap() = None;
-    };
+    warn!("Auth token {} revealed in the window", auth_token);
 
-    Ok(ensure_secure_link_client_created(state).await?)
+    Ok(auth_token.expose().to_string())
 }
 
-#[tauri::command]
-async fn start(state: State<'_, AppData>) -> Result<(), String> {
-    let secure_link_client = ensure_secure_link_client_created(&state)
+fn dialog_native_confirm(app: AppHandle) -> NativeConfirm {
+    Box::new(move |question, confirm_label| {
+        let (confirmed_sender, confirmed_receiver) = tokio::sync::oneshot::channel();
+
+        app.dialog()
+            .message(question)
+            .title("Secure Link")
+            .kind(MessageDialogKind::Warning)
+            .buttons(MessageDialogButtons::OkCancelCustom(
+                confirm_label,
+                "Отмена".to_string(),
+            ))
+            .show(move |confirmed| {
+                let _ = confirmed_sender.send(confirmed);
+            });
+
+        confirmed_receiver
+    })
+}
+
+async fn confirm_natively(state: &AppData, question: String, confirm_label: &str) -> bool {
+    (state.native_confirm)(question, confirm_label.to_string())
         .await
-        .map_err(|e| format!("{:?}", e))?;
+        .unwrap_or(false)
+}
 
-    if let Some(secure_link_client) = secure_link_client {
-        match secure_link_client.start().await {
-            Ok(()) => Ok(()),
-            Err(SecureLinkClientError::UnauthorizedError) => Err(format!("UnauthorizedError")),
-            Err(err) => Err(format!("{:?}", err)),
+// Runs actions passed on the command line, either at startup or forwarded
+// from a second launch by the single instance plugin
+async fn run_command_line_actions(app: AppHandle, actions: Vec<CommandLineAction>) {
+    for action in actions {
+        if let Err(e) = run_command_line_action(&app, &action).await {
+            warn!("Failed to run command line action {:?}: {}", action, e);
         }
-    } else {
-        Err(format!("No auth token"))
     }
 }
 
-#[tauri::command]
-async fn stop(state: State<'_, AppData>) -> Result<(), String> {
-    let maybe_client_clone = {
-        state
-            .secure_link_client
-            .lock()
-            .unwrap()
-            .as_ref()
-            .map(|client| client.clone())
-    };
+async fn run_command_line_action(
+    app: &AppHandle,
+    action: &CommandLineAction,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let state = app.state::<AppData>();
+    // Command line actions control the default link
+    let link = state.default_link();
+
+    match action {
+        CommandLineAction::Connect => tray_start(&state, &link).await,
+        CommandLineAction::Disconnect => tray_stop(&state, &link).await,
+        CommandLineAction::Toggle => match get_client_status(&link).await.state {
+            SecureLinkClientState::Stopped => tray_start(&state, &link).await,
+            SecureLinkClientState::Pending
+            | SecureLinkClientState::Running
+            | SecureLinkClientState::Stopping => tray_stop(&state, &link).await,
+        },
+        CommandLineAction::SetTokenFile(path) => {
+            let mut contents = std::fs::read_to_string(path)?;
+            let auth_token = AuthToken::new(contents.trim().to_string());
+            contents.zeroize();
+
+            if auth_token.expose().is_empty() {
+                return Err(format!("Token file {} is empty", path.display()).into());
+            }
 
-    if let Some(secure_link_client) = maybe_client_clone {
-        secure_link_client
-            .stop()
-            .await
-            .map_err(|e| format!("{:?}", e))?;
+            state.policy.check_auth_token_editing()?;
+
+            replace_auth_token(&link, auth_token).await
+        }
+        CommandLineAction::Show => {
+            app.get_webview_window("main")
+                .ok_or("no main window")?
+                .show()?;
+            Ok(())
+        }
+        CommandLineAction::Quit => Ok(quit(app)?),
     }
+}
+
+// Quitting takes the links down with the app, so it is a disconnect as far as
+// the managed policy is concerned
+fn quit(app: &AppHandle) -> Result<(), PolicyViolation> {
+    app.state::<AppData>().policy.check_disconnect()?;
+    app.exit(0);
 
     Ok(())
 }
 
-// Separate tray-specific start function
-async fn tray_start(state: &State<'_, AppData>) -> Result<(), Box<dyn std::error::Error>> {
-    let secure_link_client = ensure_secure_link_client_created(state).await?;
-
-    if let Some(secure_link_client) = secure_link_client {
-        match secure_link_client.start().await {
-            Ok(()) => Ok(()),
-            Err(SecureLinkClientError::UnauthorizedError) => {
-                eprintln!("Unauthorized error when starting from tray");
-                Err("Unauthorized error".into())
-            }
-            Err(err) => {
-                eprintln!("Error starting from tray: {:?}", err);
-                Err(err.into())
-            }
-        }
-    } else {
-        eprintln!("No auth token available for tray start");
-        Err("No auth token".into())
+// Get current client status for tray updates
+async fn get_client_status(link: &SecureLink) -> SecureLinkClientStatus {
+    link.supervisor
+        .status()
+        .await
+        .unwrap_or(SecureLinkClientStatus {
+            state: SecureLinkClientState::Stopped,
+            last_stop_reason: None,
+            traffic: None,
+        })
+}
+
+// Which of the connect/disconnect tray items are enabled for a client state
+fn tray_menu_enabled_states(client_state: &SecureLinkClientState) -> (bool, bool) {
+    match client_state {
+        SecureLinkClientState::Stopped => (true, false),
+        SecureLinkClientState::Pending | SecureLinkClientState::Running => (false, true),
+        SecureLinkClientState::Stopping => (false, false),
     }
 }
 
-// Separate tray-specific stop function
-async fn tray_stop(state: &State<'_, AppData>) -> Result<(), Box<dyn std::error::Error>> {
-    let maybe_client_clone = {
-        state
-            .secure_link_client
-            .lock()
-            .unwrap()
-            .as_ref()
-            .map(|client| client.clone())
-    };
+fn tray_link_state_text(client_state: &SecureLinkClientState) -> &'static str {
+    match client_state {
+        SecureLinkClientState::Running => "подключено",
+        SecureLinkClientState::Pending => "подключение…",
+        SecureLinkClientState::Stopping => "отключение…",
+        SecureLinkClientState::Stopped => "отключено",
+    }
+}
 
-    if let Some(secure_link_client) = maybe_client_clone {
-        secure_link_client.stop().await?;
+// The default link is controlled from the top level, every profile gets a
+// submenu titled with its state. Profile items have ids like
+// `connect:<profile id>`.
+fn build_tray_menu<M: Manager<tauri::Wry>>(
+    manager: &M,
+    profiles: &[LinkProfile],
+) -> tauri::Result<(Menu<tauri::Wry>, TrayMenuItems)> {
+    let show_item = MenuItem::with_id(manager, "show", "Показать Secure Link", true, None::<&str>)?;
+    let schedule_item = MenuItem::with_id(
+        manager,
+        "schedule",
+        "Расписание не задано",
+        false,
+        None::<&str>,
+    )?;
+    let connect_item = MenuItem::with_id(manager, "connect", "Подключиться", false, None::<&str>)?;
+    let disconnect_item =
+        MenuItem::with_id(manager, "disconnect", "Отключиться", false, None::<&str>)?;
+    let keep_connected_item = MenuItem::with_id(
+        manager,
+        "keep_connected",
+        "Оставаться подключённым",
+        false,
+        None::<&str>,
+    )?;
+    let exit_item = MenuItem::with_id(manager, "exit", "Закрыть Secure Link", true, None::<&str>)?;
+
+    let menu = Menu::with_items(
+        manager,
+        &[
+            &show_item,
+            &schedule_item,
+            &connect_item,
+            &disconnect_item,
+            &keep_connected_item,
+        ],
+    )?;
+
+    let mut profile_items = Vec::new();
+
+    for profile in profiles {
+        let connect_item = MenuItem::with_id(
+            manager,
+            format!("connect:{}", profile.id),
+            "Подключиться",
+            false,
+            None::<&str>,
+        )?;
+        let disconnect_item = MenuItem::with_id(
+            manager,
+            format!("disconnect:{}", profile.id),
+            "Отключиться",
+            false,
+            None::<&str>,
+        )?;
+        let submenu = Submenu::with_items(
+            manager,
+            format!(
+                "{}: {}",
+                profile.name,
+                tray_link_state_text(&SecureLinkClientState::Stopped)
+            ),
+            true,
+            &[&connect_item, &disconnect_item],
+        )?;
+
+        menu.append(&submenu)?;
+
+        profile_items.push(TrayProfileItems {
+            profile: profile.clone(),
+            submenu,
+            connect_item,
+            disconnect_item,
+        });
     }
 
-    Ok(())
+    menu.append(&exit_item)?;
+
+    let menu_items = TrayMenuItems {
+        schedule_item,
+        connect_item,
+        disconnect_item,
+        keep_connected_item,
+        profile_items,
+    };
+
+    Ok((menu, menu_items))
 }
 
-#[tauri::command]
-async fn update_auth_token(state: State<'_, AppData>, auth_token: String) -> Result<(), String> {
-    if let Some(current_auth_token) = load_auth_token(&state).map_err(|e| format!("{:?}", e))? {
-        if current_auth_token == auth_token {
-            return Ok(());
-        }
-    }
+// Profiles were added, removed or renamed since the menu was built
+fn rebuild_tray_menu(
+    app: &AppHandle,
+    profiles: &[LinkProfile],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (menu, menu_items) = build_tray_menu(app, profiles)?;
 
-    store_auth_token(&state, auth_token).map_err(|e| format!("{:?}", e))?;
+    app.tray_by_id(TRAY_ID)
+        .ok_or("no tray icon")?
+        .set_menu(Some(menu))?;
 
-    reinitialize_secure_link_client(&state)
-        .await
-        .map_err(|e| format!("{:?}", e))?;
+    *app.state::<AppData>().tray_menu_items.lock().unwrap() = Some(menu_items);
 
     Ok(())
 }
 
-#[tauri::command]
-async fn get_auth_token(state: State<'_, AppData>) -> Result<Option<String>, String> {
-    Ok(load_auth_token(&state).map_err(|e| e.to_string())?)
-}
+// Update tray menu items directly without recreating menu
+fn update_tray_menu(
+    app: &AppHandle,
+    profile_id: &str,
+    client_state: &SecureLinkClientState,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let state = app.state::<AppData>();
+
+    let (is_connect_enabled, is_disconnect_enabled) = tray_menu_enabled_states(client_state);
+    let is_disconnect_enabled = is_disconnect_enabled && !state.policy.forbid_disconnect;
 
-// Get current client state for tray updates
-async fn get_client_state(state: &State<'_, AppData>) -> SecureLinkClientState {
-    let maybe_client = match ensure_secure_link_client_created(state).await {
-        Ok(client) => client,
-        Err(_) => return SecureLinkClientState::Stopped,
+    // Update menu items directly
+    let menu_items = state.tray_menu_items.lock().unwrap();
+    let Some(ref items) = *menu_items else {
+        return Ok(());
     };
 
-    if let Some(secure_link_client) = maybe_client {
-        match secure_link_client.status().await {
-            Ok(status) => status,
-            Err(_) => SecureLinkClientState::Stopped,
-        }
-    } else {
-        SecureLinkClientState::Stopped
+    if profile_id == DEFAULT_PROFILE_ID {
+        items.connect_item.set_enabled(is_connect_enabled)?;
+        items.disconnect_item.set_enabled(is_disconnect_enabled)?;
+    } else if let Some(profile_items) = items
+        .profile_items
+        .iter()
+        .find(|profile_items| profile_items.profile.id == profile_id)
+    {
+        profile_items.connect_item.set_enabled(is_connect_enabled)?;
+        profile_items
+            .disconnect_item
+            .set_enabled(is_disconnect_enabled)?;
+        profile_items.submenu.set_text(format!(
+            "{}: {}",
+            profile_items.profile.name,
+            tray_link_state_text(client_state)
+        ))?;
     }
+
+    Ok(())
 }
 
-// Update tray menu items directly without recreating menu
-async fn update_tray_menu(app: &AppHandle) -> Result<(), Box<dyn std::error::Error>> {
+fn update_tray_keep_connected_item(app: &AppHandle) -> Result<(), Box<dyn std::error::Error>> {
     let state = app.state::<AppData>();
-    let client_state = get_client_state(&state).await;
 
-    let (is_connect_enabled, is_disconnect_enabled) = match client_state {
-        SecureLinkClientState::Stopped => (true, false),
-        SecureLinkClientState::Pending | SecureLinkClientState::Running => (false, true),
-    };
+    let is_warning = state
+        .all_links()
+        .iter()
+        .any(|link| matches!(*link.idle_check.lock().unwrap(), IdleCheck::Warning { .. }));
 
-    // Update menu items directly
     let menu_items = state.tray_menu_items.lock().unwrap();
     if let Some(ref items) = *menu_items {
-        items.connect_item.set_enabled(is_connect_enabled)?;
-        items.disconnect_item.set_enabled(is_disconnect_enabled)?;
+        items.keep_connected_item.set_enabled(is_warning)?;
+    }
+
+    Ok(())
+}
+
+fn update_tray_schedule_item(app: &AppHandle, text: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let state = app.state::<AppData>();
+
+    let menu_items = state.tray_menu_items.lock().unwrap();
+    if let Some(ref items) = *menu_items {
+        items.schedule_item.set_text(text)?;
     }
 
     Ok(())
@@ -275,8 +2429,44 @@ async fn tray_update_task(app: AppHandle) {
     let mut interval = tokio::time::interval(Duration::from_millis(200));
 
     loop {
-        if let Err(e) = update_tray_menu(&app).await {
-            eprintln!("Failed to update tray menu: {}", e);
+        let state = app.state::<AppData>();
+
+        let profiles = state.settings.get().profiles;
+        let is_menu_current = state
+            .tray_menu_items
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_none_or(|items| {
+                items
+                    .profile_items
+                    .iter()
+                    .map(|profile_items| &profile_items.profile)
+                    .eq(profiles.iter())
+            });
+
+        if !is_menu_current {
+            if let Err(e) = rebuild_tray_menu(&app, &profiles) {
+                warn!("Failed to rebuild tray menu: {}", e);
+            }
+        }
+
+        for link in state.all_links() {
+            let client_status = get_client_status(&link).await;
+
+            // A server that closed the session cleanly is still reachable
+            let dropped = matches!(
+                track_connection_session(&link, &client_status),
+                Some(ConnectionOutcome::NetworkError)
+            );
+
+            if dropped && link.endpoint_failover.failed() {
+                tauri::async_runtime::spawn(fail_over_secure_link(app.clone(), link.clone()));
+            }
+
+            if let Err(e) = update_tray_menu(&app, &link.profile_id, &client_status.state) {
+                eprintln!("Failed to update tray menu: {}", e);
+            }
         }
 
         interval.tick().await;
@@ -284,57 +2474,165 @@ async fn tray_update_task(app: AppHandle) {
 }
 
 #[cfg(feature = "windows-registry")]
-fn load_auth_token(
-    _state: &State<'_, AppData>,
-) -> Result<Option<String>, Box<dyn std::error::Error>> {
-    Ok(auth_token_windows_registry_storage::load_auth_token()?)
+fn load_auth_token(link: &SecureLink) -> Result<Option<AuthToken>, Box<dyn std::error::Error>> {
+    Ok(auth_token_windows_registry_storage::load_auth_token(
+        &link.profile_id,
+    )?)
 }
 
 #[cfg(not(feature = "windows-registry"))]
-fn load_auth_token(
-    state: &State<'_, AppData>,
-) -> Result<Option<String>, Box<dyn std::error::Error>> {
-    if let Ok(content) = std::fs::read_to_string(&state.auth_token_file_path) {
+fn load_auth_token(link: &SecureLink) -> Result<Option<AuthToken>, Box<dyn std::error::Error>> {
+    if let Ok(content) = std::fs::read_to_string(&link.auth_token_file_path) {
         if content.is_empty() {
             Ok(None)
         } else {
-            Ok(Some(content))
+            Ok(Some(AuthToken::new(content)))
         }
     } else {
         Ok(None)
     }
 }
 
+#[cfg(feature = "windows-registry")]
+fn load_auth_token_stored_at(link: &SecureLink) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+    auth_token_windows_registry_storage::load_auth_token_stored_at(&link.profile_id)
+}
+
+#[cfg(not(feature = "windows-registry"))]
+fn load_auth_token_stored_at(link: &SecureLink) -> Result<Option<u64>, Box<dyn std::error::Error>> {
+    let Ok(metadata) = std::fs::metadata(&link.auth_token_file_path) else {
+        return Ok(None);
+    };
+
+    Ok(Some(
+        metadata
+            .modified()?
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs(),
+    ))
+}
+
 #[cfg(feature = "windows-registry")]
 fn store_auth_token(
-    _state: &State<'_, AppData>,
-    auth_token: String,
+    link: &SecureLink,
+    auth_token: &AuthToken,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    auth_token_windows_registry_storage::store_auth_token(&auth_token)?;
+    auth_token_windows_registry_storage::store_auth_token(&link.profile_id, auth_token)?;
 
     Ok(())
 }
 
 #[cfg(not(feature = "windows-registry"))]
 fn store_auth_token(
-    state: &State<'_, AppData>,
-    auth_token: String,
+    link: &SecureLink,
+    auth_token: &AuthToken,
+) -> Result<(), Box<dyn std::error::Error>> {
+    std::fs::write(&link.auth_token_file_path, auth_token.expose())?;
+    Ok(())
+}
+
+#[cfg(feature = "windows-registry")]
+fn load_proxy_password(_app_data_dir: &Path) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    auth_token_windows_registry_storage::load_proxy_password()
+}
+
+#[cfg(not(feature = "windows-registry"))]
+fn load_proxy_password(app_data_dir: &Path) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    match std::fs::read_to_string(app_data_dir.join(PROXY_PASSWORD_FILE_NAME)) {
+        Ok(content) if !content.is_empty() => Ok(Some(content)),
+        Ok(_) => Ok(None),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// An empty password removes the stored one
+#[cfg(feature = "windows-registry")]
+fn store_proxy_password(
+    _app_data_dir: &Path,
+    password: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    auth_token_windows_registry_storage::store_proxy_password(password)
+}
+
+#[cfg(not(feature = "windows-registry"))]
+fn store_proxy_password(
+    app_data_dir: &Path,
+    password: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    std::fs::write(&state.auth_token_file_path, auth_token)?;
+    let file_path = app_data_dir.join(PROXY_PASSWORD_FILE_NAME);
+
+    if password.is_empty() {
+        match std::fs::remove_file(file_path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => return Ok(()),
+        }
+    }
+
+    std::fs::write(file_path, password)?;
     Ok(())
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    // Read before the logger exists, since the policy may set its level. A
+    // policy that can not be read is reported once logging is up.
+    let policy = managed_policy::load_managed_policy();
+    let log_level = policy
+        .as_ref()
+        .ok()
+        .and_then(|policy| policy.as_ref())
+        .and_then(|(policy, _)| policy.log_level)
+        .map_or(log::LevelFilter::Info, |log_level| log_level.level_filter());
+
     tauri::Builder::default()
-        .plugin(tauri_plugin_single_instance::init(|app, _args, _cwd| {
-            let _ = app.get_webview_window("main")
-                .expect("no main window")
-                .show();
+        .plugin(tauri_plugin_single_instance::init(|app, args, cwd| {
+            // The first argument is the executable path of the second launch
+            let actions = command_line_actions::parse_command_line_actions(
+                args.get(1..).unwrap_or_default(),
+                &cwd,
+            );
+
+            // A plain second launch just brings the running instance to front
+            if actions.is_empty() {
+                let _ = app.get_webview_window("main")
+                    .expect("no main window")
+                    .show();
+            } else {
+                tauri::async_runtime::spawn(run_command_line_actions(app.clone(), actions));
+            }
         }))
         .plugin(tauri_plugin_log::Builder::new().build())
         .plugin(tauri_plugin_process::init())
+        .plugin(tauri_plugin_notification::init())
+        .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_autostart::init(
+            tauri_plugin_autostart::MacosLauncher::LaunchAgent,
+            None,
+        ))
         .plugin(tauri_plugin_opener::init())
+        .plugin(
+            tauri_plugin_global_shortcut::Builder::new()
+                .with_handler(|app, shortcut, event| {
+                    if event.state() != ShortcutState::Pressed {
+                        return;
+                    }
+
+                    let action = app
+                        .state::<AppData>()
+                        .global_shortcuts
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .find(|(_, registered_shortcut)| registered_shortcut == shortcut)
+                        .map(|(action, _)| *action);
+
+                    if let Some(action) = action {
+                        tauri::async_runtime::spawn(run_shortcut_action(app.clone(), action));
+                    }
+                })
+                .build(),
+        )
         .on_window_event(|window, event| match event {
             tauri::WindowEvent::CloseRequested { api, .. } => {
                 window.hide().unwrap();
@@ -343,22 +2641,38 @@ pub fn run() {
             _ => {}
         })
         .setup(move |app| {
-            // Create menu items
-            let show_item = MenuItem::with_id(app, "show", "Показать Secure Link", true, None::<&str>)?;
-            let connect_item = MenuItem::with_id(app, "connect", "Подключиться", false, None::<&str>)?;
-            let disconnect_item = MenuItem::with_id(app, "disconnect", "Отключиться", false, None::<&str>)?;
-            let exit_item = MenuItem::with_id(app, "exit", "Закрыть Secure Link", true, None::<&str>)?;
-
-            let menu = Menu::with_items(app, &[&show_item, &connect_item, &disconnect_item, &exit_item])?;
-
-            // Store menu items for later updates
-            let menu_items = TrayMenuItems {
-                connect_item: connect_item.clone(),
-                disconnect_item: disconnect_item.clone()
-            };
+            let exe_path = std::env::current_exe()?;
+            let exe_dir = exe_path.parent().ok_or("Failed to get parent directory of exe")?.to_path_buf();
+
+            let app_data_dir = app.path().app_data_dir()?;
+
+            let app_data_dir =
+                if app_data_dir.exists() {
+                    app_data_dir
+                }
+                else {
+                    warn!("appdata dir not exists. using current binary location as appdata, ok for dev env.");
+                    exe_dir.clone()
+                };
+
+            // Checked before anything else, so a new version that fails
+            // anywhere in its startup is rolled back
+            let current_version = app.package_info().version.clone();
+            let installed_update = updater::check_installed_update(&exe_path, &app_data_dir, &current_version)
+                .unwrap_or_else(|e| {
+                    warn!("Failed to check the installed update: {}", e);
+                    InstalledUpdateCheck::None
+                });
+
+            if installed_update == InstalledUpdateCheck::RestartAfterRollback {
+                app.handle().restart();
+            }
+
+            // Profiles get their submenus once the settings are loaded
+            let (menu, menu_items) = build_tray_menu(app, &[])?;
 
             // Create tray icon and store the handle
-            let _tray = TrayIconBuilder::new()
+            let _tray = TrayIconBuilder::with_id(TRAY_ID)
                 .icon(app.default_window_icon().unwrap().clone())
                 .menu(&menu)
                 .show_menu_on_left_click(true)
@@ -375,7 +2689,7 @@ pub fn run() {
                             // Handle connect action using tray-specific function
                             tauri::async_runtime::spawn(async move {
                                 let state = app_handle.state::<AppData>();
-                                if let Err(e) = tray_start(&state).await {
+                                if let Err(e) = tray_start(&state, &state.default_link()).await {
                                     eprintln!("Failed to start secure link from tray: {}", e);
                                 }
                             });
@@ -384,22 +2698,51 @@ pub fn run() {
                             // Handle disconnect action using tray-specific function
                             tauri::async_runtime::spawn(async move {
                                 let state = app_handle.state::<AppData>();
-                                if let Err(e) = tray_stop(&state).await {
+                                if let Err(e) = tray_stop(&state, &state.default_link()).await {
                                     eprintln!("Failed to stop secure link from tray: {}", e);
                                 }
                             });
                         }
+                        "keep_connected" => {
+                            for link in app.state::<AppData>().all_links() {
+                                keep_connected(&link);
+                            }
+                        }
                         "exit" => {
-                            app.exit(0);
+                            if let Err(e) = quit(app) {
+                                warn!("Refusing to quit from tray: {}", e);
+                                show_notification(app, "Закрытие Secure Link запрещено политикой организации");
+                            }
+                        }
+                        id => {
+                            if let Some(profile_id) = id.strip_prefix("connect:") {
+                                let profile_id = profile_id.to_string();
+                                tauri::async_runtime::spawn(async move {
+                                    let state = app_handle.state::<AppData>();
+                                    let Some(link) = state.link(&profile_id) else {
+                                        return;
+                                    };
+                                    if let Err(e) = tray_start(&state, &link).await {
+                                        warn!("Failed to start secure link {} from tray: {}", profile_id, e);
+                                    }
+                                });
+                            } else if let Some(profile_id) = id.strip_prefix("disconnect:") {
+                                let profile_id = profile_id.to_string();
+                                tauri::async_runtime::spawn(async move {
+                                    let state = app_handle.state::<AppData>();
+                                    let Some(link) = state.link(&profile_id) else {
+                                        return;
+                                    };
+                                    if let Err(e) = tray_stop(&state, &link).await {
+                                        warn!("Failed to stop secure link {} from tray: {}", profile_id, e);
+                                    }
+                                });
+                            }
                         }
-                        _ => {}
                     }
                 })
                 .build(app)?;
 
-            let exe_path = std::env::current_exe()?;
-            let exe_dir = exe_path.parent().ok_or("Failed to get parent directory of exe")?.to_path_buf();
-
             #[cfg(feature = "secure-link-windows-service-client")]{
                 if !secure_link_windows_service_manager::is_service_installed()? {
                     secure_link_windows_service_manager::install_service(
@@ -408,57 +2751,200 @@ pub fn run() {
                 }
             }
 
-            let app_data_dir = app.path().app_data_dir()?;
-
-            let app_data_dir =
-                if app_data_dir.exists() {
-                    app_data_dir
-                }
-                else {
-                    warn!("appdata dir not exists. using current binary location as appdata, ok for dev env.");
-                    exe_dir
-                };
-
             let secure_link_server_host = env!("SECURE_LINK_SERVER_HOST", "SECURE_LINK_SERVER_HOST not set");
 
             let secure_link_server_port = env!("SECURE_LINK_SERVER_PORT", "SECURE_LINK_SERVER_PORT not set").parse::<u16>()
                 .expect("Invalid SECURE_LINK_SERVER_PORT number");
 
+            // A broken policy must not keep the app from starting, it then
+            // locks nothing and the UI shows why
+            let (policy, policy_source, policy_error) = match policy {
+                Ok(Some((policy, policy_source))) => {
+                    info!("Managed policy read from {}", policy_source);
+                    (policy, Some(policy_source), None)
+                }
+                Ok(None) => (ManagedPolicy::default(), None, None),
+                Err(e) => {
+                    warn!("Failed to read the managed policy: {}", e);
+                    (ManagedPolicy::default(), None, Some(e.to_string()))
+                }
+            };
+            let force_auto_connect = policy.force_auto_connect;
+
+            // Broken settings must not keep the app from starting, they are
+            // moved aside for support to look at
+            let settings = match SettingsStore::open(&app_data_dir) {
+                Ok(settings) => settings,
+                Err(e @ SettingsError::Malformed(_)) => {
+                    warn!("Failed to open the settings, starting with the defaults: {}", e);
+                    let (settings, backup_file_path) = SettingsStore::reset(&app_data_dir)?;
+                    if let Some(backup_file_path) = backup_file_path {
+                        warn!("Unreadable settings moved to {}", backup_file_path.display());
+                    }
+                    settings
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            let mut links = BTreeMap::new();
+            let mut profile_ids = vec![DEFAULT_PROFILE_ID.to_string()];
+            profile_ids.extend(settings.get().profiles.into_iter().map(|profile| profile.id));
+
+            for profile_id in profile_ids {
+                let link = spawn_secure_link(
+                    app.handle(),
+                    &app_data_dir,
+                    &profile_id,
+                    &settings.get().bandwidth,
+                )?;
+                links.insert(profile_id, link);
+            }
+
+            // A broken release key only turns the updater off
+            let updater = option_env!("SECURE_LINK_UPDATE_PUBLIC_KEY").and_then(|public_key| {
+                let public_key = updater::decode_public_key(public_key);
+                if public_key.is_none() {
+                    error!("Invalid SECURE_LINK_UPDATE_PUBLIC_KEY, updates are off");
+                }
+                public_key.map(|public_key| Updater::new(public_key, app_data_dir.clone()))
+            });
+
+            // The install marker is gone after this start, the settings keep
+            // the version from being offered again
+            let rolled_back_version = match installed_update {
+                InstalledUpdateCheck::RolledBack { ref version } => Some(version.clone()),
+                _ => None,
+            };
+
+            if let Some(version) = &rolled_back_version {
+                if let Err(e) = settings.update_with(|settings| {
+                    settings.updates.skipped_version = Some(version.clone())
+                }) {
+                    warn!("Failed to skip the rolled back version {}: {}", version, e);
+                }
+            }
+
+            let update_status = match (&rolled_back_version, &updater) {
+                (Some(version), _) => UpdateStatus::RolledBack { version: version.clone() },
+                (None, Some(_)) => UpdateStatus::Idle,
+                (None, None) => UpdateStatus::NotConfigured,
+            };
+
             app.manage(AppData {
-                secure_link_client: Mutex::new(None),
+                links: Mutex::new(links),
+                secure_link_client_factory: default_secure_link_client_factory(),
                 tray_menu_items: Mutex::new(Some(menu_items)), // Store menu items
-
-                #[cfg(feature = "secure-link-windows-service-client")] secure_link_service_log_file_path: {
-                    let service_log_file_name = "secure_link_service.log";
-                    app_data_dir.join(&service_log_file_name)
-                },
-                #[cfg(not(feature = "windows-registry"))] auth_token_file_path: {
-                    let auth_token_file = "auth_token_file.txt";
-                    app_data_dir.join(&auth_token_file)
-                },
+                app_data_dir,
                 secure_link_server_host: secure_link_server_host.to_string(),
-                secure_link_server_port
+                secure_link_server_port,
+                settings,
+                metrics_server: Mutex::new(None),
+                updater,
+                update_status: Mutex::new(update_status),
+                downloaded_update: Mutex::new(None),
+                global_shortcuts: Mutex::new(Vec::new()),
+                shortcut_status: Mutex::new(ShortcutStatus::default()),
+                policy,
+                policy_source,
+                policy_error,
+                native_confirm: dialog_native_confirm(app.handle().clone()),
             });
 
+            // Problems are kept in the shortcut status for the settings UI
+            let state = app.state::<AppData>();
+            if let Err(e) = register_global_shortcuts(app.handle(), &state, &state.settings.get().shortcuts) {
+                warn!("{}", e);
+            }
+
             // Start the background tray update task
             let app_handle = app.handle().clone();
             tauri::async_runtime::spawn(async move {
                 tray_update_task(app_handle).await;
             });
 
+            tauri::async_runtime::spawn(connection_schedule_task(app.handle().clone()));
+
+            tauri::async_runtime::spawn(idle_disconnect_task(app.handle().clone()));
+
+            let app_handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                let state = app_handle.state::<AppData>();
+
+                if let Err(e) = restart_metrics_server(&app_handle, &state, &state.settings.get().metrics).await {
+                    warn!("{}", e);
+                }
+            });
+
+            tauri::async_runtime::spawn(update_check_task(app.handle().clone()));
+
+            if force_auto_connect {
+                let app_handle = app.handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    let state = app_handle.state::<AppData>();
+                    if let Err(e) = tray_start(&state, &state.default_link()).await {
+                        warn!("Failed to auto-connect as the managed policy requires: {}", e);
+                    }
+                });
+            }
+
+            // Arguments of the first launch are handled the same way as forwarded ones
+            let startup_args: Vec<String> = std::env::args().skip(1).collect();
+            let startup_cwd = std::env::current_dir()?;
+            let startup_actions = command_line_actions::parse_command_line_actions(
+                &startup_args,
+                &startup_cwd.to_string_lossy(),
+            );
+
+            if !startup_actions.is_empty() {
+                tauri::async_runtime::spawn(run_command_line_actions(app.handle().clone(), startup_actions));
+            }
+
+            if installed_update == InstalledUpdateCheck::Unconfirmed {
+                if let Err(e) = updater::confirm_installed_update(&exe_path, &app.state::<AppData>().app_data_dir) {
+                    warn!("Failed to confirm the installed update: {}", e);
+                }
+            }
+
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             start,
             stop,
             current_state,
+            get_status_details,
+            get_recent_errors,
             update_auth_token,
-            get_auth_token,
+            get_auth_token_info,
+            reveal_auth_token,
+            import_auth_token_from_file,
+            enroll_with_code,
+            get_connection_history,
+            start_link,
+            stop_link,
+            get_link_status,
+            list_links,
+            update_link_auth_token,
+            get_settings,
+            update_settings,
+            get_server_config,
+            update_server_config,
+            get_schedule_config,
+            update_schedule_config,
+            get_schedule_status,
+            get_idle_status,
+            get_bandwidth_status,
+            keep_secure_link_connected,
+            get_update_status,
+            check_for_updates,
+            restart_to_update,
+            get_shortcut_status,
+            get_managed_policy,
+            #[cfg(feature = "secure-link-embedded-client")] check_connection,
             #[cfg(feature = "secure-link-windows-service-client")] get_service_log
         ])
         .plugin(
             tauri_plugin_log::Builder::new()
-                .level(log::LevelFilter::Info)
+                .level(log_level)
                 .build()
         )
         .run(tauri::generate_context!())
@@ -0,0 +1,307 @@
+// Settings an IT department enforces with a read-only policy. It comes from
+// `policy.json` in a system location, or from the policies key of the registry
+// when the app keeps its data there. The policy is read once at startup, a
+// change needs a restart.
+//
+// `{"endpoint": {"host": "...", "port": 443}, "forceAutoConnect": true,
+// "forbidDisconnect": true, "forbidTokenEditing": true, "logLevel": "debug"}`
+
+use crate::settings::{EndpointSettings, SettingsError};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[cfg(target_os = "macos")]
+pub const POLICY_FILE_PATH: &str = "/Library/Application Support/SecureLink/policy.json";
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub const POLICY_FILE_PATH: &str = "/etc/secure-link/policy.json";
+
+#[cfg(windows)]
+pub const POLICY_FILE_PATH: &str = "C:\\ProgramData\\SecureLink\\policy.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PolicyLogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl PolicyLogLevel {
+    pub fn level_filter(self) -> log::LevelFilter {
+        match self {
+            PolicyLogLevel::Off => log::LevelFilter::Off,
+            PolicyLogLevel::Error => log::LevelFilter::Error,
+            PolicyLogLevel::Warn => log::LevelFilter::Warn,
+            PolicyLogLevel::Info => log::LevelFilter::Info,
+            PolicyLogLevel::Debug => log::LevelFilter::Debug,
+            PolicyLogLevel::Trace => log::LevelFilter::Trace,
+        }
+    }
+}
+
+// Keys a newer version added are ignored, so an updated policy does not lock
+// out older installations
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManagedPolicy {
+    // The only server any link connects to
+    #[serde(default)]
+    pub endpoint: Option<EndpointSettings>,
+    // Connects the default link on launch
+    #[serde(default)]
+    pub force_auto_connect: bool,
+    #[serde(default)]
+    pub forbid_disconnect: bool,
+    #[serde(default)]
+    pub forbid_token_editing: bool,
+    #[serde(default)]
+    pub log_level: Option<PolicyLogLevel>,
+}
+
+// What the UI disables
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LockedSetting {
+    Endpoint,
+    AutoConnect,
+    Disconnect,
+    AuthToken,
+    LogLevel,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManagedPolicyStatus {
+    // Where the policy was read from, `None` when there is none
+    pub source: Option<String>,
+    pub locked_settings: Vec<LockedSetting>,
+    pub policy: ManagedPolicy,
+    // Why the policy could not be read, nothing is locked then
+    pub error: Option<String>,
+}
+
+// Displays as `PolicyViolation: ...`, so the UI can tell it from other errors
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyViolation {
+    #[error("PolicyViolation: Disconnecting is forbidden by the managed policy")]
+    Disconnect,
+
+    #[error("PolicyViolation: Editing the auth token is forbidden by the managed policy")]
+    AuthTokenEditing,
+
+    #[error("PolicyViolation: The server endpoint is pinned by the managed policy")]
+    Endpoint,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ManagedPolicyError {
+    #[error("Policy file can not be read: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Policy is malformed: {0}")]
+    Malformed(#[from] serde_json::Error),
+
+    #[cfg(feature = "windows-registry")]
+    #[error("Policy registry key can not be read: {0}")]
+    Registry(String),
+
+    #[error("Policy endpoint is invalid: {0}")]
+    InvalidEndpoint(#[from] SettingsError),
+}
+
+impl ManagedPolicy {
+    pub fn locked_settings(&self) -> Vec<LockedSetting> {
+        [
+            (self.endpoint.is_some(), LockedSetting::Endpoint),
+            (self.force_auto_connect, LockedSetting::AutoConnect),
+            (self.forbid_disconnect, LockedSetting::Disconnect),
+            (self.forbid_token_editing, LockedSetting::AuthToken),
+            (self.log_level.is_some(), LockedSetting::LogLevel),
+        ]
+        .into_iter()
+        .filter(|(is_locked, _)| *is_locked)
+        .map(|(_, locked_setting)| locked_setting)
+        .collect()
+    }
+
+    pub fn check_disconnect(&self) -> Result<(), PolicyViolation> {
+        if self.forbid_disconnect {
+            return Err(PolicyViolation::Disconnect);
+        }
+
+        Ok(())
+    }
+
+    pub fn check_auth_token_editing(&self) -> Result<(), PolicyViolation> {
+        if self.forbid_token_editing {
+            return Err(PolicyViolation::AuthTokenEditing);
+        }
+
+        Ok(())
+    }
+
+    // Endpoints in the settings do not matter while the policy pins one, so
+    // changing them is refused rather than silently ignored. The proxy and the
+    // trust decide which server answers at the pinned endpoint, so they count
+    // as endpoint changes too.
+    pub fn check_endpoints_change(&self, is_changed: bool) -> Result<(), PolicyViolation> {
+        if self.endpoint.is_some() && is_changed {
+            return Err(PolicyViolation::Endpoint);
+        }
+
+        Ok(())
+    }
+
+    // An idle timeout or a schedule disconnects on its own, so turning one on
+    // is refused like the endpoints while disconnecting is forbidden
+    pub fn check_auto_disconnect_change(&self, is_enabled: bool) -> Result<(), PolicyViolation> {
+        if self.forbid_disconnect && is_enabled {
+            return Err(PolicyViolation::Disconnect);
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ManagedPolicyError> {
+        if let Some(endpoint) = &self.endpoint {
+            endpoint.validate()?;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn parse_managed_policy(content: &str) -> Result<ManagedPolicy, ManagedPolicyError> {
+    let policy: ManagedPolicy = serde_json::from_str(content)?;
+    policy.validate()?;
+
+    Ok(policy)
+}
+
+// `None` when the file does not exist
+pub fn load_managed_policy_file(path: &Path) -> Result<Option<ManagedPolicy>, ManagedPolicyError> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => parse_managed_policy(&content).map(Some),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Returns the policy and where it was read from. A policies key in the
+// registry wins over the file.
+#[cfg(feature = "windows-registry")]
+pub fn load_managed_policy() -> Result<Option<(ManagedPolicy, String)>, ManagedPolicyError> {
+    if let Some(policy) = crate::managed_policy_windows_registry::load_managed_policy()? {
+        policy.validate()?;
+
+        return Ok(Some((
+            policy,
+            crate::managed_policy_windows_registry::REGISTRY_POLICY_KEY_DISPLAY.to_string(),
+        )));
+    }
+
+    load_managed_policy_from_file()
+}
+
+#[cfg(not(feature = "windows-registry"))]
+pub fn load_managed_policy() -> Result<Option<(ManagedPolicy, String)>, ManagedPolicyError> {
+    load_managed_policy_from_file()
+}
+
+fn load_managed_policy_from_file() -> Result<Option<(ManagedPolicy, String)>, ManagedPolicyError> {
+    Ok(load_managed_policy_file(Path::new(POLICY_FILE_PATH))?
+        .map(|policy| (policy, POLICY_FILE_PATH.to_string())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_policies_and_reports_locked_settings() {
+        let policy = parse_managed_policy(
+            r#"{"endpoint": {"host": "link.corp.example", "port": 443}, "forbidDisconnect": true, "logLevel": "debug", "addedLater": 1}"#,
+        )
+        .unwrap();
+
+        assert_eq!(policy.endpoint.as_ref().unwrap().host, "link.corp.example");
+        assert_eq!(
+            policy.log_level.unwrap().level_filter(),
+            log::LevelFilter::Debug
+        );
+        assert_eq!(
+            policy.locked_settings(),
+            vec![
+                LockedSetting::Endpoint,
+                LockedSetting::Disconnect,
+                LockedSetting::LogLevel
+            ]
+        );
+
+        assert_eq!(
+            parse_managed_policy("{}").unwrap().locked_settings(),
+            vec![]
+        );
+        assert!(matches!(
+            parse_managed_policy(r#"{"endpoint": {"host": "", "port": 443}}"#),
+            Err(ManagedPolicyError::InvalidEndpoint(_))
+        ));
+        assert!(matches!(
+            parse_managed_policy(r#"{"logLevel": "verbose"}"#),
+            Err(ManagedPolicyError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_what_the_policy_forbids() {
+        let policy = ManagedPolicy {
+            endpoint: Some(EndpointSettings {
+                host: "link.corp.example".to_string(),
+                port: 443,
+                priority: 0,
+                weight: 1,
+            }),
+            forbid_token_editing: true,
+            ..Default::default()
+        };
+
+        assert_eq!(policy.check_disconnect(), Ok(()));
+        assert_eq!(policy.check_auto_disconnect_change(true), Ok(()));
+        assert_eq!(
+            policy.check_auth_token_editing(),
+            Err(PolicyViolation::AuthTokenEditing)
+        );
+        assert_eq!(policy.check_endpoints_change(false), Ok(()));
+        assert_eq!(
+            policy.check_endpoints_change(true),
+            Err(PolicyViolation::Endpoint)
+        );
+
+        let policy = ManagedPolicy {
+            forbid_disconnect: true,
+            ..Default::default()
+        };
+
+        assert_eq!(policy.check_disconnect(), Err(PolicyViolation::Disconnect));
+        assert_eq!(policy.check_auto_disconnect_change(false), Ok(()));
+        assert_eq!(
+            policy.check_auto_disconnect_change(true),
+            Err(PolicyViolation::Disconnect)
+        );
+        assert!(PolicyViolation::Disconnect
+            .to_string()
+            .starts_with("PolicyViolation: "));
+    }
+
+    #[test]
+    fn missing_policy_file_is_no_policy() {
+        let path =
+            std::env::temp_dir().join(format!("secure_link_no_policy_{}.json", std::process::id()));
+
+        assert_eq!(load_managed_policy_file(&path).unwrap(), None);
+    }
+}
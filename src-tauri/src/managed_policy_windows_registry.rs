@@ -0,0 +1,82 @@
+// The managed policy as set by a group policy: one value per key of
+// `policy.json` under `HKLM\SOFTWARE\Policies\SecureLink`
+static REGISTRY_POLICY_KEY_PATH: &str = "SOFTWARE\\Policies\\SecureLink";
+pub static REGISTRY_POLICY_KEY_DISPLAY: &str = "HKLM\\SOFTWARE\\Policies\\SecureLink";
+
+static REGISTRY_ENDPOINT_HOST_VALUE: &str = "EndpointHost";
+static REGISTRY_ENDPOINT_PORT_VALUE: &str = "EndpointPort";
+static REGISTRY_FORCE_AUTO_CONNECT_VALUE: &str = "ForceAutoConnect";
+static REGISTRY_FORBID_DISCONNECT_VALUE: &str = "ForbidDisconnect";
+static REGISTRY_FORBID_TOKEN_EDITING_VALUE: &str = "ForbidTokenEditing";
+static REGISTRY_LOG_LEVEL_VALUE: &str = "LogLevel";
+
+use crate::managed_policy::{ManagedPolicy, ManagedPolicyError};
+use crate::settings::EndpointSettings;
+use winreg::enums::{HKEY_LOCAL_MACHINE, KEY_READ};
+use winreg::RegKey;
+
+fn load_optional_value<T: winreg::types::FromRegValue>(
+    reg_key: &RegKey,
+    name: &str,
+) -> Result<Option<T>, ManagedPolicyError> {
+    match reg_key.get_value::<T, _>(name) {
+        Ok(value) => Ok(Some(value)),
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(ManagedPolicyError::Registry(format!("{}: {}", name, e))),
+    }
+}
+
+// DWORD flags, anything but 0 is set
+fn load_flag(reg_key: &RegKey, name: &str) -> Result<bool, ManagedPolicyError> {
+    Ok(load_optional_value::<u32>(reg_key, name)?.is_some_and(|value| value != 0))
+}
+
+// `None` when the key does not exist. Opened read-only, the app never writes
+// its own policy.
+pub fn load_managed_policy() -> Result<Option<ManagedPolicy>, ManagedPolicyError> {
+    let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
+
+    let reg_key = match hklm.open_subkey_with_flags(REGISTRY_POLICY_KEY_PATH, KEY_READ) {
+        Ok(reg_key) => reg_key,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(ManagedPolicyError::Registry(e.to_string())),
+    };
+
+    let endpoint_host = load_optional_value::<String>(&reg_key, REGISTRY_ENDPOINT_HOST_VALUE)?;
+    let endpoint_port = load_optional_value::<u32>(&reg_key, REGISTRY_ENDPOINT_PORT_VALUE)?;
+
+    let endpoint = match (endpoint_host, endpoint_port) {
+        (Some(host), Some(port)) => Some(EndpointSettings {
+            host,
+            port: u16::try_from(port).map_err(|_| {
+                ManagedPolicyError::Registry(format!(
+                    "{} is out of range",
+                    REGISTRY_ENDPOINT_PORT_VALUE
+                ))
+            })?,
+            priority: 0,
+            weight: 1,
+        }),
+        (None, None) => None,
+        _ => {
+            return Err(ManagedPolicyError::Registry(format!(
+                "{} and {} are only valid together",
+                REGISTRY_ENDPOINT_HOST_VALUE, REGISTRY_ENDPOINT_PORT_VALUE
+            )))
+        }
+    };
+
+    let log_level = load_optional_value::<String>(&reg_key, REGISTRY_LOG_LEVEL_VALUE)?
+        .map(|log_level| {
+            serde_json::from_value(serde_json::Value::String(log_level.to_lowercase()))
+        })
+        .transpose()?;
+
+    Ok(Some(ManagedPolicy {
+        endpoint,
+        force_auto_connect: load_flag(&reg_key, REGISTRY_FORCE_AUTO_CONNECT_VALUE)?,
+        forbid_disconnect: load_flag(&reg_key, REGISTRY_FORBID_DISCONNECT_VALUE)?,
+        forbid_token_editing: load_flag(&reg_key, REGISTRY_FORBID_TOKEN_EDITING_VALUE)?,
+        log_level,
+    }))
+}
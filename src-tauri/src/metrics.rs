@@ -0,0 +1,456 @@
+// Prometheus metrics of the secure link, served in the text exposition format
+// on a loopback listener when enabled in the settings
+
+use crate::connection_history::ConnectionOutcome;
+use crate::secure_link_client::{SecureLinkClientState, SecureLinkTraffic};
+use crate::secure_link_supervisor::SecureLinkSupervisorError;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const DEFAULT_METRICS_PORT: u16 = 9477;
+const MAX_REQUEST_HEAD_LEN: usize = 8 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetricsSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    // The listener only ever binds to 127.0.0.1
+    #[serde(default = "default_metrics_port")]
+    pub port: u16,
+}
+
+fn default_metrics_port() -> u16 {
+    DEFAULT_METRICS_PORT
+}
+
+impl Default for MetricsSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: DEFAULT_METRICS_PORT,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricsCounters {
+    pub connect_attempts: u64,
+    // Attempts after a session or attempt that did not end with a user stop
+    pub reconnect_attempts: u64,
+    pub connects: u64,
+    pub connect_failures: BTreeMap<&'static str, u64>,
+    pub disconnects: BTreeMap<&'static str, u64>,
+}
+
+// Counters fed by the supervisor hooks, the same events that end up in the
+// connection history
+#[derive(Default)]
+pub struct SecureLinkMetrics {
+    counters: Mutex<MetricsCounters>,
+    // `None` until the first attempt ended
+    last_end_was_user_stop: Mutex<Option<bool>>,
+}
+
+impl SecureLinkMetrics {
+    pub fn connect_attempted(&self) {
+        let mut counters = self.counters.lock().unwrap();
+
+        counters.connect_attempts += 1;
+
+        if *self.last_end_was_user_stop.lock().unwrap() == Some(false) {
+            counters.reconnect_attempts += 1;
+        }
+    }
+
+    pub fn connect_finished(&self, result: &Result<(), SecureLinkSupervisorError>) {
+        let mut counters = self.counters.lock().unwrap();
+
+        match result {
+            Ok(()) => counters.connects += 1,
+            Err(e) => {
+                *counters
+                    .connect_failures
+                    .entry(connect_failure_reason(e))
+                    .or_default() += 1;
+                *self.last_end_was_user_stop.lock().unwrap() = Some(false);
+            }
+        }
+    }
+
+    pub fn disconnected(&self, outcome: ConnectionOutcome) {
+        *self
+            .counters
+            .lock()
+            .unwrap()
+            .disconnects
+            .entry(disconnect_reason(outcome))
+            .or_default() += 1;
+        *self.last_end_was_user_stop.lock().unwrap() = Some(outcome == ConnectionOutcome::UserStop);
+    }
+
+    pub fn counters(&self) -> MetricsCounters {
+        self.counters.lock().unwrap().clone()
+    }
+}
+
+fn connect_failure_reason(error: &SecureLinkSupervisorError) -> &'static str {
+    match error {
+        SecureLinkSupervisorError::Unauthorized => "unauthorized",
+        SecureLinkSupervisorError::CertificatePinMismatch => "certificate_pin_mismatch",
+        SecureLinkSupervisorError::NoAuthToken | SecureLinkSupervisorError::AuthTokenStorage(_) => {
+            "auth_token"
+        }
+        SecureLinkSupervisorError::Network(_) => "network_error",
+        SecureLinkSupervisorError::TrustConfig(_) => "trust_config_error",
+        #[cfg(feature = "secure-link-windows-service-client")]
+        SecureLinkSupervisorError::Other(_) => "error",
+        SecureLinkSupervisorError::SupervisorGone => "error",
+    }
+}
+
+fn disconnect_reason(outcome: ConnectionOutcome) -> &'static str {
+    match outcome {
+        ConnectionOutcome::Unauthorized => "unauthorized",
+        ConnectionOutcome::NetworkError => "network_error",
+        ConnectionOutcome::UserStop => "user_stop",
+        ConnectionOutcome::ServerClose => "server_close",
+        ConnectionOutcome::IdleTimeout => "idle_timeout",
+    }
+}
+
+// What one link reports in a scrape
+pub struct MetricsSnapshot {
+    // Becomes the `profile` label of every sample
+    pub profile_id: String,
+    pub state: SecureLinkClientState,
+    pub counters: MetricsCounters,
+    // Current session, `None` when the backend does not count traffic
+    pub traffic: Option<SecureLinkTraffic>,
+    // Unix seconds
+    pub auth_token_expires_at: Option<u64>,
+}
+
+// Label values and sample value of one line
+type Samples = Vec<(String, String)>;
+
+pub fn render_metrics(snapshots: &[MetricsSnapshot]) -> String {
+    let mut text = String::new();
+
+    let per_link = |sample: &dyn Fn(&MetricsSnapshot) -> Option<String>| -> Samples {
+        snapshots
+            .iter()
+            .filter_map(|snapshot| Some((profile_label(snapshot), sample(snapshot)?)))
+            .collect()
+    };
+
+    let by_reason = |values: &dyn Fn(&MetricsCounters) -> &BTreeMap<&'static str, u64>| {
+        snapshots
+            .iter()
+            .flat_map(|snapshot| {
+                values(&snapshot.counters).iter().map(|(reason, value)| {
+                    (
+                        format!("{},reason=\"{reason}\"", profile_label(snapshot)),
+                        value.to_string(),
+                    )
+                })
+            })
+            .collect()
+    };
+
+    let states = snapshots
+        .iter()
+        .flat_map(|snapshot| {
+            [
+                (SecureLinkClientState::Stopped, "stopped"),
+                (SecureLinkClientState::Pending, "pending"),
+                (SecureLinkClientState::Running, "running"),
+                (SecureLinkClientState::Stopping, "stopping"),
+            ]
+            .into_iter()
+            .map(|(state, label)| {
+                (
+                    format!("{},state=\"{label}\"", profile_label(snapshot)),
+                    u8::from(snapshot.state == state).to_string(),
+                )
+            })
+        })
+        .collect();
+
+    write_metric(
+        &mut text,
+        "secure_link_state",
+        "gauge",
+        "Current state of the secure link",
+        states,
+    );
+    write_metric(
+        &mut text,
+        "secure_link_connect_attempts_total",
+        "counter",
+        "Connection attempts",
+        per_link(&|snapshot| Some(snapshot.counters.connect_attempts.to_string())),
+    );
+    write_metric(
+        &mut text,
+        "secure_link_reconnect_attempts_total",
+        "counter",
+        "Connection attempts after the link was lost or failed to connect",
+        per_link(&|snapshot| Some(snapshot.counters.reconnect_attempts.to_string())),
+    );
+    write_metric(
+        &mut text,
+        "secure_link_connects_total",
+        "counter",
+        "Successful connections",
+        per_link(&|snapshot| Some(snapshot.counters.connects.to_string())),
+    );
+    write_metric(
+        &mut text,
+        "secure_link_connect_failures_total",
+        "counter",
+        "Failed connection attempts by reason",
+        by_reason(&|counters| &counters.connect_failures),
+    );
+    write_metric(
+        &mut text,
+        "secure_link_disconnects_total",
+        "counter",
+        "Ended sessions by reason",
+        by_reason(&|counters| &counters.disconnects),
+    );
+    write_metric(
+        &mut text,
+        "secure_link_bytes_sent_total",
+        "counter",
+        "Bytes sent in the current session",
+        per_link(&|snapshot| Some(snapshot.traffic?.bytes_sent.to_string())),
+    );
+    write_metric(
+        &mut text,
+        "secure_link_bytes_received_total",
+        "counter",
+        "Bytes received in the current session",
+        per_link(&|snapshot| Some(snapshot.traffic?.bytes_received.to_string())),
+    );
+    write_metric(
+        &mut text,
+        "secure_link_auth_token_expiry_timestamp_seconds",
+        "gauge",
+        "When the stored auth token expires, in unix seconds",
+        per_link(&|snapshot| Some(snapshot.auth_token_expires_at?.to_string())),
+    );
+
+    text
+}
+
+// Profile ids are limited to characters that need no escaping
+fn profile_label(snapshot: &MetricsSnapshot) -> String {
+    format!("profile=\"{}\"", snapshot.profile_id)
+}
+
+// Metrics without samples are left out entirely
+fn write_metric(text: &mut String, name: &str, metric_type: &str, help: &str, samples: Samples) {
+    if samples.is_empty() {
+        return;
+    }
+
+    let _ = writeln!(text, "# HELP {name} {help}");
+    let _ = writeln!(text, "# TYPE {name} {metric_type}");
+
+    for (labels, value) in samples {
+        let _ = writeln!(text, "{name}{{{labels}}} {value}");
+    }
+}
+
+// Binds the loopback listener, so a taken port is reported to the caller
+pub async fn bind_metrics_listener(port: u16) -> std::io::Result<TcpListener> {
+    TcpListener::bind(("127.0.0.1", port)).await
+}
+
+// Answers `GET /metrics` with the text `render` produces, anything else with
+// 404. Runs until the task is aborted.
+pub async fn run_metrics_server<F, Fut>(listener: TcpListener, render: F)
+where
+    F: Fn() -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = String> + Send,
+{
+    while let Ok((stream, _)) = listener.accept().await {
+        let render = render.clone();
+
+        tokio::spawn(async move {
+            let _ = tokio::time::timeout(REQUEST_TIMEOUT, serve_request(stream, render)).await;
+        });
+    }
+}
+
+async fn serve_request<F, Fut>(mut stream: TcpStream, render: F) -> std::io::Result<()>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = String>,
+{
+    let mut request_head = Vec::new();
+    let mut buffer = [0u8; 1024];
+
+    while !request_head.windows(4).any(|window| window == b"\r\n\r\n") {
+        if request_head.len() >= MAX_REQUEST_HEAD_LEN {
+            return Ok(());
+        }
+
+        let read = stream.read(&mut buffer).await?;
+
+        if read == 0 {
+            return Ok(());
+        }
+
+        request_head.extend_from_slice(&buffer[..read]);
+    }
+
+    let request_line = String::from_utf8_lossy(&request_head)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_string();
+
+    let mut request_line_parts = request_line.split_whitespace();
+    let method = request_line_parts.next();
+    let path = request_line_parts.next();
+
+    let response = match (method, path) {
+        (Some("GET"), Some("/metrics")) => {
+            let body = render().await;
+
+            format!(
+                "HTTP/1.1 200 OK\r\n\
+                 Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+                 Content-Length: {}\r\n\
+                 Connection: close\r\n\r\n{body}",
+                body.len()
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(profile_id: &str, metrics: &SecureLinkMetrics) -> MetricsSnapshot {
+        MetricsSnapshot {
+            profile_id: profile_id.to_string(),
+            state: SecureLinkClientState::Running,
+            counters: metrics.counters(),
+            traffic: Some(SecureLinkTraffic {
+                bytes_sent: 1024,
+                bytes_received: 4096,
+            }),
+            auth_token_expires_at: Some(1893456000),
+        }
+    }
+
+    #[test]
+    fn counts_reconnects_after_unexpected_ends_only() {
+        let metrics = SecureLinkMetrics::default();
+
+        metrics.connect_attempted();
+        metrics.connect_finished(&Ok(()));
+        metrics.disconnected(ConnectionOutcome::UserStop);
+
+        metrics.connect_attempted();
+        metrics.connect_finished(&Err(SecureLinkSupervisorError::Unauthorized));
+
+        metrics.connect_attempted();
+        metrics.connect_finished(&Ok(()));
+        metrics.disconnected(ConnectionOutcome::NetworkError);
+
+        metrics.connect_attempted();
+
+        let counters = metrics.counters();
+
+        assert_eq!(counters.connect_attempts, 4);
+        assert_eq!(counters.reconnect_attempts, 2);
+        assert_eq!(counters.connects, 2);
+        assert_eq!(
+            counters.connect_failures,
+            BTreeMap::from([("unauthorized", 1)])
+        );
+        assert_eq!(
+            counters.disconnects,
+            BTreeMap::from([("network_error", 1), ("user_stop", 1)])
+        );
+    }
+
+    #[test]
+    fn renders_text_format() {
+        let metrics = SecureLinkMetrics::default();
+        metrics.connect_attempted();
+        metrics.connect_finished(&Ok(()));
+        metrics.disconnected(ConnectionOutcome::IdleTimeout);
+
+        let text = render_metrics(&[
+            snapshot("default", &metrics),
+            snapshot("staging", &SecureLinkMetrics::default()),
+        ]);
+
+        assert_eq!(text.matches("# TYPE secure_link_state gauge\n").count(), 1);
+        assert!(text.contains("secure_link_state{profile=\"default\",state=\"running\"} 1\n"));
+        assert!(text.contains("secure_link_state{profile=\"default\",state=\"stopped\"} 0\n"));
+        assert!(text.contains("secure_link_connects_total{profile=\"default\"} 1\n"));
+        assert!(text.contains("secure_link_connects_total{profile=\"staging\"} 0\n"));
+        assert!(text.contains(
+            "secure_link_disconnects_total{profile=\"default\",reason=\"idle_timeout\"} 1\n"
+        ));
+        assert!(text.contains("secure_link_bytes_received_total{profile=\"staging\"} 4096\n"));
+        assert!(text.contains(
+            "secure_link_auth_token_expiry_timestamp_seconds{profile=\"default\"} 1893456000\n"
+        ));
+    }
+
+    async fn get(port: u16, path: &str) -> String {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        stream
+            .write_all(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes())
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        response
+    }
+
+    #[tokio::test]
+    async fn serves_metrics_on_loopback() {
+        let listener = bind_metrics_listener(0).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        assert!(listener.local_addr().unwrap().ip().is_loopback());
+
+        let server = tokio::spawn(run_metrics_server(listener, || async {
+            "secure_link_connects_total 3\n".to_string()
+        }));
+
+        let response = get(port, "/metrics").await;
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+        assert!(response.ends_with("\r\n\r\nsecure_link_connects_total 3\n"));
+
+        assert!(get(port, "/")
+            .await
+            .starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        server.abort();
+    }
+}
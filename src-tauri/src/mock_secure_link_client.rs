@@ -0,0 +1,229 @@
+use crate::secure_link_client::{
+    SecureLinkClient, SecureLinkClientError, SecureLinkClientFactory, SecureLinkClientParams,
+    SecureLinkClientState, SecureLinkClientStopOutcome, SecureLinkTraffic,
+};
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// `SecureLinkClientError` is not `Clone`, so scripts hold outcomes and the
+// error is built when the call is made
+#[derive(Debug, Clone)]
+pub enum MockOutcome {
+    Ok,
+    Unauthorized,
+    NetworkError(&'static str),
+    CertificatePinMismatch,
+    // Only meaningful for `stop`, which then reports a forced stop
+    ForcedStop,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockCall {
+    Start,
+    Stop,
+    Status,
+}
+
+struct MockResponse {
+    delay: Duration,
+    outcome: MockOutcome,
+}
+
+// Scriptable client. Unscripted calls succeed right away: `start` moves to
+// `Running`, `stop` to `Stopped`. While a delayed `start` is in flight the
+// state is `Pending`, while a delayed `stop` is in flight it is `Stopping`.
+pub struct MockSecureLinkClient {
+    start_responses: Mutex<VecDeque<MockResponse>>,
+    stop_responses: Mutex<VecDeque<MockResponse>>,
+    scripted_states: Mutex<VecDeque<SecureLinkClientState>>,
+    current_state: Mutex<SecureLinkClientState>,
+    calls: Mutex<Vec<MockCall>>,
+    // `None` like a backend that can not see the traffic
+    traffic: Mutex<Option<SecureLinkTraffic>>,
+}
+
+impl MockSecureLinkClient {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            start_responses: Mutex::new(VecDeque::new()),
+            stop_responses: Mutex::new(VecDeque::new()),
+            scripted_states: Mutex::new(VecDeque::new()),
+            current_state: Mutex::new(SecureLinkClientState::Stopped),
+            calls: Mutex::new(Vec::new()),
+            traffic: Mutex::new(None),
+        })
+    }
+
+    pub fn set_traffic(&self, traffic: SecureLinkTraffic) {
+        *self.traffic.lock().unwrap() = Some(traffic);
+    }
+
+    pub fn queue_start(&self, outcome: MockOutcome) {
+        self.queue_start_with_delay(Duration::ZERO, outcome);
+    }
+
+    pub fn queue_start_with_delay(&self, delay: Duration, outcome: MockOutcome) {
+        self.start_responses
+            .lock()
+            .unwrap()
+            .push_back(MockResponse { delay, outcome });
+    }
+
+    pub fn queue_stop(&self, outcome: MockOutcome) {
+        self.queue_stop_with_delay(Duration::ZERO, outcome);
+    }
+
+    pub fn queue_stop_with_delay(&self, delay: Duration, outcome: MockOutcome) {
+        self.stop_responses
+            .lock()
+            .unwrap()
+            .push_back(MockResponse { delay, outcome });
+    }
+
+    // Each `status` call takes the next scripted state, the last one sticks
+    pub fn queue_states(&self, states: impl IntoIterator<Item = SecureLinkClientState>) {
+        self.scripted_states.lock().unwrap().extend(states);
+    }
+
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    fn record_call(&self, call: MockCall) {
+        self.calls.lock().unwrap().push(call);
+    }
+}
+
+#[async_trait]
+impl SecureLinkClient for MockSecureLinkClient {
+    async fn start(&self) -> Result<(), SecureLinkClientError> {
+        self.record_call(MockCall::Start);
+
+        let response = self
+            .start_responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or(MockResponse {
+                delay: Duration::ZERO,
+                outcome: MockOutcome::Ok,
+            });
+
+        if !response.delay.is_zero() {
+            *self.current_state.lock().unwrap() = SecureLinkClientState::Pending;
+            tokio::time::sleep(response.delay).await;
+        }
+
+        let result = mock_result(&response.outcome);
+
+        *self.current_state.lock().unwrap() = match result {
+            Ok(()) => SecureLinkClientState::Running,
+            Err(_) => SecureLinkClientState::Stopped,
+        };
+
+        result
+    }
+
+    async fn stop(&self) -> Result<SecureLinkClientStopOutcome, SecureLinkClientError> {
+        self.record_call(MockCall::Stop);
+
+        let response = self
+            .stop_responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or(MockResponse {
+                delay: Duration::ZERO,
+                outcome: MockOutcome::Ok,
+            });
+
+        if !response.delay.is_zero() {
+            let mut current_state = self.current_state.lock().unwrap();
+
+            if *current_state == SecureLinkClientState::Running {
+                *current_state = SecureLinkClientState::Stopping;
+            }
+        }
+
+        tokio::time::sleep(response.delay).await;
+
+        mock_result(&response.outcome)?;
+
+        *self.current_state.lock().unwrap() = SecureLinkClientState::Stopped;
+
+        match response.outcome {
+            MockOutcome::ForcedStop => Ok(SecureLinkClientStopOutcome::Forced),
+            _ => Ok(SecureLinkClientStopOutcome::Clean),
+        }
+    }
+
+    async fn status(&self) -> Result<SecureLinkClientState, SecureLinkClientError> {
+        self.record_call(MockCall::Status);
+
+        let mut scripted_states = self.scripted_states.lock().unwrap();
+        let mut current_state = self.current_state.lock().unwrap();
+
+        if scripted_states.len() > 1 {
+            *current_state = scripted_states.pop_front().unwrap();
+        } else if let Some(last_state) = scripted_states.front() {
+            *current_state = last_state.clone();
+        }
+
+        Ok(current_state.clone())
+    }
+
+    async fn traffic(&self) -> Option<SecureLinkTraffic> {
+        *self.traffic.lock().unwrap()
+    }
+}
+
+fn mock_result(outcome: &MockOutcome) -> Result<(), SecureLinkClientError> {
+    match outcome {
+        MockOutcome::Ok | MockOutcome::ForcedStop => Ok(()),
+        MockOutcome::Unauthorized => Err(SecureLinkClientError::UnauthorizedError),
+        MockOutcome::NetworkError(message) => {
+            Err(SecureLinkClientError::NetworkError((*message).into()))
+        }
+        MockOutcome::CertificatePinMismatch => Err(SecureLinkClientError::CertificatePinMismatch),
+    }
+}
+
+// Hands out prepared mocks in order, or fresh ones once the queue is empty, and
+// remembers the auth token each client was created with
+#[derive(Default)]
+pub struct MockSecureLinkClientFactory {
+    prepared_clients: Mutex<VecDeque<Arc<MockSecureLinkClient>>>,
+    created_clients: Mutex<Vec<(String, Arc<MockSecureLinkClient>)>>,
+}
+
+impl MockSecureLinkClientFactory {
+    pub fn prepare(&self, client: Arc<MockSecureLinkClient>) {
+        self.prepared_clients.lock().unwrap().push_back(client);
+    }
+
+    pub fn created_clients(&self) -> Vec<(String, Arc<MockSecureLinkClient>)> {
+        self.created_clients.lock().unwrap().clone()
+    }
+
+    pub fn factory(self: &Arc<Self>) -> SecureLinkClientFactory {
+        let this = self.clone();
+
+        Box::new(move |params: SecureLinkClientParams<'_>| {
+            let client = this
+                .prepared_clients
+                .lock()
+                .unwrap()
+                .pop_front()
+                .unwrap_or_else(MockSecureLinkClient::new);
+
+            this.created_clients
+                .lock()
+                .unwrap()
+                .push((params.auth_token.expose().to_string(), client.clone()));
+
+            client
+        })
+    }
+}
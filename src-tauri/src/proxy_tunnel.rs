@@ -0,0 +1,400 @@
+use crate::proxy_route::ProxyRoute;
+use crate::server_config::ProxyServerConfig;
+use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+use base64::Engine;
+use std::io;
+use std::net::IpAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+// Upper bound for the proxy response head, a misbehaving proxy must not make
+// us buffer forever
+const MAX_HTTP_CONNECT_RESPONSE_LEN: usize = 16 * 1024;
+
+// Opens a TCP stream to the target, tunneled through the proxy if the route has one
+pub async fn open_tunnel(
+    route: &ProxyRoute,
+    target_host: &str,
+    target_port: u16,
+) -> io::Result<TcpStream> {
+    match route {
+        ProxyRoute::Direct => TcpStream::connect((target_host, target_port)).await,
+        ProxyRoute::HttpConnect(proxy_server) => {
+            http_connect(proxy_server, target_host, target_port).await
+        }
+        ProxyRoute::Socks5(proxy_server) => {
+            socks5_connect(proxy_server, target_host, target_port).await
+        }
+    }
+}
+
+async fn http_connect(
+    proxy_server: &ProxyServerConfig,
+    target_host: &str,
+    target_port: u16,
+) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect((proxy_server.host.as_str(), proxy_server.port)).await?;
+
+    let authority = match target_host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(_)) => format!("[{target_host}]:{target_port}"),
+        _ => format!("{target_host}:{target_port}"),
+    };
+
+    let mut request = format!("CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n");
+
+    if let Some(username) = &proxy_server.username {
+        let credentials = format!(
+            "{}:{}",
+            username,
+            proxy_server.password.as_deref().unwrap_or_default()
+        );
+
+        request.push_str(&format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            BASE64_STANDARD.encode(credentials)
+        ));
+    }
+
+    request.push_str("\r\n");
+
+    stream.write_all(request.as_bytes()).await?;
+
+    // Read byte by byte so nothing past the response head is consumed, the rest
+    // of the stream belongs to the tunnel
+    let mut response = Vec::new();
+
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_HTTP_CONNECT_RESPONSE_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "proxy response head is too long",
+            ));
+        }
+
+        response.push(stream.read_u8().await?);
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+
+    match status_line.split_whitespace().nth(1) {
+        Some("200") => Ok(stream),
+        _ => Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("proxy refused tunnel: {status_line}"),
+        )),
+    }
+}
+
+async fn socks5_connect(
+    proxy_server: &ProxyServerConfig,
+    target_host: &str,
+    target_port: u16,
+) -> io::Result<TcpStream> {
+    const SOCKS_VERSION: u8 = 0x05;
+    const NO_AUTHENTICATION: u8 = 0x00;
+    const USERNAME_PASSWORD: u8 = 0x02;
+    const NO_ACCEPTABLE_METHODS: u8 = 0xFF;
+
+    let mut stream = TcpStream::connect((proxy_server.host.as_str(), proxy_server.port)).await?;
+
+    let methods: &[u8] = match proxy_server.username {
+        Some(_) => &[NO_AUTHENTICATION, USERNAME_PASSWORD],
+        None => &[NO_AUTHENTICATION],
+    };
+
+    let mut greeting = vec![SOCKS_VERSION, methods.len() as u8];
+    greeting.extend_from_slice(methods);
+    stream.write_all(&greeting).await?;
+
+    let mut method_reply = [0u8; 2];
+    stream.read_exact(&mut method_reply).await?;
+
+    if method_reply[0] != SOCKS_VERSION {
+        return Err(socks5_error("proxy is not a SOCKS5 server"));
+    }
+
+    match method_reply[1] {
+        NO_AUTHENTICATION => {}
+        USERNAME_PASSWORD => {
+            let username = proxy_server.username.as_deref().unwrap_or_default();
+            let password = proxy_server.password.as_deref().unwrap_or_default();
+
+            if username.len() > 255 || password.len() > 255 {
+                return Err(socks5_error("SOCKS5 credentials are too long"));
+            }
+
+            // RFC 1929 username/password sub-negotiation
+            let mut auth_request = vec![0x01, username.len() as u8];
+            auth_request.extend_from_slice(username.as_bytes());
+            auth_request.push(password.len() as u8);
+            auth_request.extend_from_slice(password.as_bytes());
+            stream.write_all(&auth_request).await?;
+
+            let mut auth_reply = [0u8; 2];
+            stream.read_exact(&mut auth_reply).await?;
+
+            if auth_reply[1] != 0x00 {
+                return Err(socks5_error("SOCKS5 proxy rejected credentials"));
+            }
+        }
+        NO_ACCEPTABLE_METHODS => {
+            return Err(socks5_error(
+                "SOCKS5 proxy requires unsupported authentication",
+            ))
+        }
+        method => {
+            return Err(socks5_error(&format!(
+                "SOCKS5 proxy selected unknown method {method}"
+            )))
+        }
+    }
+
+    let mut connect_request = vec![SOCKS_VERSION, 0x01, 0x00];
+
+    match target_host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(address)) => {
+            connect_request.push(0x01);
+            connect_request.extend_from_slice(&address.octets());
+        }
+        Ok(IpAddr::V6(address)) => {
+            connect_request.push(0x04);
+            connect_request.extend_from_slice(&address.octets());
+        }
+        Err(_) => {
+            if target_host.len() > 255 {
+                return Err(socks5_error("target host name is too long"));
+            }
+
+            // Let the proxy resolve the name, the client may not see the DNS
+            connect_request.push(0x03);
+            connect_request.push(target_host.len() as u8);
+            connect_request.extend_from_slice(target_host.as_bytes());
+        }
+    }
+
+    connect_request.extend_from_slice(&target_port.to_be_bytes());
+    stream.write_all(&connect_request).await?;
+
+    let mut connect_reply = [0u8; 4];
+    stream.read_exact(&mut connect_reply).await?;
+
+    if connect_reply[1] != 0x00 {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("SOCKS5 proxy refused tunnel with code {}", connect_reply[1]),
+        ));
+    }
+
+    // Skip the bound address, it is of no use to us
+    let bound_address_len = match connect_reply[3] {
+        0x01 => 4,
+        0x04 => 16,
+        0x03 => stream.read_u8().await? as usize,
+        address_type => {
+            return Err(socks5_error(&format!(
+                "SOCKS5 proxy replied with unknown address type {address_type}"
+            )))
+        }
+    };
+
+    let mut bound_address = vec![0u8; bound_address_len + 2];
+    stream.read_exact(&mut bound_address).await?;
+
+    Ok(stream)
+}
+
+fn socks5_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+
+    // Target that echoes everything back, standing in for the secure link server
+    async fn spawn_echo_server() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = stream.split();
+                    let _ = tokio::io::copy(&mut reader, &mut writer).await;
+                });
+            }
+        });
+
+        port
+    }
+
+    // HTTP CONNECT proxy stand-in, records the request head it received
+    async fn spawn_http_connect_proxy(status_line: &'static str) -> (u16, Arc<Mutex<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let request_head = Arc::new(Mutex::new(String::new()));
+        let request_head_clone = request_head.clone();
+
+        tokio::spawn(async move {
+            let (mut client, _) = listener.accept().await.unwrap();
+
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                head.push(client.read_u8().await.unwrap());
+            }
+
+            let head = String::from_utf8(head).unwrap();
+            *request_head_clone.lock().unwrap() = head.clone();
+
+            client
+                .write_all(format!("{status_line}\r\n\r\n").as_bytes())
+                .await
+                .unwrap();
+
+            if !status_line.contains(" 200 ") {
+                return;
+            }
+
+            let authority = head.split_whitespace().nth(1).unwrap().to_string();
+            let mut target = TcpStream::connect(authority).await.unwrap();
+            let _ = tokio::io::copy_bidirectional(&mut client, &mut target).await;
+        });
+
+        (port, request_head)
+    }
+
+    // SOCKS5 proxy stand-in, records the requested target and credentials
+    async fn spawn_socks5_proxy() -> (u16, Arc<Mutex<Option<(String, String, u16)>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let seen = Arc::new(Mutex::new(None));
+        let seen_clone = seen.clone();
+
+        tokio::spawn(async move {
+            let (mut client, _) = listener.accept().await.unwrap();
+
+            let mut greeting = [0u8; 2];
+            client.read_exact(&mut greeting).await.unwrap();
+            let mut methods = vec![0u8; greeting[1] as usize];
+            client.read_exact(&mut methods).await.unwrap();
+            assert!(methods.contains(&0x02));
+            client.write_all(&[0x05, 0x02]).await.unwrap();
+
+            let mut auth_header = [0u8; 2];
+            client.read_exact(&mut auth_header).await.unwrap();
+            let mut username = vec![0u8; auth_header[1] as usize];
+            client.read_exact(&mut username).await.unwrap();
+            let password_len = client.read_u8().await.unwrap();
+            let mut password = vec![0u8; password_len as usize];
+            client.read_exact(&mut password).await.unwrap();
+            client.write_all(&[0x01, 0x00]).await.unwrap();
+
+            let mut request = [0u8; 4];
+            client.read_exact(&mut request).await.unwrap();
+            assert_eq!(request[3], 0x03);
+            let host_len = client.read_u8().await.unwrap();
+            let mut host = vec![0u8; host_len as usize];
+            client.read_exact(&mut host).await.unwrap();
+            let target_port = client.read_u16().await.unwrap();
+
+            let host = String::from_utf8(host).unwrap();
+            *seen_clone.lock().unwrap() = Some((
+                host.clone(),
+                format!(
+                    "{}:{}",
+                    String::from_utf8(username).unwrap(),
+                    String::from_utf8(password).unwrap()
+                ),
+                target_port,
+            ));
+
+            let mut target = TcpStream::connect((host.as_str(), target_port))
+                .await
+                .unwrap();
+
+            client
+                .write_all(&[0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0, 0])
+                .await
+                .unwrap();
+
+            let _ = tokio::io::copy_bidirectional(&mut client, &mut target).await;
+        });
+
+        (port, seen)
+    }
+
+    async fn assert_tunnel_echoes(mut stream: TcpStream) {
+        stream.write_all(b"secure link").await.unwrap();
+
+        let mut echoed = [0u8; 11];
+        stream.read_exact(&mut echoed).await.unwrap();
+
+        assert_eq!(&echoed, b"secure link");
+    }
+
+    #[tokio::test]
+    async fn http_connect_tunnel_goes_through_proxy() {
+        let target_port = spawn_echo_server().await;
+        let (proxy_port, request_head) =
+            spawn_http_connect_proxy("HTTP/1.1 200 Connection established").await;
+
+        let route = ProxyRoute::HttpConnect(ProxyServerConfig {
+            host: "127.0.0.1".to_string(),
+            port: proxy_port,
+            username: Some("user".to_string()),
+            password: Some("pass".to_string()),
+        });
+
+        let stream = open_tunnel(&route, "127.0.0.1", target_port).await.unwrap();
+        assert_tunnel_echoes(stream).await;
+
+        let request_head = request_head.lock().unwrap().clone();
+        assert!(request_head.starts_with(&format!("CONNECT 127.0.0.1:{target_port} HTTP/1.1\r\n")));
+        assert!(request_head.contains("Proxy-Authorization: Basic dXNlcjpwYXNz\r\n"));
+    }
+
+    #[tokio::test]
+    async fn http_connect_refused_tunnel_is_an_error() {
+        let (proxy_port, _) =
+            spawn_http_connect_proxy("HTTP/1.1 407 Proxy Authentication Required").await;
+
+        let route = ProxyRoute::HttpConnect(ProxyServerConfig {
+            host: "127.0.0.1".to_string(),
+            port: proxy_port,
+            username: None,
+            password: None,
+        });
+
+        let error = open_tunnel(&route, "127.0.0.1", 1).await.unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+    }
+
+    #[tokio::test]
+    async fn socks5_tunnel_goes_through_proxy() {
+        let target_port = spawn_echo_server().await;
+        let (proxy_port, seen) = spawn_socks5_proxy().await;
+
+        let route = ProxyRoute::Socks5(ProxyServerConfig {
+            host: "127.0.0.1".to_string(),
+            port: proxy_port,
+            username: Some("user".to_string()),
+            password: Some("pass".to_string()),
+        });
+
+        let stream = open_tunnel(&route, "localhost", target_port).await.unwrap();
+        assert_tunnel_echoes(stream).await;
+
+        assert_eq!(
+            seen.lock().unwrap().clone(),
+            Some((
+                "localhost".to_string(),
+                "user:pass".to_string(),
+                target_port
+            ))
+        );
+    }
+}
@@ -1,4 +1,9 @@
+use crate::auth_token::AuthToken;
+use crate::bandwidth_limit::BandwidthLimiter;
+use crate::client_errors::ClientErrorLog;
+use crate::server_config::ServerConfig;
 use async_trait::async_trait;
+use std::sync::Arc;
 
 #[derive(thiserror::Error, Debug)]
 pub enum SecureLinkClientError {
@@ -9,23 +14,94 @@ pub enum SecureLinkClientError {
     #[error("ServiceError")]
     ServiceError(Box<dyn std::error::Error>),
 
+    // The service runs the session of the profile with this id
+    #[cfg(feature = "secure-link-windows-service-client")]
+    #[error("ServiceBusy")]
+    ServiceBusy(String),
+
     #[error("NetworkError")]
     NetworkError(Box<dyn std::error::Error>),
+
+    #[error("CertificatePinMismatch")]
+    CertificatePinMismatch,
+
+    #[error("TrustConfigError")]
+    TrustConfigError(Box<dyn std::error::Error>),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum SecureLinkClientState {
     Running,
     Pending,
+    // Stop was requested, the session has not ended yet
+    Stopping,
     Stopped,
 }
 
+// `Forced` means the session did not end within the stop timeout and was
+// aborted
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SecureLinkClientStopOutcome {
+    Clean,
+    Forced,
+}
+
+// Why the last running session ended
+#[derive(Debug, Clone, PartialEq)]
+pub enum SecureLinkClientStopReason {
+    UserStop,
+    ServerClose,
+    NetworkError,
+    // Disconnected after no traffic passed for the configured idle timeout
+    IdleTimeout,
+}
+
+// Bytes moved through the link in the current or last session
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecureLinkTraffic {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
 #[async_trait]
 
 pub trait SecureLinkClient: Send + Sync {
     async fn start(&self) -> Result<(), SecureLinkClientError>;
 
-    async fn stop(&self) -> Result<(), SecureLinkClientError>;
+    // Returns once the session has ended
+    async fn stop(&self) -> Result<SecureLinkClientStopOutcome, SecureLinkClientError>;
 
     async fn status(&self) -> Result<SecureLinkClientState, SecureLinkClientError>;
+
+    // Backends that can not tell why a session ended report `None`
+    async fn last_stop_reason(&self) -> Option<SecureLinkClientStopReason> {
+        None
+    }
+
+    // `None` for backends that can not see the link traffic
+    async fn traffic(&self) -> Option<SecureLinkTraffic> {
+        None
+    }
+}
+
+// Everything needed to create a client for the current auth token and server
+pub struct SecureLinkClientParams<'a> {
+    pub auth_token: &'a AuthToken,
+    pub secure_link_server_host: &'a str,
+    pub secure_link_server_port: u16,
+    pub server_config: &'a ServerConfig,
+    // Shared with the app, which changes the limits at runtime
+    pub bandwidth_limiter: &'a Arc<BandwidthLimiter>,
+    // Of the link, clients record why their sessions ended
+    pub error_log: &'a Arc<ClientErrorLog>,
+    // Separate for every link
+    #[cfg(feature = "secure-link-windows-service-client")]
+    pub service_log_file_path: &'a std::path::Path,
+    #[cfg(feature = "secure-link-windows-service-client")]
+    pub profile_id: &'a str,
 }
+
+pub type SecureLinkClientFactory =
+    Box<dyn Fn(SecureLinkClientParams<'_>) -> Arc<dyn SecureLinkClient> + Send + Sync>;
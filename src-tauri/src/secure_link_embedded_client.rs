@@ -1,6 +1,17 @@
-use crate::secure_link_client::{SecureLinkClient, SecureLinkClientError, SecureLinkClientState};
+use crate::auth_token::AuthToken;
+use crate::bandwidth_limit::BandwidthLimiter;
+use crate::client_errors::{ClientErrorCategory, ClientErrorLog};
+use crate::proxy_route;
+use crate::proxy_tunnel;
+use crate::secure_link_client::{
+    SecureLinkClient, SecureLinkClientError, SecureLinkClientState, SecureLinkClientStopOutcome,
+    SecureLinkClientStopReason, SecureLinkTraffic,
+};
+use crate::server_config::ServerConfig;
+use crate::server_trust;
+use crate::traffic_meter::{MeteredStream, TrafficCounters};
 use async_trait::async_trait;
-use log::error;
+use log::{error, warn};
 use secure_link_client::{SecureLink, SecureLinkError};
 use std::sync::Arc;
 use std::sync::Mutex;
@@ -10,26 +21,41 @@ pub struct SecureLinkEmbeddedClient {
 }
 
 struct SecureLinkEmbeddedClientInner {
-    auth_token: String,
+    auth_token: AuthToken,
     secure_link_server_host: String,
     secure_link_server_port: u16,
+    server_config: ServerConfig,
     shutdown_sender: Mutex<Option<tokio::sync::mpsc::UnboundedSender<()>>>,
+    message_loop_task: Mutex<Option<tokio::task::JoinHandle<()>>>,
     current_state: Arc<Mutex<SecureLinkClientState>>,
+    last_stop_reason: Arc<Mutex<Option<SecureLinkClientStopReason>>>,
+    traffic_counters: Arc<TrafficCounters>,
+    bandwidth_limiter: Arc<BandwidthLimiter>,
+    error_log: Arc<ClientErrorLog>,
 }
 
 impl SecureLinkEmbeddedClient {
     pub fn new(
-        auth_token: &str,
+        auth_token: &AuthToken,
         secure_link_server_host: &str,
         secure_link_server_port: u16,
+        server_config: &ServerConfig,
+        bandwidth_limiter: Arc<BandwidthLimiter>,
+        error_log: Arc<ClientErrorLog>,
     ) -> Self {
         Self {
             inner: Arc::new(SecureLinkEmbeddedClientInner {
-                auth_token: auth_token.to_string(),
+                auth_token: auth_token.clone(),
                 secure_link_server_host: secure_link_server_host.to_string(),
                 secure_link_server_port,
+                server_config: server_config.clone(),
                 shutdown_sender: Mutex::new(None),
+                message_loop_task: Mutex::new(None),
                 current_state: Arc::new(Mutex::new(SecureLinkClientState::Stopped)),
+                last_stop_reason: Arc::new(Mutex::new(None)),
+                traffic_counters: Arc::new(TrafficCounters::default()),
+                bandwidth_limiter,
+                error_log,
             }),
         }
     }
@@ -41,13 +67,21 @@ impl SecureLinkClient for SecureLinkEmbeddedClient {
         self.inner.start().await
     }
 
-    async fn stop(&self) -> Result<(), SecureLinkClientError> {
+    async fn stop(&self) -> Result<SecureLinkClientStopOutcome, SecureLinkClientError> {
         self.inner.stop().await
     }
 
     async fn status(&self) -> Result<SecureLinkClientState, SecureLinkClientError> {
         Ok(self.inner.current_state.lock().unwrap().clone())
     }
+
+    async fn last_stop_reason(&self) -> Option<SecureLinkClientStopReason> {
+        self.inner.last_stop_reason.lock().unwrap().clone()
+    }
+
+    async fn traffic(&self) -> Option<SecureLinkTraffic> {
+        Some(self.inner.traffic_counters.snapshot())
+    }
 }
 
 impl SecureLinkEmbeddedClientInner {
@@ -62,10 +96,15 @@ impl SecureLinkEmbeddedClientInner {
                 SecureLinkClientState::Pending => {
                     return Ok(());
                 }
+                SecureLinkClientState::Stopping => {
+                    return Ok(());
+                }
                 SecureLinkClientState::Stopped => {}
             }
 
             *current_state_ref = SecureLinkClientState::Pending;
+            *self.last_stop_reason.lock().unwrap() = None;
+            self.traffic_counters.reset();
 
             let (shutdown_tx, shutdown_rx) = tokio::sync::mpsc::unbounded_channel();
 
@@ -74,17 +113,14 @@ impl SecureLinkEmbeddedClientInner {
             shutdown_rx
         };
 
-        let connect_to_global_channel_future = SecureLink::connect_to_global_channel(
-            &self.secure_link_server_host,
-            self.secure_link_server_port,
-            &self.auth_token,
-        );
+        let connect_to_global_channel_future = self.connect_to_global_channel();
 
         let current_state_ref_clone = { self.current_state.clone() };
 
         let global_channel_connect_result = tokio::select! {
             _ = shutdown_rx.recv() => {
 
+                *self.last_stop_reason.lock().unwrap() = Some(SecureLinkClientStopReason::UserStop);
                 *current_state_ref_clone.lock().unwrap() = SecureLinkClientState::Stopped;
                 return Ok(())
             }
@@ -105,52 +141,145 @@ impl SecureLinkEmbeddedClientInner {
             Err(err) => {
                 *self.current_state.lock().unwrap() = SecureLinkClientState::Stopped;
 
-                return match err {
-                    SecureLinkError::UnauthorizedError => {
-                        Err(SecureLinkClientError::UnauthorizedError)
-                    }
-                    _ => Err(SecureLinkClientError::NetworkError(Box::new(err))),
-                };
+                return Err(err);
             }
         };
 
         let current_state_ref_clone = self.current_state.clone();
+        let last_stop_reason_ref_clone = self.last_stop_reason.clone();
+        let error_log = self.error_log.clone();
 
         // Spawn the main loop
-        tokio::spawn(async move {
-            
-            tokio::select! {
-            
-                 _ = shutdown_rx.recv() => {
-                     // Shutdown requested
-                 }
-                 result = secure_link.run_message_loop() => {
-            
-                     if let Err(err) = result {
-                           error!("Secure link main loop ended with error {err}");
-                     }
-            
-                 }
-            
-             }
+        let message_loop_task = tokio::spawn(async move {
+            let stop_reason = tokio::select! {
+
+                _ = shutdown_rx.recv() => {
+                    // Shutdown requested
+                    SecureLinkClientStopReason::UserStop
+                }
+                result = secure_link.run_message_loop() => {
 
+                    match result {
+                        Ok(()) => {
+                            error_log.record(
+                                ClientErrorCategory::ServerClosed,
+                                "Server closed the connection".to_string(),
+                            );
+                            SecureLinkClientStopReason::ServerClose
+                        }
+                        Err(err) => {
+                            error!("Secure link main loop ended with error {err}");
+                            error_log.record(ClientErrorCategory::Network, err.to_string());
+                            SecureLinkClientStopReason::NetworkError
+                        }
+                    }
+
+                }
+
+            };
+
+            *last_stop_reason_ref_clone.lock().unwrap() = Some(stop_reason);
             *current_state_ref_clone.lock().unwrap() = SecureLinkClientState::Stopped
         });
 
+        *self.message_loop_task.lock().unwrap() = Some(message_loop_task);
+
         Ok(())
     }
 
-    async fn stop(&self) -> Result<(), SecureLinkClientError> {
-        // Send shutdown signal
+    async fn connect_to_global_channel(&self) -> Result<SecureLink, SecureLinkClientError> {
+        let proxy_route = proxy_route::resolve_proxy_route(
+            &self.server_config.proxy,
+            &self.secure_link_server_host,
+        );
+
+        let server_tls = server_trust::build_server_tls(&self.server_config.trust)
+            .map_err(SecureLinkClientError::TrustConfigError)?;
+
+        // Direct connections go through `open_tunnel` too, so the traffic of
+        // every session is metered
+        let stream = proxy_tunnel::open_tunnel(
+            &proxy_route,
+            &self.secure_link_server_host,
+            self.secure_link_server_port,
+        )
+        .await
+        .map_err(|err| SecureLinkClientError::NetworkError(Box::new(err)))?;
+
+        // TLS and the global channel handshake run inside the tunnel, the
+        // proxy only sees the server name it was asked to connect to
+        let connect_result = SecureLink::connect_to_global_channel_over_stream(
+            MeteredStream::new(
+                stream,
+                self.traffic_counters.clone(),
+                self.bandwidth_limiter.clone(),
+            ),
+            &self.secure_link_server_host,
+            self.auth_token.expose(),
+            server_tls
+                .as_ref()
+                .map(|server_tls| server_tls.tls_config.clone()),
+        )
+        .await;
+
+        if connect_result.is_err()
+            && server_tls
+                .as_ref()
+                .is_some_and(|server_tls| server_tls.is_pin_mismatch())
+        {
+            return Err(SecureLinkClientError::CertificatePinMismatch);
+        }
+
+        connect_result.map_err(|err| match err {
+            SecureLinkError::UnauthorizedError => SecureLinkClientError::UnauthorizedError,
+            _ => SecureLinkClientError::NetworkError(Box::new(err)),
+        })
+    }
+
+    async fn stop(&self) -> Result<SecureLinkClientStopOutcome, SecureLinkClientError> {
         let sender = {
             let mut sender_guard = self.shutdown_sender.lock().unwrap();
             sender_guard.take()
         };
 
+        let message_loop_task = { self.message_loop_task.lock().unwrap().take() };
+
+        if message_loop_task.is_some() {
+            let mut current_state = self.current_state.lock().unwrap();
+
+            if *current_state == SecureLinkClientState::Running {
+                *current_state = SecureLinkClientState::Stopping;
+            }
+        }
+
+        // Send shutdown signal
         if let Some(sender) = sender {
             let _ = sender.send(()); // Ignore send errors (receiver might be dropped)
         }
 
-        Ok(())
+        // A connect in progress ends on the signal inside `start`
+        let Some(mut message_loop_task) = message_loop_task else {
+            return Ok(SecureLinkClientStopOutcome::Clean);
+        };
+
+        let stop_timeout = self.server_config.stop_timeout();
+
+        if tokio::time::timeout(stop_timeout, &mut message_loop_task)
+            .await
+            .is_ok()
+        {
+            return Ok(SecureLinkClientStopOutcome::Clean);
+        }
+
+        warn!("Secure link message loop did not stop within {stop_timeout:?}, aborting it");
+
+        message_loop_task.abort();
+        let _ = message_loop_task.await;
+
+        // The aborted loop never got to record its end
+        *self.last_stop_reason.lock().unwrap() = Some(SecureLinkClientStopReason::UserStop);
+        *self.current_state.lock().unwrap() = SecureLinkClientState::Stopped;
+
+        Ok(SecureLinkClientStopOutcome::Forced)
     }
 }
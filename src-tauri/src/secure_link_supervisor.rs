@@ -0,0 +1,475 @@
+use crate::secure_link_client::{
+    SecureLinkClient, SecureLinkClientError, SecureLinkClientState, SecureLinkClientStopOutcome,
+    SecureLinkClientStopReason, SecureLinkTraffic,
+};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+
+// `SecureLinkClientError` holds non-`Send` errors, so replies carry this
+// summary instead
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum SecureLinkSupervisorError {
+    #[error("No auth token")]
+    NoAuthToken,
+
+    #[error("Auth token storage error: {0}")]
+    AuthTokenStorage(String),
+
+    #[error("UnauthorizedError")]
+    Unauthorized,
+
+    #[error("CertificatePinMismatch")]
+    CertificatePinMismatch,
+
+    #[error("NetworkError: {0}")]
+    Network(String),
+
+    #[error("TrustConfigError: {0}")]
+    TrustConfig(String),
+
+    // Errors of the Windows service
+    #[cfg(feature = "secure-link-windows-service-client")]
+    #[error("{0}")]
+    Other(String),
+
+    #[error("Secure link supervisor is not running")]
+    SupervisorGone,
+}
+
+impl From<SecureLinkClientError> for SecureLinkSupervisorError {
+    fn from(error: SecureLinkClientError) -> Self {
+        match error {
+            SecureLinkClientError::UnauthorizedError => SecureLinkSupervisorError::Unauthorized,
+            SecureLinkClientError::CertificatePinMismatch => {
+                SecureLinkSupervisorError::CertificatePinMismatch
+            }
+            SecureLinkClientError::NetworkError(error) => {
+                SecureLinkSupervisorError::Network(error.to_string())
+            }
+            SecureLinkClientError::TrustConfigError(error) => {
+                SecureLinkSupervisorError::TrustConfig(error.to_string())
+            }
+            #[cfg(feature = "secure-link-windows-service-client")]
+            SecureLinkClientError::ServiceError(error) => {
+                SecureLinkSupervisorError::Other(format!("Service error: {}", error))
+            }
+            #[cfg(feature = "secure-link-windows-service-client")]
+            SecureLinkClientError::ServiceBusy(profile_id) => SecureLinkSupervisorError::Other(
+                format!("The service runs the link {}", profile_id),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SecureLinkClientStatus {
+    pub state: SecureLinkClientState,
+    pub last_stop_reason: Option<SecureLinkClientStopReason>,
+    pub traffic: Option<SecureLinkTraffic>,
+}
+
+// Application side of the supervisor: creating clients and keeping the
+// connection history
+pub trait SupervisorContext: Send + 'static {
+    // `None` when there is no auth token yet
+    fn create_client(&self)
+        -> Result<Option<Arc<dyn SecureLinkClient>>, SecureLinkSupervisorError>;
+
+    fn start_attempted(&self);
+
+    fn start_finished(&self, result: &Result<(), SecureLinkSupervisorError>);
+
+    // Called after a failed start. Returning `true` makes the supervisor
+    // create a new client, e.g. for the next server endpoint, and start it
+    // for the same callers.
+    fn fail_over(&self, _error: &SecureLinkSupervisorError) -> bool {
+        false
+    }
+
+    // `reason` is the one the stop was requested with, `traffic` the totals of
+    // the session that ended
+    fn stopped(&self, reason: SecureLinkClientStopReason, traffic: Option<SecureLinkTraffic>);
+}
+
+type Reply<T> = oneshot::Sender<Result<T, SecureLinkSupervisorError>>;
+
+pub enum SupervisorRequest {
+    Start(Reply<()>),
+    Stop(
+        SecureLinkClientStopReason,
+        Reply<SecureLinkClientStopOutcome>,
+    ),
+    // Stops and drops the current client, the next one picks up the stored
+    // auth token and server config
+    Reconfigure(Reply<()>),
+    Status(Reply<SecureLinkClientStatus>),
+}
+
+// Handle to the supervisor task, the only owner of the secure link client.
+// Commands, the tray, the scheduler and command line actions all go through
+// it, so lifecycle changes never interleave.
+#[derive(Clone)]
+pub struct SecureLinkSupervisor {
+    sender: mpsc::UnboundedSender<SupervisorRequest>,
+}
+
+impl SecureLinkSupervisor {
+    // The receiver goes to `run_secure_link_supervisor`
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<SupervisorRequest>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        (Self { sender }, receiver)
+    }
+
+    pub async fn start(&self) -> Result<(), SecureLinkSupervisorError> {
+        self.request(SupervisorRequest::Start).await
+    }
+
+    pub async fn stop(&self) -> Result<SecureLinkClientStopOutcome, SecureLinkSupervisorError> {
+        self.stop_with_reason(SecureLinkClientStopReason::UserStop)
+            .await
+    }
+
+    // Stops for a reason other than the user asking, e.g. the idle timeout
+    pub async fn stop_with_reason(
+        &self,
+        reason: SecureLinkClientStopReason,
+    ) -> Result<SecureLinkClientStopOutcome, SecureLinkSupervisorError> {
+        self.request(|reply| SupervisorRequest::Stop(reason, reply))
+            .await
+    }
+
+    pub async fn reconfigure(&self) -> Result<(), SecureLinkSupervisorError> {
+        self.request(SupervisorRequest::Reconfigure).await
+    }
+
+    pub async fn status(&self) -> Result<SecureLinkClientStatus, SecureLinkSupervisorError> {
+        self.request(SupervisorRequest::Status).await
+    }
+
+    async fn request<T>(
+        &self,
+        request: impl FnOnce(Reply<T>) -> SupervisorRequest,
+    ) -> Result<T, SecureLinkSupervisorError> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+
+        self.sender
+            .send(request(reply_sender))
+            .map_err(|_| SecureLinkSupervisorError::SupervisorGone)?;
+
+        reply_receiver
+            .await
+            .map_err(|_| SecureLinkSupervisorError::SupervisorGone)?
+    }
+}
+
+type BoxedFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+struct InFlightStart {
+    future: BoxedFuture<Result<(), SecureLinkSupervisorError>>,
+    replies: Vec<Reply<()>>,
+}
+
+enum StopReply {
+    Stop(Reply<SecureLinkClientStopOutcome>),
+    Reconfigure(Reply<()>),
+}
+
+// The traffic is read once the client stopped, so it covers the whole session
+type StopResult =
+    Result<(SecureLinkClientStopOutcome, Option<SecureLinkTraffic>), SecureLinkSupervisorError>;
+
+struct InFlightStop {
+    // Joining stops keep the reason of the first one
+    reason: SecureLinkClientStopReason,
+    future: BoxedFuture<StopResult>,
+    // Set once the client stopped, while a cancelled start is still winding down
+    result: Option<StopResult>,
+    replies: Vec<StopReply>,
+}
+
+struct Supervisor<C: SupervisorContext> {
+    context: C,
+    client: Option<Arc<dyn SecureLinkClient>>,
+    start: Option<InFlightStart>,
+    stop: Option<InFlightStop>,
+    // Reason of the last stop that went through the supervisor, reported
+    // instead of the plain `UserStop` the client knows about
+    last_stop_reason: Option<SecureLinkClientStopReason>,
+    // Requests that have to wait for the start or stop in flight
+    deferred: VecDeque<SupervisorRequest>,
+}
+
+// Handles one request at a time. Starts and stops run in the background so
+// status requests are still answered, e.g. with `Pending` while connecting,
+// and a stop can cancel a start in flight. Anything else waits for them.
+pub async fn run_secure_link_supervisor<C: SupervisorContext>(
+    mut receiver: mpsc::UnboundedReceiver<SupervisorRequest>,
+    context: C,
+) {
+    let mut supervisor = Supervisor {
+        context,
+        client: None,
+        start: None,
+        stop: None,
+        last_stop_reason: None,
+        deferred: VecDeque::new(),
+    };
+
+    loop {
+        let start_future = supervisor.start.as_mut().map(|start| &mut start.future);
+        let stop_future = supervisor
+            .stop
+            .as_mut()
+            .filter(|stop| stop.result.is_none())
+            .map(|stop| &mut stop.future);
+
+        tokio::select! {
+            result = in_flight(start_future) => supervisor.start_finished(result),
+            result = in_flight(stop_future) => supervisor.stop_finished(result),
+            request = receiver.recv() => match request {
+                Some(request) => supervisor.handle(request).await,
+                None => break,
+            }
+        }
+
+        supervisor.run_deferred().await;
+    }
+}
+
+// Never resolves when nothing is in flight
+async fn in_flight<T>(future: Option<&mut BoxedFuture<T>>) -> T {
+    match future {
+        Some(future) => future.await,
+        None => std::future::pending().await,
+    }
+}
+
+impl<C: SupervisorContext> Supervisor<C> {
+    async fn handle(&mut self, request: SupervisorRequest) {
+        match request {
+            SupervisorRequest::Status(reply) => {
+                let _ = reply.send(self.status().await);
+            }
+            SupervisorRequest::Start(reply) => {
+                if self.stop.is_some() {
+                    self.deferred.push_back(SupervisorRequest::Start(reply));
+                } else if let Some(start) = &mut self.start {
+                    start.replies.push(reply);
+                } else {
+                    self.begin_start(reply);
+                }
+            }
+            SupervisorRequest::Stop(reason, reply) => match &mut self.stop {
+                Some(stop) => stop.replies.push(StopReply::Stop(reply)),
+                None => match self.client.clone() {
+                    Some(client) => self.begin_stop(client, reason, StopReply::Stop(reply)),
+                    None => {
+                        let _ = reply.send(Ok(SecureLinkClientStopOutcome::Clean));
+                    }
+                },
+            },
+            SupervisorRequest::Reconfigure(reply) => {
+                if self.stop.is_some() {
+                    self.deferred
+                        .push_back(SupervisorRequest::Reconfigure(reply));
+                    return;
+                }
+
+                match self.client.clone() {
+                    Some(client) => self.begin_stop(
+                        client,
+                        SecureLinkClientStopReason::UserStop,
+                        StopReply::Reconfigure(reply),
+                    ),
+                    None => {
+                        let _ = reply.send(self.recreate_client());
+                    }
+                }
+            }
+        }
+    }
+
+    async fn run_deferred(&mut self) {
+        while self.stop.is_none() {
+            let Some(request) = self.deferred.pop_front() else {
+                return;
+            };
+
+            self.handle(request).await;
+        }
+    }
+
+    async fn status(&mut self) -> Result<SecureLinkClientStatus, SecureLinkSupervisorError> {
+        let Some(client) = self.ensure_client()? else {
+            return Ok(SecureLinkClientStatus {
+                state: SecureLinkClientState::Stopped,
+                last_stop_reason: None,
+                traffic: None,
+            });
+        };
+
+        let state = client
+            .status()
+            .await
+            .map_err(SecureLinkSupervisorError::from)?;
+
+        let last_stop_reason = match client.last_stop_reason().await {
+            Some(SecureLinkClientStopReason::UserStop) => self
+                .last_stop_reason
+                .clone()
+                .or(Some(SecureLinkClientStopReason::UserStop)),
+            last_stop_reason => last_stop_reason,
+        };
+
+        Ok(SecureLinkClientStatus {
+            state,
+            last_stop_reason,
+            traffic: client.traffic().await,
+        })
+    }
+
+    fn ensure_client(
+        &mut self,
+    ) -> Result<Option<Arc<dyn SecureLinkClient>>, SecureLinkSupervisorError> {
+        if self.client.is_none() {
+            self.client = self.context.create_client()?;
+        }
+
+        Ok(self.client.clone())
+    }
+
+    fn recreate_client(&mut self) -> Result<(), SecureLinkSupervisorError> {
+        self.client = None;
+        self.ensure_client()?;
+
+        Ok(())
+    }
+
+    fn begin_start(&mut self, reply: Reply<()>) {
+        let client = match self.ensure_client() {
+            Ok(Some(client)) => client,
+            Ok(None) => {
+                let _ = reply.send(Err(SecureLinkSupervisorError::NoAuthToken));
+                return;
+            }
+            Err(e) => {
+                let _ = reply.send(Err(e));
+                return;
+            }
+        };
+
+        self.last_stop_reason = None;
+        self.start_client(client, vec![reply]);
+    }
+
+    fn start_client(&mut self, client: Arc<dyn SecureLinkClient>, replies: Vec<Reply<()>>) {
+        self.context.start_attempted();
+
+        self.start = Some(InFlightStart {
+            future: Box::pin(async move {
+                client
+                    .start()
+                    .await
+                    .map_err(SecureLinkSupervisorError::from)
+            }),
+            replies,
+        });
+    }
+
+    fn start_finished(&mut self, result: Result<(), SecureLinkSupervisorError>) {
+        let Some(start) = self.start.take() else {
+            return;
+        };
+
+        self.context.start_finished(&result);
+
+        // Nobody asked to stop in the meantime, so the callers get the
+        // result of the next endpoint instead
+        if let Err(e) = &result {
+            if self.stop.is_none() && self.context.fail_over(e) {
+                self.client = None;
+
+                if let Ok(Some(client)) = self.ensure_client() {
+                    self.start_client(client, start.replies);
+                    return;
+                }
+            }
+        }
+
+        for reply in start.replies {
+            let _ = reply.send(result.clone());
+        }
+
+        // A stop that cancelled this start is finished only now, so the
+        // session ends after it was recorded as started
+        if let Some(result) = self.stop.as_mut().and_then(|stop| stop.result.take()) {
+            self.stop_finished(result);
+        }
+    }
+
+    fn begin_stop(
+        &mut self,
+        client: Arc<dyn SecureLinkClient>,
+        reason: SecureLinkClientStopReason,
+        reply: StopReply,
+    ) {
+        self.stop = Some(InFlightStop {
+            reason,
+            future: Box::pin(async move {
+                let outcome = client.stop().await?;
+
+                Ok((outcome, client.traffic().await))
+            }),
+            result: None,
+            replies: vec![reply],
+        });
+    }
+
+    fn stop_finished(&mut self, result: StopResult) {
+        if self.start.is_some() {
+            if let Some(stop) = &mut self.stop {
+                stop.result = Some(result);
+            }
+
+            return;
+        }
+
+        let Some(stop) = self.stop.take() else {
+            return;
+        };
+
+        let result = result.map(|(outcome, traffic)| {
+            self.context.stopped(stop.reason.clone(), traffic);
+            self.last_stop_reason = Some(stop.reason.clone());
+
+            outcome
+        });
+
+        let is_reconfigure = stop
+            .replies
+            .iter()
+            .any(|reply| matches!(reply, StopReply::Reconfigure(_)));
+
+        // A failed stop keeps the old client, as it may still be connected
+        let reconfigure_result = match &result {
+            Ok(_) if is_reconfigure => self.recreate_client(),
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.clone()),
+        };
+
+        for reply in stop.replies {
+            match reply {
+                StopReply::Stop(reply) => {
+                    let _ = reply.send(result.clone());
+                }
+                StopReply::Reconfigure(reply) => {
+                    let _ = reply.send(reconfigure_result.clone());
+                }
+            }
+        }
+    }
+}
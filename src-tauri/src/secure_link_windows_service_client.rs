@@ -1,36 +1,103 @@
-use crate::secure_link_client::{SecureLinkClient, SecureLinkClientError, SecureLinkClientState};
+use crate::auth_token::AuthToken;
+use crate::secure_link_client::{
+    SecureLinkClient, SecureLinkClientError, SecureLinkClientState, SecureLinkClientStopOutcome,
+};
+use crate::settings::DEFAULT_PROFILE_ID;
 use async_trait::async_trait;
 use secure_link_windows_service_manager::{SecureLinkServiceError, ServiceState};
+use std::sync::Mutex;
+
+// The service runs one session for the whole machine, so only one link can be
+// connected through it at a time
+static SERVICE_OWNER: ServiceOwner = ServiceOwner::new();
+
 pub struct SecureLinkWindowsServiceClient {
     secure_link_server_host: String,
     secure_link_server_port: u16,
-    auth_token: String,
+    auth_token: AuthToken,
     service_log_file_path: String,
+    profile_id: String,
 }
 
 impl SecureLinkWindowsServiceClient {
     pub fn new(
         secure_link_server_host: &str,
         secure_link_server_port: u16,
-        auth_token: &str,
+        auth_token: &AuthToken,
         service_log_file_path: &str,
+        profile_id: &str,
     ) -> Self {
         SecureLinkWindowsServiceClient {
             secure_link_server_host: secure_link_server_host.to_string(),
             secure_link_server_port,
-            auth_token: auth_token.to_string(),
+            auth_token: auth_token.clone(),
             service_log_file_path: service_log_file_path.to_string(),
+            profile_id: profile_id.to_string(),
+        }
+    }
+}
+
+// The link whose session the service runs. A service found running at launch
+// was started for the default link, which is the one it has the token of.
+struct ServiceOwner(Mutex<Option<String>>);
+
+impl ServiceOwner {
+    const fn new() -> Self {
+        Self(Mutex::new(None))
+    }
+
+    fn is_owned_by(&self, profile_id: &str) -> bool {
+        let owner = self.0.lock().unwrap();
+
+        owner.as_deref().unwrap_or(DEFAULT_PROFILE_ID) == profile_id
+    }
+
+    // Takes the service over unless another link has it running. Returns the
+    // profile id of that link otherwise.
+    fn claim(&self, profile_id: &str, service_is_stopped: bool) -> Result<(), String> {
+        let mut owner = self.0.lock().unwrap();
+        let current_owner = owner.as_deref().unwrap_or(DEFAULT_PROFILE_ID);
+
+        if current_owner != profile_id && !service_is_stopped {
+            return Err(current_owner.to_string());
         }
+
+        *owner = Some(profile_id.to_string());
+
+        Ok(())
+    }
+}
+
+fn map_service_state(state: ServiceState) -> SecureLinkClientState {
+    match state {
+        ServiceState::Running => SecureLinkClientState::Running,
+        ServiceState::StartPending => SecureLinkClientState::Pending,
+        ServiceState::StopPending => SecureLinkClientState::Stopping,
+        ServiceState::Stopped => SecureLinkClientState::Stopped,
+        _ => SecureLinkClientState::Pending,
+    }
+}
+
+fn query_service_state() -> Result<SecureLinkClientState, SecureLinkClientError> {
+    match secure_link_windows_service_manager::query_state() {
+        Ok(state) => Ok(map_service_state(state)),
+        Err(error) => Err(SecureLinkClientError::ServiceError(Box::new(error))),
     }
 }
 
 #[async_trait]
 impl SecureLinkClient for SecureLinkWindowsServiceClient {
     async fn start(&self) -> Result<(), SecureLinkClientError> {
+        let service_is_stopped = query_service_state()? == SecureLinkClientState::Stopped;
+
+        SERVICE_OWNER
+            .claim(&self.profile_id, service_is_stopped)
+            .map_err(SecureLinkClientError::ServiceBusy)?;
+
         let start_service_result = secure_link_windows_service_manager::start_service(
             self.secure_link_server_host.as_str(),
             self.secure_link_server_port,
-            &self.auth_token,
+            self.auth_token.expose(),
             &self.service_log_file_path,
         );
 
@@ -48,22 +115,58 @@ impl SecureLinkClient for SecureLinkWindowsServiceClient {
         }
     }
 
-    async fn stop(&self) -> Result<(), SecureLinkClientError> {
+    // The session of another link is left alone
+    async fn stop(&self) -> Result<SecureLinkClientStopOutcome, SecureLinkClientError> {
+        if !SERVICE_OWNER.is_owned_by(&self.profile_id) {
+            return Ok(SecureLinkClientStopOutcome::Clean);
+        }
+
         match secure_link_windows_service_manager::stop_service() {
-            Ok(()) => Ok(()),
+            Ok(()) => Ok(SecureLinkClientStopOutcome::Clean),
             Err(error) => Err(SecureLinkClientError::ServiceError(Box::new(error))),
         }
     }
 
+    // Stopped for every link but the one the service runs the session of
     async fn status(&self) -> Result<SecureLinkClientState, SecureLinkClientError> {
-        match secure_link_windows_service_manager::query_state() {
-            Ok(state) => match state {
-                ServiceState::Running => Ok(SecureLinkClientState::Running),
-                ServiceState::StartPending => Ok(SecureLinkClientState::Pending),
-                ServiceState::Stopped => Ok(SecureLinkClientState::Stopped),
-                _ => Ok(SecureLinkClientState::Pending),
-            },
-            Err(error) => Err(SecureLinkClientError::ServiceError(Box::new(error))),
+        if !SERVICE_OWNER.is_owned_by(&self.profile_id) {
+            return Ok(SecureLinkClientState::Stopped);
         }
+
+        query_service_state()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_running_service_belongs_to_the_default_link() {
+        let owner = ServiceOwner::new();
+
+        assert!(owner.is_owned_by(DEFAULT_PROFILE_ID));
+        assert!(!owner.is_owned_by("staging"));
+        assert_eq!(owner.claim("staging", false), Err("default".to_string()));
+        assert_eq!(owner.claim(DEFAULT_PROFILE_ID, false), Ok(()));
+    }
+
+    #[test]
+    fn second_link_is_rejected_while_the_service_runs() {
+        let owner = ServiceOwner::new();
+
+        assert_eq!(owner.claim("staging", true), Ok(()));
+        assert!(owner.is_owned_by("staging"));
+        assert!(!owner.is_owned_by(DEFAULT_PROFILE_ID));
+
+        assert_eq!(
+            owner.claim(DEFAULT_PROFILE_ID, false),
+            Err("staging".to_string())
+        );
+        assert!(owner.is_owned_by("staging"));
+
+        // Once stopped any link can take the service over
+        assert_eq!(owner.claim(DEFAULT_PROFILE_ID, true), Ok(()));
+        assert!(owner.is_owned_by(DEFAULT_PROFILE_ID));
     }
 }
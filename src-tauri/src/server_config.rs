@@ -0,0 +1,201 @@
+use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+use base64::Engine;
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::CertificateDer;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const DEFAULT_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Server settings that can be changed at runtime. The server itself is the one
+// the app was built for unless the settings name other endpoints.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerConfig {
+    #[serde(default)]
+    pub proxy: ProxyConfig,
+    #[serde(default)]
+    pub trust: TrustConfig,
+    // How long a stop waits for the session to end before aborting it
+    #[serde(default)]
+    pub stop_timeout_ms: Option<u64>,
+    // Disconnect after this long without traffic, `None` keeps the link up
+    #[serde(default)]
+    pub idle_timeout_minutes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "camelCase")]
+pub enum ProxyConfig {
+    #[default]
+    None,
+    // Taken from the HTTPS_PROXY / ALL_PROXY environment variables, then on
+    // Windows from the proxy of the Internet Options
+    System,
+    HttpConnect(ProxyServerConfig),
+    Socks5(ProxyServerConfig),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProxyServerConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    // Only read from updates, it is kept apart from the settings like the auth
+    // token and put back when a client is created
+    #[serde(default, skip_serializing)]
+    pub password: Option<String>,
+}
+
+// Extra trust on top of the public web PKI roots, for on-prem servers behind a
+// private CA. Pins are base64 SHA-256 hashes of a DER SubjectPublicKeyInfo in
+// the server chain, optionally prefixed with `sha256/`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrustConfig {
+    pub ca_bundle_path: Option<PathBuf>,
+    #[serde(default)]
+    pub spki_sha256_pins: Vec<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ServerConfigError {
+    #[error("Proxy host is empty")]
+    EmptyProxyHost,
+
+    #[error("Proxy port is zero")]
+    InvalidProxyPort,
+
+    #[error("Proxy password is set without a username")]
+    ProxyPasswordWithoutUsername,
+
+    #[error("CA bundle {0} can not be read: {1}")]
+    UnreadableCaBundle(String, String),
+
+    #[error("CA bundle {0} contains no certificates")]
+    EmptyCaBundle(String),
+
+    #[error("SPKI pin {0} is not a base64 SHA-256 hash")]
+    InvalidSpkiPin(String),
+
+    #[error("Stop timeout is zero")]
+    InvalidStopTimeout,
+
+    #[error("Idle timeout is zero")]
+    InvalidIdleTimeout,
+}
+
+impl ProxyConfig {
+    pub fn server(&self) -> Option<&ProxyServerConfig> {
+        match self {
+            ProxyConfig::None | ProxyConfig::System => None,
+            ProxyConfig::HttpConnect(proxy_server) | ProxyConfig::Socks5(proxy_server) => {
+                Some(proxy_server)
+            }
+        }
+    }
+
+    // The password of an update, so it can be stored apart from the settings
+    pub fn take_password(&mut self) -> Option<String> {
+        match self {
+            ProxyConfig::None | ProxyConfig::System => None,
+            ProxyConfig::HttpConnect(proxy_server) | ProxyConfig::Socks5(proxy_server) => {
+                proxy_server.password.take()
+            }
+        }
+    }
+
+    pub fn with_password(mut self, password: Option<String>) -> Self {
+        if let ProxyConfig::HttpConnect(proxy_server) | ProxyConfig::Socks5(proxy_server) =
+            &mut self
+        {
+            proxy_server.password = password;
+        }
+
+        self
+    }
+}
+
+impl ServerConfig {
+    pub fn validate(&self) -> Result<(), ServerConfigError> {
+        if let Some(proxy_server) = self.proxy.server() {
+            proxy_server.validate()?;
+        }
+
+        if self.stop_timeout_ms == Some(0) {
+            return Err(ServerConfigError::InvalidStopTimeout);
+        }
+
+        if self.idle_timeout_minutes == Some(0) {
+            return Err(ServerConfigError::InvalidIdleTimeout);
+        }
+
+        self.trust.validate()
+    }
+
+    pub fn stop_timeout(&self) -> Duration {
+        self.stop_timeout_ms
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_STOP_TIMEOUT)
+    }
+
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout_minutes
+            .map(|minutes| Duration::from_secs(minutes * 60))
+    }
+}
+
+impl TrustConfig {
+    fn validate(&self) -> Result<(), ServerConfigError> {
+        if let Some(ca_bundle_path) = &self.ca_bundle_path {
+            load_ca_bundle(ca_bundle_path)?;
+        }
+
+        self.decoded_spki_sha256_pins()?;
+
+        Ok(())
+    }
+
+    pub fn decoded_spki_sha256_pins(&self) -> Result<Vec<[u8; 32]>, ServerConfigError> {
+        self.spki_sha256_pins
+            .iter()
+            .map(|pin| {
+                BASE64_STANDARD
+                    .decode(pin.trim().trim_start_matches("sha256/"))
+                    .ok()
+                    .and_then(|hash| <[u8; 32]>::try_from(hash).ok())
+                    .ok_or_else(|| ServerConfigError::InvalidSpkiPin(pin.clone()))
+            })
+            .collect()
+    }
+}
+
+pub fn load_ca_bundle(path: &Path) -> Result<Vec<CertificateDer<'static>>, ServerConfigError> {
+    let certificates = CertificateDer::pem_file_iter(path)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| {
+            ServerConfigError::UnreadableCaBundle(path.display().to_string(), e.to_string())
+        })?;
+
+    if certificates.is_empty() {
+        return Err(ServerConfigError::EmptyCaBundle(path.display().to_string()));
+    }
+
+    Ok(certificates)
+}
+
+impl ProxyServerConfig {
+    fn validate(&self) -> Result<(), ServerConfigError> {
+        if self.host.trim().is_empty() {
+            return Err(ServerConfigError::EmptyProxyHost);
+        }
+
+        if self.port == 0 {
+            return Err(ServerConfigError::InvalidProxyPort);
+        }
+
+        Ok(())
+    }
+}
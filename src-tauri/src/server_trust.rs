@@ -0,0 +1,297 @@
+use crate::server_config::{self, TrustConfig};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+// TLS settings built from the runtime trust config. `pin_mismatch` is raised by
+// the verifier, so a failed connect can be told apart from other TLS errors.
+pub struct ServerTls {
+    pub tls_config: Arc<ClientConfig>,
+    pin_mismatch: Arc<AtomicBool>,
+}
+
+impl ServerTls {
+    pub fn is_pin_mismatch(&self) -> bool {
+        self.pin_mismatch.load(Ordering::SeqCst)
+    }
+}
+
+// Returns `None` for the default trust config, the secure link client then uses
+// its built-in roots, plus its dev certificates with the `load-dev-certs` feature
+pub fn build_server_tls(
+    trust_config: &TrustConfig,
+) -> Result<Option<ServerTls>, Box<dyn std::error::Error>> {
+    if *trust_config == TrustConfig::default() {
+        return Ok(None);
+    }
+
+    let mut root_cert_store =
+        RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+    if let Some(ca_bundle_path) = &trust_config.ca_bundle_path {
+        for certificate in server_config::load_ca_bundle(ca_bundle_path)? {
+            root_cert_store.add(certificate)?;
+        }
+    }
+
+    let crypto_provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+
+    let webpki_verifier = WebPkiServerVerifier::builder_with_provider(
+        Arc::new(root_cert_store),
+        crypto_provider.clone(),
+    )
+    .build()?;
+
+    let pin_mismatch = Arc::new(AtomicBool::new(false));
+
+    let verifier = PinnedServerCertVerifier {
+        webpki_verifier,
+        spki_sha256_pins: trust_config.decoded_spki_sha256_pins()?,
+        pin_mismatch: pin_mismatch.clone(),
+    };
+
+    let tls_config = ClientConfig::builder_with_provider(crypto_provider)
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+
+    Ok(Some(ServerTls {
+        tls_config: Arc::new(tls_config),
+        pin_mismatch,
+    }))
+}
+
+// Regular web PKI verification, followed by an SPKI pin check when pins are set.
+// A pin matches any certificate the server presents, so both leaf and
+// intermediate keys can be pinned.
+#[derive(Debug)]
+struct PinnedServerCertVerifier {
+    webpki_verifier: Arc<WebPkiServerVerifier>,
+    spki_sha256_pins: Vec<[u8; 32]>,
+    pin_mismatch: Arc<AtomicBool>,
+}
+
+impl ServerCertVerifier for PinnedServerCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.webpki_verifier.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        if self.spki_sha256_pins.is_empty() {
+            return Ok(verified);
+        }
+
+        let is_pinned = std::iter::once(end_entity)
+            .chain(intermediates)
+            .filter_map(spki_sha256)
+            .any(|spki_hash| self.spki_sha256_pins.contains(&spki_hash));
+
+        if is_pinned {
+            Ok(verified)
+        } else {
+            self.pin_mismatch.store(true, Ordering::SeqCst);
+
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.webpki_verifier
+            .verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.webpki_verifier
+            .verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.webpki_verifier.supported_verify_schemes()
+    }
+}
+
+fn spki_sha256(certificate: &CertificateDer<'_>) -> Option<[u8; 32]> {
+    let (_, certificate) = X509Certificate::from_der(certificate.as_ref()).ok()?;
+
+    Some(Sha256::digest(certificate.public_key().raw).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server_config::ServerConfigError;
+    use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+    use base64::Engine;
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair, KeyUsagePurpose};
+
+    struct TestChain {
+        ca_certificate: CertificateDer<'static>,
+        server_certificate: CertificateDer<'static>,
+    }
+
+    // A CA and the `localhost` certificate it signed
+    fn generate_chain() -> TestChain {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params
+            .distinguished_name
+            .push(DnType::CommonName, "Secure Link test CA");
+        ca_params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+        let ca_certificate = ca_params.self_signed(&ca_key).unwrap();
+
+        let server_key = KeyPair::generate().unwrap();
+        let server_certificate = CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .signed_by(&server_key, &ca_certificate, &ca_key)
+            .unwrap();
+
+        TestChain {
+            ca_certificate: ca_certificate.der().clone(),
+            server_certificate: server_certificate.der().clone(),
+        }
+    }
+
+    fn pinned_verifier(
+        chain: &TestChain,
+        spki_sha256_pins: Vec<[u8; 32]>,
+    ) -> PinnedServerCertVerifier {
+        let mut root_cert_store = RootCertStore::empty();
+        root_cert_store.add(chain.ca_certificate.clone()).unwrap();
+
+        let webpki_verifier = WebPkiServerVerifier::builder_with_provider(
+            Arc::new(root_cert_store),
+            Arc::new(rustls::crypto::aws_lc_rs::default_provider()),
+        )
+        .build()
+        .unwrap();
+
+        PinnedServerCertVerifier {
+            webpki_verifier,
+            spki_sha256_pins,
+            pin_mismatch: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // The server sends its CA along, so intermediate pins can match
+    fn verify(verifier: &PinnedServerCertVerifier, chain: &TestChain) -> Result<(), rustls::Error> {
+        verifier
+            .verify_server_cert(
+                &chain.server_certificate,
+                std::slice::from_ref(&chain.ca_certificate),
+                &ServerName::try_from("localhost").unwrap(),
+                &[],
+                UnixTime::now(),
+            )
+            .map(|_| ())
+    }
+
+    #[test]
+    fn matching_pin_is_accepted() {
+        let chain = generate_chain();
+        let verifier = pinned_verifier(
+            &chain,
+            vec![spki_sha256(&chain.server_certificate).unwrap()],
+        );
+
+        assert_eq!(verify(&verifier, &chain), Ok(()));
+        assert!(!verifier.pin_mismatch.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn non_matching_pin_is_a_pin_mismatch() {
+        let chain = generate_chain();
+        let other_chain = generate_chain();
+        let verifier = pinned_verifier(
+            &chain,
+            vec![spki_sha256(&other_chain.server_certificate).unwrap()],
+        );
+
+        assert_eq!(
+            verify(&verifier, &chain),
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure
+            ))
+        );
+        assert!(verifier.pin_mismatch.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn any_of_several_pins_matches_the_chain() {
+        let chain = generate_chain();
+        let other_chain = generate_chain();
+        let verifier = pinned_verifier(
+            &chain,
+            vec![
+                spki_sha256(&other_chain.server_certificate).unwrap(),
+                spki_sha256(&chain.ca_certificate).unwrap(),
+            ],
+        );
+
+        assert_eq!(verify(&verifier, &chain), Ok(()));
+        assert!(!verifier.pin_mismatch.load(Ordering::SeqCst));
+
+        // Without pins only the web PKI verification applies
+        assert_eq!(verify(&pinned_verifier(&chain, Vec::new()), &chain), Ok(()));
+    }
+
+    #[test]
+    fn bad_base64_pin_is_rejected() {
+        let chain = generate_chain();
+        let pin = format!(
+            "sha256/{}",
+            BASE64_STANDARD.encode(spki_sha256(&chain.server_certificate).unwrap())
+        );
+
+        let trust_config = |spki_sha256_pins: Vec<String>| TrustConfig {
+            ca_bundle_path: None,
+            spki_sha256_pins,
+        };
+
+        assert!(build_server_tls(&trust_config(vec![pin]))
+            .unwrap()
+            .is_some());
+
+        for bad_pin in ["not base64!", "c2hvcnQ="] {
+            let error = build_server_tls(&trust_config(vec![bad_pin.to_string()]))
+                .err()
+                .unwrap();
+
+            assert!(matches!(
+                error.downcast_ref::<ServerConfigError>(),
+                Some(ServerConfigError::InvalidSpkiPin(_))
+            ));
+        }
+    }
+}
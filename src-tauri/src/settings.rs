@@ -0,0 +1,761 @@
+use crate::bandwidth_limit::BandwidthLimit;
+use crate::connection_schedule::{ConnectionSchedule, ScheduleConfig, ScheduleError};
+use crate::enrollment::EnrollmentSettings;
+use crate::global_shortcuts::ShortcutSettings;
+use crate::metrics::MetricsSettings;
+use crate::server_config::{ServerConfig, ServerConfigError};
+use crate::updater::UpdateSettings;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub const SETTINGS_SCHEMA_VERSION: u64 = 1;
+
+const SETTINGS_FILE_NAME: &str = "settings.json";
+
+// The link to `endpoint` or the server the app was built for. It always
+// exists and can not be used as the id of another profile.
+pub const DEFAULT_PROFILE_ID: &str = "default";
+
+type Migration = fn(&mut Map<String, Value>) -> Result<(), SettingsError>;
+
+// `MIGRATIONS[n]` turns schema version `n + 1` into `n + 2`. Sections that
+// are only added get a `#[serde(default)]` instead of a new version.
+const MIGRATIONS: [Migration; SETTINGS_SCHEMA_VERSION as usize - 1] = [];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Settings {
+    pub schema_version: u64,
+    // Launch the app when the user logs in
+    #[serde(default)]
+    pub autostart: bool,
+    #[serde(default = "default_notifications")]
+    pub notifications: bool,
+    // BCP 47 tag such as `ru` or `en-US`, `None` follows the system
+    #[serde(default)]
+    pub locale: Option<String>,
+    // Overrides the server the app was built for
+    #[serde(default)]
+    pub endpoint: Option<EndpointSettings>,
+    // Servers the default link fails over between, used instead of
+    // `endpoint` when not empty
+    #[serde(default)]
+    pub endpoints: Vec<EndpointSettings>,
+    // Proxy, trust and timeouts
+    #[serde(default)]
+    pub server: ServerConfig,
+    #[serde(default)]
+    pub schedule: ScheduleConfig,
+    // Limits every link on its own and applies to running links without
+    // reconnecting
+    #[serde(default)]
+    pub bandwidth: BandwidthLimit,
+    // Prometheus endpoint on the loopback interface
+    #[serde(default)]
+    pub metrics: MetricsSettings,
+    // Links run alongside the default one
+    #[serde(default)]
+    pub profiles: Vec<LinkProfile>,
+
+    #[serde(default)]
+    pub updates: UpdateSettings,
+
+    #[serde(default)]
+    pub enrollment: EnrollmentSettings,
+
+    #[serde(default)]
+    pub shortcuts: ShortcutSettings,
+
+    // Keys this version does not know, kept so they survive a write
+    #[serde(flatten)]
+    pub unknown_fields: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EndpointSettings {
+    pub host: String,
+    pub port: u16,
+    // Lower is tried first when failing over
+    #[serde(default)]
+    pub priority: u32,
+    // Share of the clients among endpoints of the same priority
+    #[serde(default = "default_endpoint_weight")]
+    pub weight: u32,
+}
+
+// `id` names the files of the link, so it is limited to ASCII letters,
+// digits, `-` and `_`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkProfile {
+    pub id: String,
+    pub name: String,
+    pub endpoint: EndpointSettings,
+    // Failed over to like `Settings::endpoints`, together with `endpoint`
+    #[serde(default)]
+    pub endpoints: Vec<EndpointSettings>,
+}
+
+fn default_notifications() -> bool {
+    true
+}
+
+fn default_endpoint_weight() -> u32 {
+    1
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            schema_version: SETTINGS_SCHEMA_VERSION,
+            autostart: false,
+            notifications: default_notifications(),
+            locale: None,
+            endpoint: None,
+            endpoints: Vec::new(),
+            server: ServerConfig::default(),
+            schedule: ScheduleConfig::default(),
+            bandwidth: BandwidthLimit::default(),
+            metrics: MetricsSettings::default(),
+            profiles: Vec::new(),
+            updates: UpdateSettings::default(),
+            enrollment: EnrollmentSettings::default(),
+            shortcuts: ShortcutSettings::default(),
+            unknown_fields: Map::new(),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SettingsError {
+    #[error("Settings file can not be accessed: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Settings are malformed: {0}")]
+    Malformed(#[from] serde_json::Error),
+
+    #[error("Settings update is not an object")]
+    UpdateNotAnObject,
+
+    #[error("Schema version can not be updated")]
+    SchemaVersionUpdate,
+
+    #[error("Locale {0} is not a language tag")]
+    InvalidLocale(String),
+
+    #[error("Endpoint host is empty")]
+    EmptyEndpointHost,
+
+    #[error("Endpoint port is zero")]
+    InvalidEndpointPort,
+
+    #[error("Endpoint weight is zero")]
+    InvalidEndpointWeight,
+
+    #[error("Bandwidth limit is zero")]
+    InvalidBandwidthLimit,
+
+    #[error("Metrics port is zero")]
+    InvalidMetricsPort,
+
+    #[error("Profile id {0:?} is not allowed")]
+    InvalidProfileId(String),
+
+    #[error("Profile id {0} is used more than once")]
+    DuplicateProfileId(String),
+
+    #[error("Profile {0} has no name")]
+    EmptyProfileName(String),
+
+    #[error("Release manifest URL is not an HTTP URL")]
+    InvalidManifestUrl,
+
+    #[error("Enrollment URL is not an HTTPS URL")]
+    InvalidEnrollmentUrl,
+
+    #[error(transparent)]
+    Server(#[from] ServerConfigError),
+
+    #[error(transparent)]
+    Schedule(#[from] ScheduleError),
+}
+
+impl Settings {
+    // Only the sections that differ from `current` are validated, so a stale
+    // CA bundle path does not block changing the locale
+    fn validate_changes(&self, current: &Settings) -> Result<(), SettingsError> {
+        if self.locale != current.locale {
+            if let Some(locale) = &self.locale {
+                if !is_language_tag(locale) {
+                    return Err(SettingsError::InvalidLocale(locale.clone()));
+                }
+            }
+        }
+
+        if self.endpoint != current.endpoint {
+            if let Some(endpoint) = &self.endpoint {
+                endpoint.validate()?;
+            }
+        }
+
+        if self.endpoints != current.endpoints {
+            for endpoint in &self.endpoints {
+                endpoint.validate()?;
+            }
+        }
+
+        if self.profiles != current.profiles {
+            validate_profiles(&self.profiles)?;
+        }
+
+        if self.server != current.server {
+            self.server.validate()?;
+        }
+
+        if self.schedule != current.schedule {
+            ConnectionSchedule::from_config(&self.schedule)?;
+        }
+
+        if !self.bandwidth.is_valid() {
+            return Err(SettingsError::InvalidBandwidthLimit);
+        }
+
+        if self.metrics.enabled && self.metrics.port == 0 {
+            return Err(SettingsError::InvalidMetricsPort);
+        }
+
+        if self.updates != current.updates && !self.updates.is_valid() {
+            return Err(SettingsError::InvalidManifestUrl);
+        }
+
+        if self.enrollment != current.enrollment && !self.enrollment.is_valid() {
+            return Err(SettingsError::InvalidEnrollmentUrl);
+        }
+
+        Ok(())
+    }
+}
+
+impl EndpointSettings {
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        if self.host.trim().is_empty() {
+            return Err(SettingsError::EmptyEndpointHost);
+        }
+
+        if self.port == 0 {
+            return Err(SettingsError::InvalidEndpointPort);
+        }
+
+        if self.weight == 0 {
+            return Err(SettingsError::InvalidEndpointWeight);
+        }
+
+        Ok(())
+    }
+}
+
+fn validate_profiles(profiles: &[LinkProfile]) -> Result<(), SettingsError> {
+    let mut ids = HashSet::new();
+
+    for profile in profiles {
+        let id_is_valid = (1..=32).contains(&profile.id.len())
+            && profile
+                .id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            && profile.id != DEFAULT_PROFILE_ID;
+
+        if !id_is_valid {
+            return Err(SettingsError::InvalidProfileId(profile.id.clone()));
+        }
+
+        if !ids.insert(profile.id.as_str()) {
+            return Err(SettingsError::DuplicateProfileId(profile.id.clone()));
+        }
+
+        if profile.name.trim().is_empty() {
+            return Err(SettingsError::EmptyProfileName(profile.id.clone()));
+        }
+
+        profile.endpoint.validate()?;
+
+        for endpoint in &profile.endpoints {
+            endpoint.validate()?;
+        }
+    }
+
+    Ok(())
+}
+
+// Loose check for tags like `ru`, `en-US` or `zh-Hant-TW`
+fn is_language_tag(locale: &str) -> bool {
+    let mut subtags = locale.split('-');
+
+    let language_is_valid = subtags.next().is_some_and(|language| {
+        (2..=3).contains(&language.len()) && language.chars().all(|c| c.is_ascii_alphabetic())
+    });
+
+    language_is_valid
+        && subtags.all(|subtag| {
+            (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+#[derive(Debug, Clone)]
+pub struct SettingsChange {
+    pub previous: Settings,
+    pub current: Settings,
+}
+
+// `settings.json` in the app data directory, kept in memory and written
+// atomically on every change
+pub struct SettingsStore {
+    file_path: PathBuf,
+    settings: Mutex<Settings>,
+}
+
+impl SettingsStore {
+    // Migrates older settings and writes the result back. Settings of a newer
+    // version are read as far as this version knows them and left as they are.
+    pub fn open(app_data_dir: &Path) -> Result<Self, SettingsError> {
+        let file_path = app_data_dir.join(SETTINGS_FILE_NAME);
+
+        let mut settings = match std::fs::read_to_string(&file_path) {
+            Ok(content) => serde_json::from_str::<Map<String, Value>>(&content)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let settings = Settings::default();
+                write_settings_file(&file_path, &settings)?;
+
+                return Ok(Self {
+                    file_path,
+                    settings: Mutex::new(settings),
+                });
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let schema_version = settings
+            .get("schemaVersion")
+            .and_then(Value::as_u64)
+            .unwrap_or(SETTINGS_SCHEMA_VERSION);
+
+        if schema_version > SETTINGS_SCHEMA_VERSION {
+            warn!(
+                "Settings schema version {} is newer than {}, keeping the settings this version does not know",
+                schema_version,
+                SETTINGS_SCHEMA_VERSION
+            );
+        }
+
+        for (version, migration) in MIGRATIONS
+            .iter()
+            .enumerate()
+            .skip(schema_version.saturating_sub(1) as usize)
+        {
+            migration(&mut settings)?;
+            settings.insert("schemaVersion".to_string(), (version as u64 + 2).into());
+        }
+
+        let settings: Settings = serde_json::from_value(Value::Object(settings))?;
+
+        if schema_version < SETTINGS_SCHEMA_VERSION {
+            write_settings_file(&file_path, &settings)?;
+        }
+
+        Ok(Self {
+            file_path,
+            settings: Mutex::new(settings),
+        })
+    }
+
+    // Moves settings `open` failed on aside and starts over with the defaults.
+    // Returns where the old file went.
+    pub fn reset(app_data_dir: &Path) -> Result<(Self, Option<PathBuf>), SettingsError> {
+        let file_path = app_data_dir.join(SETTINGS_FILE_NAME);
+
+        let backup_file_path = file_path.with_extension(format!(
+            "json.broken-{}",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S")
+        ));
+
+        let backup_file_path = match std::fs::rename(&file_path, &backup_file_path) {
+            Ok(()) => Some(backup_file_path),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+
+        let settings = Settings::default();
+        write_settings_file(&file_path, &settings)?;
+
+        Ok((
+            Self {
+                file_path,
+                settings: Mutex::new(settings),
+            },
+            backup_file_path,
+        ))
+    }
+
+    pub fn get(&self) -> Settings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    // Applies a JSON merge patch (RFC 7396): present keys replace the current
+    // values, objects are merged and `null` resets a key to its default.
+    // Returns `None` when nothing changed.
+    pub fn update(&self, patch: &Value) -> Result<Option<SettingsChange>, SettingsError> {
+        let mut settings = self.settings.lock().unwrap();
+
+        let updated_settings = patch_settings(&settings, patch)?;
+
+        self.store(&mut settings, updated_settings)
+    }
+
+    // The settings `update` would store for `patch`, for checks that have to
+    // run before
+    pub fn patched(&self, patch: &Value) -> Result<Settings, SettingsError> {
+        patch_settings(&self.settings.lock().unwrap(), patch)
+    }
+
+    // Changes the settings in code, with the same validation as `update`
+    pub fn update_with(
+        &self,
+        change: impl FnOnce(&mut Settings),
+    ) -> Result<Option<SettingsChange>, SettingsError> {
+        let mut settings = self.settings.lock().unwrap();
+
+        let mut updated_settings = settings.clone();
+        change(&mut updated_settings);
+
+        self.store(&mut settings, updated_settings)
+    }
+
+    fn store(
+        &self,
+        settings: &mut Settings,
+        updated_settings: Settings,
+    ) -> Result<Option<SettingsChange>, SettingsError> {
+        if updated_settings == *settings {
+            return Ok(None);
+        }
+
+        updated_settings.validate_changes(settings)?;
+
+        write_settings_file(&self.file_path, &updated_settings)?;
+
+        let previous = std::mem::replace(settings, updated_settings.clone());
+
+        Ok(Some(SettingsChange {
+            previous,
+            current: updated_settings,
+        }))
+    }
+}
+
+fn patch_settings(settings: &Settings, patch: &Value) -> Result<Settings, SettingsError> {
+    let Value::Object(patch) = patch else {
+        return Err(SettingsError::UpdateNotAnObject);
+    };
+
+    if patch.contains_key("schemaVersion") {
+        return Err(SettingsError::SchemaVersionUpdate);
+    }
+
+    let mut updated_settings = serde_json::to_value(settings)?;
+    merge_patch(&mut updated_settings, &Value::Object(patch.clone()));
+
+    Ok(serde_json::from_value(updated_settings)?)
+}
+
+fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+
+    let Value::Object(target) = target else {
+        return;
+    };
+
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            merge_patch(target.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+// Writes to a temporary file first so a crash never leaves truncated settings
+fn write_settings_file(file_path: &Path, settings: &Settings) -> Result<(), SettingsError> {
+    let temp_file_path = file_path.with_extension("json.tmp");
+
+    {
+        let mut temp_file = std::fs::File::create(&temp_file_path)?;
+        temp_file.write_all(serde_json::to_string_pretty(settings)?.as_bytes())?;
+        temp_file.sync_all()?;
+    }
+
+    std::fs::rename(&temp_file_path, file_path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+            let dir = std::env::temp_dir().join(format!(
+                "secure_link_settings_test_{}_{}",
+                std::process::id(),
+                NEXT_ID.fetch_add(1, Ordering::SeqCst)
+            ));
+            std::fs::create_dir_all(&dir).unwrap();
+
+            Self(dir)
+        }
+
+        fn read_settings_file(&self) -> Value {
+            serde_json::from_str(&std::fs::read_to_string(self.0.join(SETTINGS_FILE_NAME)).unwrap())
+                .unwrap()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn defaults_without_any_files() {
+        let dir = TempDir::new();
+
+        let store = SettingsStore::open(&dir.0).unwrap();
+
+        assert_eq!(store.get(), Settings::default());
+        assert_eq!(
+            dir.read_settings_file()["schemaVersion"],
+            json!(SETTINGS_SCHEMA_VERSION)
+        );
+    }
+
+    #[test]
+    fn defaults_sections_missing_from_the_file() {
+        let dir = TempDir::new();
+        std::fs::write(
+            dir.0.join(SETTINGS_FILE_NAME),
+            json!({
+                "schemaVersion": SETTINGS_SCHEMA_VERSION,
+                "endpoint": {"host": "link.example.com", "port": 443},
+                "profiles": [{"id": "staging", "name": "Staging", "endpoint": {"host": "staging.example.com", "port": 443}}],
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let settings = SettingsStore::open(&dir.0).unwrap().get();
+
+        assert_eq!(settings.endpoint.unwrap().weight, 1);
+        assert_eq!(settings.profiles[0].endpoints, Vec::new());
+        assert_eq!(settings.bandwidth, BandwidthLimit::default());
+        assert_eq!(settings.enrollment, EnrollmentSettings::default());
+    }
+
+    #[test]
+    fn keeps_unknown_keys() {
+        let dir = TempDir::new();
+        std::fs::write(
+            dir.0.join(SETTINGS_FILE_NAME),
+            json!({"schemaVersion": SETTINGS_SCHEMA_VERSION, "addedLater": {"enabled": true}})
+                .to_string(),
+        )
+        .unwrap();
+
+        let store = SettingsStore::open(&dir.0).unwrap();
+        store.update(&json!({"locale": "en"})).unwrap();
+
+        assert_eq!(
+            dir.read_settings_file()["addedLater"],
+            json!({"enabled": true})
+        );
+        assert_eq!(
+            store.get().unknown_fields.get("addedLater"),
+            Some(&json!({"enabled": true}))
+        );
+    }
+
+    #[test]
+    fn reset_moves_broken_settings_aside() {
+        let dir = TempDir::new();
+        std::fs::write(dir.0.join(SETTINGS_FILE_NAME), "{not json").unwrap();
+
+        assert!(matches!(
+            SettingsStore::open(&dir.0),
+            Err(SettingsError::Malformed(_))
+        ));
+
+        let (store, backup_file_path) = SettingsStore::reset(&dir.0).unwrap();
+
+        assert_eq!(store.get(), Settings::default());
+        assert_eq!(
+            std::fs::read_to_string(backup_file_path.unwrap()).unwrap(),
+            "{not json"
+        );
+        assert_eq!(
+            SettingsStore::open(&dir.0).unwrap().get(),
+            Settings::default()
+        );
+    }
+
+    #[test]
+    fn reads_settings_of_a_newer_version_without_writing_them() {
+        let dir = TempDir::new();
+        let newer_settings = json!({
+            "schemaVersion": SETTINGS_SCHEMA_VERSION + 1,
+            "locale": "en",
+            "addedLater": {"enabled": true},
+        })
+        .to_string();
+        std::fs::write(dir.0.join(SETTINGS_FILE_NAME), &newer_settings).unwrap();
+
+        let settings = SettingsStore::open(&dir.0).unwrap().get();
+
+        assert_eq!(settings.schema_version, SETTINGS_SCHEMA_VERSION + 1);
+        assert_eq!(settings.locale.as_deref(), Some("en"));
+        assert_eq!(
+            settings.unknown_fields.get("addedLater"),
+            Some(&json!({"enabled": true}))
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.0.join(SETTINGS_FILE_NAME)).unwrap(),
+            newer_settings
+        );
+    }
+
+    #[test]
+    fn partial_update_keeps_other_settings() {
+        let dir = TempDir::new();
+        let store = SettingsStore::open(&dir.0).unwrap();
+
+        store
+            .update(&json!({"server": {"stopTimeoutMs": 1000}}))
+            .unwrap();
+        let settings = store
+            .update(&json!({"locale": "en-US", "autostart": true}))
+            .unwrap()
+            .unwrap()
+            .current;
+
+        assert_eq!(settings.locale.as_deref(), Some("en-US"));
+        assert!(settings.autostart);
+        assert_eq!(settings.server.stop_timeout_ms, Some(1000));
+        assert_eq!(dir.read_settings_file()["locale"], json!("en-US"));
+
+        // `null` resets to the default
+        let change = store.update(&json!({"locale": null})).unwrap().unwrap();
+
+        assert_eq!(change.previous.locale.as_deref(), Some("en-US"));
+        assert_eq!(change.current.locale, None);
+    }
+
+    #[test]
+    fn unchanged_update_reports_nothing() {
+        let dir = TempDir::new();
+        let store = SettingsStore::open(&dir.0).unwrap();
+
+        assert!(store
+            .update(&json!({"notifications": true}))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_updates() {
+        let dir = TempDir::new();
+        let store = SettingsStore::open(&dir.0).unwrap();
+
+        assert!(matches!(
+            store.update(&json!({"locale": "not a locale"})),
+            Err(SettingsError::InvalidLocale(_))
+        ));
+        assert!(matches!(
+            store.update(&json!({"endpoint": {"host": "link.example.com", "port": 0}})),
+            Err(SettingsError::InvalidEndpointPort)
+        ));
+        assert!(matches!(
+            store.update(&json!({"endpoints": [
+                {"host": "a.example.com", "port": 443},
+                {"host": "b.example.com", "port": 443, "weight": 0},
+            ]})),
+            Err(SettingsError::InvalidEndpointWeight)
+        ));
+        assert!(matches!(
+            store.update(&json!({"server": {"stopTimeoutMs": 0}})),
+            Err(SettingsError::Server(ServerConfigError::InvalidStopTimeout))
+        ));
+        assert!(matches!(
+            store.update(&json!({"schedule": {"timeZone": "Mars/Olympus"}})),
+            Err(SettingsError::Schedule(_))
+        ));
+        assert!(matches!(
+            store.update(&json!({"bandwidth": {"uploadBytesPerSecond": 0}})),
+            Err(SettingsError::InvalidBandwidthLimit)
+        ));
+        assert!(matches!(
+            store.update(&json!({"metrics": {"enabled": true, "port": 0}})),
+            Err(SettingsError::InvalidMetricsPort)
+        ));
+        assert!(matches!(
+            store.update(&json!({"profiles": [
+                {"id": "staging", "name": "Staging", "endpoint": {"host": "a.example.com", "port": 443}},
+                {"id": "staging", "name": "Staging 2", "endpoint": {"host": "b.example.com", "port": 443}},
+            ]})),
+            Err(SettingsError::DuplicateProfileId(_))
+        ));
+        assert!(matches!(
+            store.update(&json!({"profiles": [
+                {"id": "default", "name": "Default", "endpoint": {"host": "a.example.com", "port": 443}},
+            ]})),
+            Err(SettingsError::InvalidProfileId(_))
+        ));
+        assert!(matches!(
+            store.update(&json!({"profiles": [
+                {"id": "../prod", "name": "Prod", "endpoint": {"host": "a.example.com", "port": 443}},
+            ]})),
+            Err(SettingsError::InvalidProfileId(_))
+        ));
+        assert!(matches!(
+            store.update(&json!({"updates": {"manifestUrl": "file:///etc/passwd"}})),
+            Err(SettingsError::InvalidManifestUrl)
+        ));
+        assert!(matches!(
+            store.update(&json!({"enrollment": {"url": "http://enroll.example.com/enroll"}})),
+            Err(SettingsError::InvalidEnrollmentUrl)
+        ));
+        assert!(matches!(
+            store.update(&json!({"schemaVersion": 2})),
+            Err(SettingsError::SchemaVersionUpdate)
+        ));
+
+        assert_eq!(store.get(), Settings::default());
+    }
+}
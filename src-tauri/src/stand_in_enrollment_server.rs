@@ -0,0 +1,171 @@
+// Local stand-in for the enrollment server, used by the enrollment tests. It
+// serves plain HTTP/1.1 on 127.0.0.1 and answers POST requests to `/enroll`
+// by the state of the code in the body, see `enrollment`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+const MAX_REQUEST_HEAD_LEN: usize = 16 * 1024;
+const MAX_REQUEST_BODY_LEN: usize = 16 * 1024;
+
+#[derive(Debug, Clone)]
+pub enum StandInEnrollmentState {
+    // Answered once with this body, then `Used`
+    Valid(serde_json::Value),
+    Used,
+    Expired,
+}
+
+#[derive(Default)]
+struct Enrollments {
+    codes: HashMap<String, StandInEnrollmentState>,
+    // Of every request, in order
+    device_ids: Vec<String>,
+}
+
+pub struct StandInEnrollmentServer {
+    port: u16,
+    enrollments: Arc<Mutex<Enrollments>>,
+    accept_task: JoinHandle<()>,
+}
+
+impl StandInEnrollmentServer {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let enrollments = Arc::new(Mutex::new(Enrollments::default()));
+        let enrollments_clone = enrollments.clone();
+
+        let accept_task = tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let enrollments = enrollments_clone.clone();
+
+                tokio::spawn(async move {
+                    let Some(body) = read_request_body(&mut stream).await else {
+                        return;
+                    };
+
+                    let response = enroll(&enrollments, &body);
+
+                    let _ = stream.write_all(&response).await;
+                    let _ = stream.shutdown().await;
+                });
+            }
+        });
+
+        Self {
+            port,
+            enrollments,
+            accept_task,
+        }
+    }
+
+    pub fn add_code(&self, code: &str, state: StandInEnrollmentState) {
+        self.enrollments
+            .lock()
+            .unwrap()
+            .codes
+            .insert(code.to_string(), state);
+    }
+
+    pub fn device_ids(&self) -> Vec<String> {
+        self.enrollments.lock().unwrap().device_ids.clone()
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://127.0.0.1:{}/enroll", self.port)
+    }
+}
+
+impl Drop for StandInEnrollmentServer {
+    fn drop(&mut self) {
+        self.accept_task.abort();
+    }
+}
+
+// `None` for anything but a POST to `/enroll` with a body. A request sent to
+// an HTTP proxy names the whole URL, it is taken as well so the stand-in can
+// be its own proxy.
+async fn read_request_body(stream: &mut TcpStream) -> Option<Vec<u8>> {
+    let mut request_head = Vec::new();
+
+    while !request_head.ends_with(b"\r\n\r\n") {
+        if request_head.len() >= MAX_REQUEST_HEAD_LEN {
+            return None;
+        }
+
+        request_head.push(stream.read_u8().await.ok()?);
+    }
+
+    let request_head = String::from_utf8_lossy(&request_head);
+
+    let request_target = request_head
+        .strip_prefix("POST ")?
+        .split_whitespace()
+        .next()?;
+
+    if !(request_target == "/enroll"
+        || request_target.starts_with("http://") && request_target.ends_with("/enroll"))
+    {
+        return None;
+    }
+
+    let content_length = request_head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .filter(|content_length| *content_length <= MAX_REQUEST_BODY_LEN)?;
+
+    let mut body = vec![0; content_length];
+    stream.read_exact(&mut body).await.ok()?;
+
+    Some(body)
+}
+
+fn enroll(enrollments: &Mutex<Enrollments>, body: &[u8]) -> Vec<u8> {
+    let Ok(request) = serde_json::from_slice::<serde_json::Value>(body) else {
+        return response("400 Bad Request", b"");
+    };
+
+    let code = request["code"].as_str().unwrap_or_default();
+    let device_id = request["deviceId"].as_str().unwrap_or_default();
+
+    let mut enrollments = enrollments.lock().unwrap();
+    enrollments.device_ids.push(device_id.to_string());
+
+    match enrollments.codes.get(code).cloned() {
+        Some(StandInEnrollmentState::Valid(enrollment)) => {
+            enrollments
+                .codes
+                .insert(code.to_string(), StandInEnrollmentState::Used);
+
+            response("200 OK", &serde_json::to_vec(&enrollment).unwrap())
+        }
+        Some(StandInEnrollmentState::Used) => response("409 Conflict", b""),
+        Some(StandInEnrollmentState::Expired) => response("410 Gone", b""),
+        None => response("404 Not Found", b""),
+    }
+}
+
+fn response(status: &str, body: &[u8]) -> Vec<u8> {
+    let mut response = format!(
+        "HTTP/1.1 {}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        status,
+        body.len()
+    )
+    .into_bytes();
+    response.extend_from_slice(body);
+    response
+}
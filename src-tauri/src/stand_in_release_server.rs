@@ -0,0 +1,102 @@
+// Local stand-in for the release server, used by the updater tests. It serves
+// plain HTTP/1.1 on 127.0.0.1 and answers GET requests with the bodies
+// registered for their paths, or `404 Not Found`.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+const MAX_REQUEST_HEAD_LEN: usize = 16 * 1024;
+
+pub struct StandInReleaseServer {
+    port: u16,
+    bodies: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    accept_task: JoinHandle<()>,
+}
+
+impl StandInReleaseServer {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let bodies = Arc::new(Mutex::new(HashMap::<String, Vec<u8>>::new()));
+        let bodies_clone = bodies.clone();
+
+        let accept_task = tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let bodies = bodies_clone.clone();
+
+                tokio::spawn(async move {
+                    let mut request_head = Vec::new();
+
+                    while !request_head.ends_with(b"\r\n\r\n") {
+                        if request_head.len() >= MAX_REQUEST_HEAD_LEN {
+                            return;
+                        }
+
+                        let Ok(byte) = stream.read_u8().await else {
+                            return;
+                        };
+                        request_head.push(byte);
+                    }
+
+                    let request_head = String::from_utf8_lossy(&request_head);
+                    let path = request_head
+                        .split_whitespace()
+                        .nth(1)
+                        .unwrap_or_default()
+                        .to_string();
+
+                    let body = bodies.lock().unwrap().get(&path).cloned();
+
+                    let response = match body {
+                        Some(body) => {
+                            let mut response = format!(
+                                "HTTP/1.1 200 OK\r\n\
+                                 Content-Type: application/octet-stream\r\n\
+                                 Content-Length: {}\r\n\
+                                 Connection: close\r\n\r\n",
+                                body.len()
+                            )
+                            .into_bytes();
+                            response.extend_from_slice(&body);
+                            response
+                        }
+                        None => b"HTTP/1.1 404 Not Found\r\n\
+                                  Content-Length: 0\r\n\
+                                  Connection: close\r\n\r\n"
+                            .to_vec(),
+                    };
+
+                    let _ = stream.write_all(&response).await;
+                    let _ = stream.shutdown().await;
+                });
+            }
+        });
+
+        Self {
+            port,
+            bodies,
+            accept_task,
+        }
+    }
+
+    pub fn serve(&self, path: &str, body: &[u8]) {
+        self.bodies
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), body.to_vec());
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://127.0.0.1:{}{}", self.port, path)
+    }
+}
+
+impl Drop for StandInReleaseServer {
+    fn drop(&mut self) {
+        self.accept_task.abort();
+    }
+}
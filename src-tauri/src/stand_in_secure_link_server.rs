@@ -0,0 +1,226 @@
+// Local stand-in for the secure link server, used by the end to end tests of
+// the embedded client. It serves TLS on 127.0.0.1 with a freshly generated CA
+// and `localhost` certificate, and speaks the global channel handshake as the
+// secure link client sees it: an HTTP/1.1 request head carrying
+// `Authorization: Bearer <token>`, answered with `101 Switching Protocols` or
+// `401 Unauthorized`. After a successful handshake incoming traffic is
+// drained until the client or the scripted behavior ends the session.
+
+use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair, KeyUsagePurpose};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::ServerConfig;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+
+const MAX_HANDSHAKE_REQUEST_LEN: usize = 16 * 1024;
+
+#[derive(Debug, Clone)]
+pub enum StandInBehavior {
+    // Accept valid tokens and keep the session open
+    Accept,
+    // Accept valid tokens, then drop the connection after the given time
+    CloseAfter(Duration),
+    // Accept TCP connections but never start the TLS handshake
+    Stall,
+    // Reject every token as unauthorized
+    RejectUnauthorized,
+    // Wait before answering the global channel handshake
+    SlowHandshake(Duration),
+}
+
+pub struct StandInSecureLinkServer {
+    port: u16,
+    ca_bundle_path: PathBuf,
+    handshakes: Arc<AtomicUsize>,
+    accept_task: JoinHandle<()>,
+}
+
+impl StandInSecureLinkServer {
+    // The CA certificate is written to `dir`, point the trust config at it
+    pub async fn start(behavior: StandInBehavior, valid_auth_token: &str, dir: &Path) -> Self {
+        let (ca_pem, certificate, private_key) = generate_certificates();
+
+        let ca_bundle_path = dir.join("stand_in_ca.pem");
+        std::fs::write(&ca_bundle_path, ca_pem).unwrap();
+
+        let server_config = ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::aws_lc_rs::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(vec![certificate], private_key)
+        .unwrap();
+
+        let tls_acceptor = TlsAcceptor::from(Arc::new(server_config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let handshakes = Arc::new(AtomicUsize::new(0));
+        let handshakes_clone = handshakes.clone();
+        let valid_auth_token = valid_auth_token.to_string();
+
+        let accept_task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let tls_acceptor = tls_acceptor.clone();
+                let behavior = behavior.clone();
+                let valid_auth_token = valid_auth_token.clone();
+                let handshakes = handshakes_clone.clone();
+
+                tokio::spawn(async move {
+                    if let StandInBehavior::Stall = behavior {
+                        // Hold the socket open without ever answering
+                        let _stream = stream;
+                        return std::future::pending().await;
+                    }
+
+                    let Ok(mut tls_stream) = tls_acceptor.accept(stream).await else {
+                        return;
+                    };
+
+                    let Some(request_head) = read_request_head(&mut tls_stream).await else {
+                        return;
+                    };
+
+                    handshakes.fetch_add(1, Ordering::SeqCst);
+
+                    if let StandInBehavior::SlowHandshake(delay) = behavior {
+                        tokio::time::sleep(delay).await;
+                    }
+
+                    let is_authorized = !matches!(behavior, StandInBehavior::RejectUnauthorized)
+                        && bearer_token(&request_head).as_deref()
+                            == Some(valid_auth_token.as_str());
+
+                    if !is_authorized {
+                        let _ = tls_stream
+                            .write_all(b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n")
+                            .await;
+                        let _ = tls_stream.shutdown().await;
+                        return;
+                    }
+
+                    if tls_stream
+                        .write_all(
+                            b"HTTP/1.1 101 Switching Protocols\r\n\
+                              Connection: Upgrade\r\n\
+                              Upgrade: secure-link\r\n\r\n",
+                        )
+                        .await
+                        .is_err()
+                    {
+                        return;
+                    }
+
+                    let session_lifetime = match behavior {
+                        StandInBehavior::CloseAfter(lifetime) => lifetime,
+                        _ => Duration::MAX,
+                    };
+
+                    let mut buffer = [0u8; 4096];
+
+                    let _ = tokio::time::timeout(session_lifetime, async {
+                        while let Ok(read) = tls_stream.read(&mut buffer).await {
+                            if read == 0 {
+                                break;
+                            }
+                        }
+                    })
+                    .await;
+
+                    let _ = tls_stream.shutdown().await;
+                });
+            }
+        });
+
+        Self {
+            port,
+            ca_bundle_path,
+            handshakes,
+            accept_task,
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn ca_bundle_path(&self) -> &Path {
+        &self.ca_bundle_path
+    }
+
+    // Number of global channel handshakes received, accepted or not
+    pub fn handshakes(&self) -> usize {
+        self.handshakes.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for StandInSecureLinkServer {
+    fn drop(&mut self) {
+        self.accept_task.abort();
+    }
+}
+
+// Returns the CA certificate as PEM, and the `localhost` certificate it signed
+// together with its private key
+fn generate_certificates() -> (String, CertificateDer<'static>, PrivateKeyDer<'static>) {
+    let ca_key = KeyPair::generate().unwrap();
+    let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    ca_params
+        .distinguished_name
+        .push(DnType::CommonName, "Secure Link stand-in CA");
+    ca_params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    let ca_certificate = ca_params.self_signed(&ca_key).unwrap();
+
+    let server_key = KeyPair::generate().unwrap();
+    let mut server_params = CertificateParams::new(vec!["localhost".to_string()]).unwrap();
+    server_params
+        .distinguished_name
+        .push(DnType::CommonName, "localhost");
+    let server_certificate = server_params
+        .signed_by(&server_key, &ca_certificate, &ca_key)
+        .unwrap();
+
+    (
+        ca_certificate.pem(),
+        server_certificate.der().clone(),
+        PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(server_key.serialize_der())),
+    )
+}
+
+async fn read_request_head(stream: &mut (impl AsyncRead + Unpin)) -> Option<String> {
+    let mut request_head = Vec::new();
+
+    while !request_head.ends_with(b"\r\n\r\n") {
+        if request_head.len() >= MAX_HANDSHAKE_REQUEST_LEN {
+            return None;
+        }
+
+        request_head.push(stream.read_u8().await.ok()?);
+    }
+
+    String::from_utf8(request_head).ok()
+}
+
+fn bearer_token(request_head: &str) -> Option<String> {
+    request_head.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+
+        if !name.trim().eq_ignore_ascii_case("authorization") {
+            return None;
+        }
+
+        value
+            .trim()
+            .strip_prefix("Bearer ")
+            .map(|token| token.trim().to_string())
+    })
+}
@@ -0,0 +1,105 @@
+// Everything support needs to know about a link in one struct, returned by
+// `get_status_details`. The state is tracked here, the rest is filled in from
+// the app state.
+
+use crate::client_errors::ClientErrorRecord;
+use crate::endpoint_failover::EndpointStatus;
+use crate::secure_link_client::SecureLinkClientState;
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BackendKind {
+    Embedded,
+    WindowsService,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusDetails {
+    pub state: String,
+    // Unix millis
+    pub state_entered_at: u64,
+    // `None` in builds without a client backend
+    pub backend: Option<BackendKind>,
+    pub server_host: String,
+    pub server_port: u16,
+    pub token_fingerprint: Option<String>,
+    pub last_error: Option<ClientErrorRecord>,
+    // All endpoints of the link with their health, `server_host` and
+    // `server_port` are the active one
+    pub endpoints: Vec<EndpointStatus>,
+    pub app_version: String,
+    pub features: Vec<&'static str>,
+}
+
+// The windows service wins when both backends are built in, like in
+// `default_secure_link_client_factory`
+pub fn backend_kind() -> Option<BackendKind> {
+    if cfg!(feature = "secure-link-windows-service-client") {
+        Some(BackendKind::WindowsService)
+    } else if cfg!(feature = "secure-link-embedded-client") {
+        Some(BackendKind::Embedded)
+    } else {
+        None
+    }
+}
+
+pub fn enabled_features() -> Vec<&'static str> {
+    [
+        (
+            "secure-link-embedded-client",
+            cfg!(feature = "secure-link-embedded-client"),
+        ),
+        (
+            "secure-link-windows-service-client",
+            cfg!(feature = "secure-link-windows-service-client"),
+        ),
+        ("windows-registry", cfg!(feature = "windows-registry")),
+    ]
+    .into_iter()
+    .filter_map(|(feature, enabled)| enabled.then_some(feature))
+    .collect()
+}
+
+#[derive(Default)]
+pub struct LinkStatusTracker {
+    // The last observed state and when it was entered
+    state: Mutex<Option<(SecureLinkClientState, SystemTime)>>,
+}
+
+impl LinkStatusTracker {
+    // Returns when the observed state was entered, which is now if it
+    // differs from the previous one
+    pub fn observe(&self, client_state: &SecureLinkClientState) -> SystemTime {
+        let mut state = self.state.lock().unwrap();
+
+        match &*state {
+            Some((previous_state, entered_at)) if previous_state == client_state => *entered_at,
+            _ => {
+                let entered_at = SystemTime::now();
+                *state = Some((client_state.clone(), entered_at));
+                entered_at
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_entered_at_while_the_state_holds() {
+        let tracker = LinkStatusTracker::default();
+
+        let pending_at = tracker.observe(&SecureLinkClientState::Pending);
+        assert_eq!(tracker.observe(&SecureLinkClientState::Pending), pending_at);
+
+        let running_at = tracker.observe(&SecureLinkClientState::Running);
+        assert!(running_at >= pending_at);
+        assert_eq!(tracker.observe(&SecureLinkClientState::Running), running_at);
+    }
+}
@@ -0,0 +1,1525 @@
+// Command tests run against `MockSecureLinkClient` and keep the auth token in a
+// temporary directory, so they are skipped when the token lives in the registry
+#![cfg(not(feature = "windows-registry"))]
+
+use super::*;
+use crate::mock_secure_link_client::{
+    MockCall, MockOutcome, MockSecureLinkClient, MockSecureLinkClientFactory,
+};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use tauri::test::MockRuntime;
+
+struct TestApp {
+    app: tauri::App<MockRuntime>,
+    factory: Arc<MockSecureLinkClientFactory>,
+    app_data_dir: PathBuf,
+    // Answer to the native confirmations, and the questions asked so far
+    confirms: Arc<AtomicBool>,
+    confirm_questions: Arc<Mutex<Vec<String>>>,
+}
+
+impl TestApp {
+    fn new() -> Self {
+        Self::with_policy(ManagedPolicy::default())
+    }
+
+    fn with_policy(policy: ManagedPolicy) -> Self {
+        static NEXT_TEST_APP_ID: AtomicUsize = AtomicUsize::new(0);
+
+        let app_data_dir = std::env::temp_dir().join(format!(
+            "secure_link_app_test_{}_{}",
+            std::process::id(),
+            NEXT_TEST_APP_ID.fetch_add(1, Ordering::SeqCst)
+        ));
+        std::fs::create_dir_all(&app_data_dir).unwrap();
+
+        let factory = Arc::new(MockSecureLinkClientFactory::default());
+        let app = tauri::test::mock_app();
+
+        let default_link = spawn_secure_link(
+            app.handle(),
+            &app_data_dir,
+            DEFAULT_PROFILE_ID,
+            &BandwidthLimit::default(),
+        )
+        .unwrap();
+
+        let confirms = Arc::new(AtomicBool::new(true));
+        let confirm_questions = Arc::new(Mutex::new(Vec::new()));
+        let native_confirm: NativeConfirm = {
+            let confirms = confirms.clone();
+            let confirm_questions = confirm_questions.clone();
+
+            Box::new(move |question, _| {
+                confirm_questions.lock().unwrap().push(question);
+
+                let (confirmed_sender, confirmed_receiver) = tokio::sync::oneshot::channel();
+                let _ = confirmed_sender.send(confirms.load(Ordering::SeqCst));
+                confirmed_receiver
+            })
+        };
+
+        app.manage(AppData {
+            links: Mutex::new(BTreeMap::from([(
+                DEFAULT_PROFILE_ID.to_string(),
+                default_link,
+            )])),
+            secure_link_client_factory: factory.factory(),
+            tray_menu_items: Mutex::new(None),
+            app_data_dir: app_data_dir.clone(),
+            secure_link_server_host: "localhost".to_string(),
+            secure_link_server_port: 60200,
+            settings: SettingsStore::open(&app_data_dir).unwrap(),
+            metrics_server: Mutex::new(None),
+            updater: None,
+            update_status: Mutex::new(UpdateStatus::NotConfigured),
+            downloaded_update: Mutex::new(None),
+            global_shortcuts: Mutex::new(Vec::new()),
+            shortcut_status: Mutex::new(ShortcutStatus::default()),
+            policy,
+            policy_source: None,
+            policy_error: None,
+            native_confirm,
+        });
+
+        Self {
+            app,
+            factory,
+            app_data_dir,
+            confirms,
+            confirm_questions,
+        }
+    }
+
+    fn state(&self) -> State<'_, AppData> {
+        self.app.state::<AppData>()
+    }
+
+    fn store_auth_token(&self, auth_token: &str) {
+        std::fs::write(self.app_data_dir.join("auth_token_file.txt"), auth_token).unwrap();
+    }
+
+    fn stored_auth_token(&self) -> String {
+        std::fs::read_to_string(self.app_data_dir.join("auth_token_file.txt")).unwrap()
+    }
+
+    fn connection_history(&self) -> Vec<ConnectionRecord> {
+        self.state()
+            .default_link()
+            .connection_history
+            .query(&ConnectionHistoryRange::default())
+            .unwrap()
+    }
+}
+
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.app_data_dir);
+    }
+}
+
+#[tokio::test]
+async fn current_state_is_stopped_without_auth_token() {
+    let test_app = TestApp::new();
+
+    assert_eq!(
+        current_state(test_app.state()).await,
+        Ok("Stopped".to_string())
+    );
+    assert!(test_app.factory.created_clients().is_empty());
+}
+
+#[tokio::test]
+async fn current_state_follows_client_states() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+
+    let client = MockSecureLinkClient::new();
+    client.queue_states([
+        SecureLinkClientState::Pending,
+        SecureLinkClientState::Running,
+        SecureLinkClientState::Stopped,
+    ]);
+    test_app.factory.prepare(client);
+
+    for expected_state in ["Pending", "Running", "Stopped", "Stopped"] {
+        assert_eq!(
+            current_state(test_app.state()).await,
+            Ok(expected_state.to_string())
+        );
+    }
+}
+
+#[tokio::test]
+async fn current_state_is_pending_while_start_is_in_flight() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+
+    let client = MockSecureLinkClient::new();
+    client.queue_start_with_delay(Duration::from_millis(200), MockOutcome::Ok);
+    test_app.factory.prepare(client);
+
+    let (start_result, state_during_start) = tokio::join!(start(test_app.state(), None), async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        current_state(test_app.state()).await
+    });
+
+    assert_eq!(start_result, Ok(()));
+    assert_eq!(state_during_start, Ok("Pending".to_string()));
+    assert_eq!(
+        current_state(test_app.state()).await,
+        Ok("Running".to_string())
+    );
+}
+
+#[tokio::test]
+async fn start_without_auth_token_fails() {
+    let test_app = TestApp::new();
+
+    assert_eq!(
+        start(test_app.state(), None).await,
+        Err("No auth token".to_string())
+    );
+    assert!(test_app.factory.created_clients().is_empty());
+}
+
+#[tokio::test]
+async fn start_creates_client_with_stored_auth_token() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+
+    assert_eq!(start(test_app.state(), None).await, Ok(()));
+
+    let created_clients = test_app.factory.created_clients();
+    assert_eq!(created_clients.len(), 1);
+    assert_eq!(created_clients[0].0, "token-1");
+    assert_eq!(created_clients[0].1.calls(), vec![MockCall::Start]);
+
+    assert_eq!(
+        current_state(test_app.state()).await,
+        Ok("Running".to_string())
+    );
+}
+
+#[tokio::test]
+async fn start_reuses_existing_client() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+
+    assert_eq!(start(test_app.state(), None).await, Ok(()));
+    assert_eq!(
+        stop(test_app.state()).await,
+        Ok(SecureLinkClientStopOutcome::Clean)
+    );
+    assert_eq!(start(test_app.state(), None).await, Ok(()));
+
+    let created_clients = test_app.factory.created_clients();
+    assert_eq!(created_clients.len(), 1);
+    assert_eq!(
+        created_clients[0].1.calls(),
+        vec![MockCall::Start, MockCall::Stop, MockCall::Start]
+    );
+}
+
+#[tokio::test]
+async fn start_reports_unauthorized() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+
+    let client = MockSecureLinkClient::new();
+    client.queue_start(MockOutcome::Unauthorized);
+    test_app.factory.prepare(client);
+
+    assert_eq!(
+        start(test_app.state(), None).await,
+        Err("UnauthorizedError".to_string())
+    );
+
+    let connection_history = test_app.connection_history();
+    assert_eq!(connection_history.len(), 1);
+    assert_eq!(
+        connection_history[0].outcome,
+        ConnectionOutcome::Unauthorized
+    );
+}
+
+#[tokio::test]
+async fn status_details_report_the_last_error() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+
+    let client = MockSecureLinkClient::new();
+    client.queue_start(MockOutcome::Unauthorized);
+    test_app.factory.prepare(client);
+
+    assert!(start(test_app.state(), None).await.is_err());
+
+    let details = get_status_details(test_app.app.handle().clone(), test_app.state(), None)
+        .await
+        .unwrap();
+
+    assert_eq!(details.state, "Stopped");
+    assert_eq!(details.server_host, "localhost");
+    assert_eq!(details.server_port, 60200);
+    assert_eq!(
+        details.token_fingerprint,
+        Some(AuthToken::new("token-1".to_string()).fingerprint())
+    );
+    assert_eq!(details.last_error.unwrap().message, "UnauthorizedError");
+    assert_eq!(details.features, status_details::enabled_features());
+
+    assert_eq!(
+        get_status_details(
+            test_app.app.handle().clone(),
+            test_app.state(),
+            Some("missing".to_string())
+        )
+        .await,
+        Err(unknown_profile_error("missing"))
+    );
+}
+
+#[tokio::test]
+async fn failed_starts_are_kept_in_recent_errors() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+
+    let client = MockSecureLinkClient::new();
+    client.queue_start(MockOutcome::Unauthorized);
+    client.queue_start(MockOutcome::NetworkError("connection refused"));
+    test_app.factory.prepare(client);
+
+    assert!(start(test_app.state(), None).await.is_err());
+    assert!(start(test_app.state(), None).await.is_err());
+
+    let recent_errors = get_recent_errors(test_app.state(), None).await.unwrap();
+    assert_eq!(
+        recent_errors
+            .iter()
+            .map(|error| error.category)
+            .collect::<Vec<_>>(),
+        vec![
+            ClientErrorCategory::Unauthorized,
+            ClientErrorCategory::Network
+        ]
+    );
+    assert!(recent_errors[0].id < recent_errors[1].id);
+}
+
+#[tokio::test]
+async fn start_reports_certificate_pin_mismatch() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+
+    let client = MockSecureLinkClient::new();
+    client.queue_start(MockOutcome::CertificatePinMismatch);
+    test_app.factory.prepare(client);
+
+    assert_eq!(
+        start(test_app.state(), None).await,
+        Err("CertificatePinMismatch".to_string())
+    );
+}
+
+#[tokio::test]
+async fn start_reports_network_error() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+
+    let client = MockSecureLinkClient::new();
+    client.queue_start(MockOutcome::NetworkError("connection refused"));
+    test_app.factory.prepare(client);
+
+    let error = start(test_app.state(), None).await.unwrap_err();
+    assert!(error.starts_with("NetworkError"), "{error}");
+
+    assert_eq!(
+        current_state(test_app.state()).await,
+        Ok("Stopped".to_string())
+    );
+    assert_eq!(
+        test_app.connection_history()[0].outcome,
+        ConnectionOutcome::NetworkError
+    );
+}
+
+#[tokio::test]
+async fn start_fails_over_to_the_next_endpoint() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+
+    let client = MockSecureLinkClient::new();
+    client.queue_start(MockOutcome::NetworkError("connection refused"));
+    test_app.factory.prepare(client);
+
+    update_settings(
+        test_app.app.handle().clone(),
+        test_app.state(),
+        serde_json::json!({"endpoints": [
+            {"host": "backup.example.com", "port": 443, "priority": 1},
+            {"host": "primary.example.com", "port": 443},
+        ]}),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(start(test_app.state(), None).await, Ok(()));
+
+    assert_eq!(test_app.factory.created_clients().len(), 2);
+    assert_eq!(
+        secure_link_server_address(&test_app.state(), DEFAULT_PROFILE_ID),
+        "backup.example.com:443"
+    );
+
+    let history = test_app.connection_history();
+    assert_eq!(history[0].outcome, ConnectionOutcome::NetworkError);
+    assert_eq!(history[0].server, "primary.example.com:443");
+
+    let details = get_status_details(test_app.app.handle().clone(), test_app.state(), None)
+        .await
+        .unwrap();
+    let endpoint_health: Vec<_> = details
+        .endpoints
+        .iter()
+        .map(|endpoint| {
+            (
+                endpoint.host.as_str(),
+                endpoint.active,
+                endpoint.cooldown_remaining_ms.is_some(),
+            )
+        })
+        .collect();
+
+    assert_eq!(details.server_host, "backup.example.com");
+    assert_eq!(
+        endpoint_health,
+        vec![
+            ("backup.example.com", true, false),
+            ("primary.example.com", false, true),
+        ]
+    );
+}
+
+fn always_blocked_schedule() -> ScheduleConfig {
+    use chrono::Weekday;
+
+    ScheduleConfig {
+        enabled: true,
+        time_zone: "UTC".to_string(),
+        rules: vec![connection_schedule::ScheduleRule {
+            action: connection_schedule::ScheduleRuleAction::Block,
+            days: vec![
+                Weekday::Mon,
+                Weekday::Tue,
+                Weekday::Wed,
+                Weekday::Thu,
+                Weekday::Fri,
+                Weekday::Sat,
+                Weekday::Sun,
+            ],
+            start: None,
+            end: None,
+        }],
+    }
+}
+
+#[tokio::test]
+async fn start_outside_schedule_window_needs_override() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+    test_app
+        .state()
+        .settings
+        .update_with(|settings| settings.schedule = always_blocked_schedule())
+        .unwrap();
+
+    assert_eq!(
+        start(test_app.state(), None).await,
+        Err("OutsideScheduleWindow".to_string())
+    );
+    assert!(test_app.factory.created_clients().is_empty());
+
+    assert_eq!(start(test_app.state(), Some(true)).await, Ok(()));
+    assert_eq!(
+        current_state(test_app.state()).await,
+        Ok("Running".to_string())
+    );
+}
+
+#[tokio::test]
+async fn tray_start_outside_schedule_window_is_refused() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+    test_app
+        .state()
+        .settings
+        .update_with(|settings| settings.schedule = always_blocked_schedule())
+        .unwrap();
+
+    assert!(
+        tray_start(&test_app.state(), &test_app.state().default_link())
+            .await
+            .is_err()
+    );
+    assert!(test_app.factory.created_clients().is_empty());
+}
+
+#[tokio::test]
+async fn stop_without_client_does_nothing() {
+    let test_app = TestApp::new();
+
+    assert_eq!(
+        stop(test_app.state()).await,
+        Ok(SecureLinkClientStopOutcome::Clean)
+    );
+    assert!(test_app.factory.created_clients().is_empty());
+}
+
+#[tokio::test]
+async fn stop_stops_running_client() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+
+    let client = MockSecureLinkClient::new();
+    client.set_traffic(SecureLinkTraffic {
+        bytes_sent: 512,
+        bytes_received: 4096,
+    });
+    test_app.factory.prepare(client);
+
+    assert_eq!(start(test_app.state(), None).await, Ok(()));
+    assert_eq!(
+        stop(test_app.state()).await,
+        Ok(SecureLinkClientStopOutcome::Clean)
+    );
+
+    let created_clients = test_app.factory.created_clients();
+    assert_eq!(
+        created_clients[0].1.calls(),
+        vec![MockCall::Start, MockCall::Stop]
+    );
+    assert_eq!(
+        current_state(test_app.state()).await,
+        Ok("Stopped".to_string())
+    );
+
+    let connection_history = test_app.connection_history();
+    assert_eq!(connection_history.len(), 1);
+    assert_eq!(connection_history[0].outcome, ConnectionOutcome::UserStop);
+    assert_eq!(connection_history[0].server, "localhost:60200");
+    assert_eq!(connection_history[0].bytes_sent, Some(512));
+    assert_eq!(connection_history[0].bytes_received, Some(4096));
+}
+
+#[tokio::test]
+async fn stop_reports_forced_stop() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+
+    let client = MockSecureLinkClient::new();
+    client.queue_stop(MockOutcome::ForcedStop);
+    test_app.factory.prepare(client);
+
+    assert_eq!(start(test_app.state(), None).await, Ok(()));
+    assert_eq!(
+        stop(test_app.state()).await,
+        Ok(SecureLinkClientStopOutcome::Forced)
+    );
+    assert_eq!(
+        test_app.connection_history()[0].outcome,
+        ConnectionOutcome::UserStop
+    );
+}
+
+#[tokio::test]
+async fn idle_stop_is_recorded_as_idle_timeout() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+
+    assert_eq!(start(test_app.state(), None).await, Ok(()));
+    assert_eq!(
+        test_app
+            .state()
+            .default_link()
+            .supervisor
+            .stop_with_reason(SecureLinkClientStopReason::IdleTimeout)
+            .await,
+        Ok(SecureLinkClientStopOutcome::Clean)
+    );
+
+    let connection_history = test_app.connection_history();
+    assert_eq!(connection_history.len(), 1);
+    assert_eq!(
+        connection_history[0].outcome,
+        ConnectionOutcome::IdleTimeout
+    );
+}
+
+#[tokio::test]
+async fn metrics_count_established_sessions_only() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+
+    let client = MockSecureLinkClient::new();
+    client.queue_start(MockOutcome::Unauthorized);
+    test_app.factory.prepare(client);
+
+    assert!(start(test_app.state(), None).await.is_err());
+    assert_eq!(
+        stop(test_app.state()).await,
+        Ok(SecureLinkClientStopOutcome::Clean)
+    );
+
+    assert!(test_app
+        .state()
+        .default_link()
+        .metrics
+        .counters()
+        .disconnects
+        .is_empty());
+
+    assert_eq!(start(test_app.state(), None).await, Ok(()));
+    assert_eq!(
+        stop(test_app.state()).await,
+        Ok(SecureLinkClientStopOutcome::Clean)
+    );
+
+    let counters = test_app.state().default_link().metrics.counters();
+    assert_eq!(counters.connect_attempts, 2);
+    assert_eq!(counters.reconnect_attempts, 1);
+    assert_eq!(counters.connects, 1);
+    assert_eq!(counters.connect_failures.get("unauthorized"), Some(&1));
+    assert_eq!(counters.disconnects.get("user_stop"), Some(&1));
+
+    let text = render_all_metrics(&test_app.state()).await;
+    assert!(text.contains("secure_link_state{profile=\"default\",state=\"stopped\"} 1\n"));
+    assert!(text.contains(
+        "secure_link_connect_failures_total{profile=\"default\",reason=\"unauthorized\"} 1\n"
+    ));
+}
+
+#[tokio::test]
+async fn current_state_is_stopping_while_stop_is_in_flight() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+
+    let client = MockSecureLinkClient::new();
+    client.queue_stop_with_delay(Duration::from_millis(200), MockOutcome::Ok);
+    test_app.factory.prepare(client);
+
+    assert_eq!(start(test_app.state(), None).await, Ok(()));
+
+    let (stop_result, state_during_stop) = tokio::join!(stop(test_app.state()), async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        current_state(test_app.state()).await
+    });
+
+    assert_eq!(stop_result, Ok(SecureLinkClientStopOutcome::Clean));
+    assert_eq!(state_during_stop, Ok("Stopping".to_string()));
+    assert_eq!(
+        current_state(test_app.state()).await,
+        Ok("Stopped".to_string())
+    );
+}
+
+#[tokio::test]
+async fn stop_reports_client_error() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+
+    let client = MockSecureLinkClient::new();
+    client.queue_stop(MockOutcome::NetworkError("stop failed"));
+    test_app.factory.prepare(client);
+
+    assert_eq!(start(test_app.state(), None).await, Ok(()));
+    assert!(stop(test_app.state()).await.is_err());
+}
+
+#[tokio::test]
+async fn update_auth_token_stores_token_and_replaces_client() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+
+    assert_eq!(start(test_app.state(), None).await, Ok(()));
+    assert_eq!(
+        update_auth_token(test_app.state(), AuthToken::new("token-2".to_string())).await,
+        Ok(())
+    );
+
+    assert_eq!(test_app.stored_auth_token(), "token-2");
+
+    let created_clients = test_app.factory.created_clients();
+    assert_eq!(created_clients.len(), 2);
+    assert_eq!(
+        created_clients[0].1.calls(),
+        vec![MockCall::Start, MockCall::Stop]
+    );
+    assert_eq!(created_clients[1].0, "token-2");
+    assert!(created_clients[1].1.calls().is_empty());
+}
+
+#[tokio::test]
+async fn update_auth_token_with_same_token_keeps_client() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+
+    assert_eq!(start(test_app.state(), None).await, Ok(()));
+    assert_eq!(
+        update_auth_token(test_app.state(), AuthToken::new("token-1".to_string())).await,
+        Ok(())
+    );
+
+    let created_clients = test_app.factory.created_clients();
+    assert_eq!(created_clients.len(), 1);
+    assert_eq!(created_clients[0].1.calls(), vec![MockCall::Start]);
+}
+
+#[tokio::test]
+async fn update_auth_token_without_previous_token_creates_client() {
+    let test_app = TestApp::new();
+
+    assert_eq!(
+        update_auth_token(test_app.state(), AuthToken::new("token-1".to_string())).await,
+        Ok(())
+    );
+
+    assert_eq!(test_app.stored_auth_token(), "token-1");
+    assert_eq!(test_app.factory.created_clients()[0].0, "token-1");
+}
+
+#[tokio::test]
+async fn auth_token_info_does_not_contain_the_token() {
+    let test_app = TestApp::new();
+
+    assert_eq!(
+        get_auth_token_info(test_app.state()).await,
+        Ok(AuthTokenInfo::default())
+    );
+
+    test_app.store_auth_token("opaque-token-0123456789");
+
+    let info = get_auth_token_info(test_app.state()).await.unwrap();
+    assert!(info.present);
+    assert_eq!(info.masked_preview.as_deref(), Some("opaq…6789"));
+    assert!(info.stored_at.is_some());
+    assert_eq!(info.expires_at, None);
+    assert!(!serde_json::to_string(&info)
+        .unwrap()
+        .contains("opaque-token-0123456789"));
+}
+
+#[tokio::test]
+async fn import_auth_token_from_file_creates_the_enrolled_profile() {
+    let test_app = TestApp::new();
+
+    let plain_text_path = test_app.app_data_dir.join("token.txt");
+    std::fs::write(&plain_text_path, "token-1\n").unwrap();
+
+    let imported = import_auth_token_from_file(
+        test_app.app.handle().clone(),
+        test_app.state(),
+        Some(plain_text_path),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(imported.profile_id, DEFAULT_PROFILE_ID);
+    assert!(imported.token.present);
+    assert_eq!(test_app.stored_auth_token(), "token-1");
+
+    let enrollment_path = test_app.app_data_dir.join("enrollment.json");
+    std::fs::write(
+        &enrollment_path,
+        r#"{"token": "token-2", "endpoint": {"host": "office.example.com", "port": 8443}, "profileName": "Main Office"}"#,
+    )
+    .unwrap();
+
+    let imported = import_auth_token_from_file(
+        test_app.app.handle().clone(),
+        test_app.state(),
+        Some(enrollment_path),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(imported.profile_id, "main-office");
+    assert_eq!(
+        std::fs::read_to_string(
+            test_app
+                .app_data_dir
+                .join("auth_token_file_main-office.txt")
+        )
+        .unwrap(),
+        "token-2"
+    );
+    assert_eq!(
+        secure_link_server_address(&test_app.state(), "main-office"),
+        "office.example.com:8443"
+    );
+    assert_eq!(test_app.stored_auth_token(), "token-1");
+
+    let mistyped_path = test_app.app_data_dir.join("mistyped.txt");
+    std::fs::write(&mistyped_path, "token 3").unwrap();
+
+    assert!(import_auth_token_from_file(
+        test_app.app.handle().clone(),
+        test_app.state(),
+        Some(mistyped_path),
+    )
+    .await
+    .is_err());
+    assert_eq!(test_app.stored_auth_token(), "token-1");
+}
+
+#[tokio::test]
+async fn enroll_with_code_stores_the_device_token() {
+    use crate::stand_in_enrollment_server::{StandInEnrollmentServer, StandInEnrollmentState};
+
+    let test_app = TestApp::new();
+
+    assert_eq!(
+        enroll_with_code(
+            test_app.app.handle().clone(),
+            test_app.state(),
+            "ABCD-1234".to_string()
+        )
+        .await
+        .unwrap_err(),
+        EnrollmentError::NotConfigured
+    );
+
+    let server = StandInEnrollmentServer::start().await;
+    server.add_code(
+        "ABCD1234",
+        StandInEnrollmentState::Valid(serde_json::json!({
+            "token": "device-token-1",
+            "endpoint": {"host": "link.example.com", "port": 8443},
+        })),
+    );
+
+    update_settings(
+        test_app.app.handle().clone(),
+        test_app.state(),
+        serde_json::json!({"enrollment": {"url": server.url()}}),
+    )
+    .await
+    .unwrap();
+
+    let enrolled = enroll_with_code(
+        test_app.app.handle().clone(),
+        test_app.state(),
+        "abcd-1234".to_string(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(enrolled.profile_id, DEFAULT_PROFILE_ID);
+    assert_eq!(test_app.stored_auth_token(), "device-token-1");
+    assert_eq!(
+        secure_link_server_address(&test_app.state(), DEFAULT_PROFILE_ID),
+        "link.example.com:8443"
+    );
+
+    assert_eq!(
+        enroll_with_code(
+            test_app.app.handle().clone(),
+            test_app.state(),
+            "ABCD1234".to_string()
+        )
+        .await
+        .unwrap_err(),
+        EnrollmentError::CodeAlreadyUsed
+    );
+
+    // The same device id every time
+    let device_ids = server.device_ids();
+    assert_eq!(device_ids.len(), 2);
+    assert_eq!(device_ids[0], device_ids[1]);
+}
+
+#[tokio::test]
+async fn reinitialize_secure_link_client_stops_and_recreates_client() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+
+    assert_eq!(start(test_app.state(), None).await, Ok(()));
+
+    assert_eq!(
+        reinitialize_secure_link_client(&test_app.state().default_link()).await,
+        Ok(())
+    );
+
+    let created_clients = test_app.factory.created_clients();
+    assert_eq!(created_clients.len(), 2);
+    assert_eq!(
+        created_clients[0].1.calls(),
+        vec![MockCall::Start, MockCall::Stop]
+    );
+    assert_eq!(
+        test_app.connection_history()[0].outcome,
+        ConnectionOutcome::UserStop
+    );
+}
+
+#[tokio::test]
+async fn update_settings_with_new_endpoint_recreates_client() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+
+    assert_eq!(start(test_app.state(), None).await, Ok(()));
+
+    let settings = update_settings(
+        test_app.app.handle().clone(),
+        test_app.state(),
+        serde_json::json!({"endpoint": {"host": "link.example.com", "port": 443}}),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        settings
+            .endpoint
+            .map(|endpoint| (endpoint.host, endpoint.port)),
+        Some(("link.example.com".to_string(), 443))
+    );
+
+    let created_clients = test_app.factory.created_clients();
+    assert_eq!(created_clients.len(), 2);
+    assert_eq!(
+        created_clients[0].1.calls(),
+        vec![MockCall::Start, MockCall::Stop]
+    );
+    assert_eq!(
+        secure_link_server_address(&test_app.state(), DEFAULT_PROFILE_ID),
+        "link.example.com:443"
+    );
+}
+
+#[tokio::test]
+async fn update_settings_without_client_changes_keeps_client() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+
+    assert_eq!(start(test_app.state(), None).await, Ok(()));
+
+    let settings = update_settings(
+        test_app.app.handle().clone(),
+        test_app.state(),
+        serde_json::json!({"locale": "en", "notifications": false}),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(settings.locale.as_deref(), Some("en"));
+    assert!(!settings.notifications);
+    assert_eq!(test_app.factory.created_clients().len(), 1);
+}
+
+#[tokio::test]
+async fn update_settings_rejects_invalid_patch() {
+    let test_app = TestApp::new();
+
+    assert!(update_settings(
+        test_app.app.handle().clone(),
+        test_app.state(),
+        serde_json::json!({"endpoint": {"host": " ", "port": 443}}),
+    )
+    .await
+    .is_err());
+    assert_eq!(test_app.state().settings.get(), Settings::default());
+}
+
+#[tokio::test]
+async fn proxy_password_is_kept_out_of_the_settings() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+
+    assert_eq!(start(test_app.state(), None).await, Ok(()));
+
+    let server_config: ServerConfig = serde_json::from_value(serde_json::json!({
+        "proxy": {"mode": "httpConnect", "host": "proxy.local", "port": 3128, "username": "user", "password": "secret"}
+    }))
+    .unwrap();
+
+    update_server_config(
+        test_app.app.handle().clone(),
+        test_app.state(),
+        server_config,
+    )
+    .await
+    .unwrap();
+
+    let settings_file =
+        std::fs::read_to_string(test_app.app_data_dir.join("settings.json")).unwrap();
+    assert!(settings_file.contains("proxy.local"));
+    assert!(!settings_file.contains("secret"));
+
+    let view = serde_json::to_value(get_server_config(test_app.state()).await.unwrap()).unwrap();
+    assert_eq!(view["proxyPasswordSet"], true);
+    assert_eq!(view["proxy"].get("password"), None);
+
+    assert_eq!(
+        client_server_config(&test_app.state())
+            .proxy
+            .server()
+            .and_then(|proxy_server| proxy_server.password.clone()),
+        Some("secret".to_string())
+    );
+
+    // Only the password changes, the link still gets a new client
+    update_settings(
+        test_app.app.handle().clone(),
+        test_app.state(),
+        serde_json::json!({"server": {"proxy": {"password": null}}}),
+    )
+    .await
+    .unwrap();
+
+    let view = serde_json::to_value(get_server_config(test_app.state()).await.unwrap()).unwrap();
+    assert_eq!(view["proxyPasswordSet"], false);
+    assert_eq!(test_app.factory.created_clients().len(), 3);
+}
+
+#[tokio::test]
+async fn reinitialize_secure_link_client_propagates_stop_error() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+
+    let client = MockSecureLinkClient::new();
+    client.queue_stop(MockOutcome::NetworkError("stop failed"));
+    test_app.factory.prepare(client);
+
+    assert_eq!(start(test_app.state(), None).await, Ok(()));
+    assert!(
+        reinitialize_secure_link_client(&test_app.state().default_link())
+            .await
+            .is_err()
+    );
+    assert_eq!(test_app.factory.created_clients().len(), 1);
+}
+
+#[tokio::test]
+async fn reinitialize_secure_link_client_without_auth_token_creates_nothing() {
+    let test_app = TestApp::new();
+
+    assert_eq!(
+        reinitialize_secure_link_client(&test_app.state().default_link()).await,
+        Ok(())
+    );
+    assert!(test_app.factory.created_clients().is_empty());
+}
+
+#[tokio::test]
+async fn update_auth_token_during_start_is_serialized() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+
+    let client = MockSecureLinkClient::new();
+    client.queue_start_with_delay(Duration::from_millis(200), MockOutcome::Ok);
+    test_app.factory.prepare(client);
+
+    let (start_result, update_result) = tokio::join!(start(test_app.state(), None), async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        update_auth_token(test_app.state(), AuthToken::new("token-2".to_string())).await
+    });
+
+    assert_eq!(start_result, Ok(()));
+    assert_eq!(update_result, Ok(()));
+
+    let created_clients = test_app.factory.created_clients();
+    assert_eq!(created_clients.len(), 2);
+    assert_eq!(
+        created_clients[0].1.calls(),
+        vec![MockCall::Start, MockCall::Stop]
+    );
+    assert_eq!(created_clients[1].0, "token-2");
+
+    // The session of the first client was recorded as started before it ended
+    let connection_history = test_app.connection_history();
+    assert_eq!(connection_history.len(), 1);
+    assert_eq!(connection_history[0].outcome, ConnectionOutcome::UserStop);
+}
+
+#[test]
+fn tray_menu_enables_connect_only_when_stopped() {
+    assert_eq!(
+        tray_menu_enabled_states(&SecureLinkClientState::Stopped),
+        (true, false)
+    );
+    assert_eq!(
+        tray_menu_enabled_states(&SecureLinkClientState::Pending),
+        (false, true)
+    );
+    assert_eq!(
+        tray_menu_enabled_states(&SecureLinkClientState::Running),
+        (false, true)
+    );
+    assert_eq!(
+        tray_menu_enabled_states(&SecureLinkClientState::Stopping),
+        (false, false)
+    );
+}
+
+async fn add_staging_profile(test_app: &TestApp) {
+    update_settings(
+        test_app.app.handle().clone(),
+        test_app.state(),
+        serde_json::json!({"profiles": [{
+            "id": "staging",
+            "name": "Staging",
+            "endpoint": {"host": "staging.example.com", "port": 443},
+        }]}),
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn bandwidth_limit_applies_to_every_link_on_its_own() {
+    let test_app = TestApp::new();
+    update_settings(
+        test_app.app.handle().clone(),
+        test_app.state(),
+        serde_json::json!({"bandwidth": {"uploadBytesPerSecond": 1000}}),
+    )
+    .await
+    .unwrap();
+    add_staging_profile(&test_app).await;
+
+    let default_link = test_app.state().default_link();
+    let staging_link = test_app.state().link("staging").unwrap();
+    default_link.bandwidth_limiter.consume_upload(1000);
+
+    let now = Instant::now();
+    assert!(default_link
+        .bandwidth_limiter
+        .upload_available(now)
+        .is_err());
+    assert_eq!(
+        staging_link.bandwidth_limiter.upload_available(now),
+        Ok(1000)
+    );
+}
+
+#[tokio::test]
+async fn idle_status_reports_the_first_link_to_be_disconnected() {
+    let test_app = TestApp::new();
+    add_staging_profile(&test_app).await;
+
+    let staging_link = test_app.state().link("staging").unwrap();
+    *test_app.state().default_link().idle_check.lock().unwrap() = IdleCheck::Warning {
+        disconnect_in_ms: 40_000,
+    };
+    *staging_link.idle_check.lock().unwrap() = IdleCheck::Warning {
+        disconnect_in_ms: 10_000,
+    };
+
+    assert_eq!(
+        get_idle_status(test_app.state()).await,
+        Ok(IdleCheck::Warning {
+            disconnect_in_ms: 10_000
+        })
+    );
+
+    keep_secure_link_connected(test_app.state(), Some("staging".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(
+        get_idle_status(test_app.state()).await,
+        Ok(IdleCheck::Warning {
+            disconnect_in_ms: 40_000
+        })
+    );
+
+    keep_secure_link_connected(test_app.state(), None)
+        .await
+        .unwrap();
+    assert_eq!(
+        get_idle_status(test_app.state()).await,
+        Ok(IdleCheck::Active)
+    );
+
+    assert_eq!(
+        keep_secure_link_connected(test_app.state(), Some("missing".to_string())).await,
+        Err("UnknownProfile: missing".to_string())
+    );
+}
+
+#[tokio::test]
+async fn profile_links_run_alongside_the_default_link() {
+    let test_app = TestApp::new();
+    test_app.store_auth_token("token-1");
+    std::fs::write(
+        test_app.app_data_dir.join("auth_token_file_staging.txt"),
+        "token-2",
+    )
+    .unwrap();
+
+    add_staging_profile(&test_app).await;
+
+    assert_eq!(start(test_app.state(), None).await, Ok(()));
+    assert_eq!(
+        start_link(test_app.state(), "staging".to_string(), None).await,
+        Ok(())
+    );
+
+    let links = list_links(test_app.state()).await.unwrap();
+    assert_eq!(
+        links
+            .iter()
+            .map(|link| (
+                link.profile_id.as_str(),
+                link.state.as_str(),
+                link.address.as_str()
+            ))
+            .collect::<Vec<_>>(),
+        vec![
+            ("default", "Running", "localhost:60200"),
+            ("staging", "Running", "staging.example.com:443"),
+        ]
+    );
+
+    assert_eq!(
+        stop_link(test_app.state(), "staging".to_string()).await,
+        Ok(SecureLinkClientStopOutcome::Clean)
+    );
+    assert_eq!(
+        current_state(test_app.state()).await,
+        Ok("Running".to_string())
+    );
+
+    let mut auth_tokens: Vec<String> = test_app
+        .factory
+        .created_clients()
+        .into_iter()
+        .map(|(auth_token, _)| auth_token)
+        .collect();
+    auth_tokens.sort();
+    assert_eq!(auth_tokens, vec!["token-1", "token-2"]);
+
+    // Each link keeps its own history
+    let staging_history = get_connection_history(
+        test_app.state(),
+        ConnectionHistoryRange::default(),
+        Some("staging".to_string()),
+    )
+    .await
+    .unwrap();
+    assert_eq!(staging_history.len(), 1);
+    assert_eq!(staging_history[0].outcome, ConnectionOutcome::UserStop);
+    assert!(test_app.connection_history().is_empty());
+}
+
+#[tokio::test]
+async fn removing_a_profile_stops_its_link() {
+    let test_app = TestApp::new();
+    std::fs::write(
+        test_app.app_data_dir.join("auth_token_file_staging.txt"),
+        "token-2",
+    )
+    .unwrap();
+
+    add_staging_profile(&test_app).await;
+
+    assert_eq!(
+        start_link(test_app.state(), "staging".to_string(), None).await,
+        Ok(())
+    );
+
+    update_settings(
+        test_app.app.handle().clone(),
+        test_app.state(),
+        serde_json::json!({"profiles": null}),
+    )
+    .await
+    .unwrap();
+
+    let created_clients = test_app.factory.created_clients();
+    assert_eq!(created_clients.len(), 1);
+    assert_eq!(
+        created_clients[0].1.calls(),
+        vec![MockCall::Start, MockCall::Stop]
+    );
+    assert_eq!(
+        start_link(test_app.state(), "staging".to_string(), None).await,
+        Err("UnknownProfile: staging".to_string())
+    );
+    assert_eq!(list_links(test_app.state()).await.unwrap().len(), 1);
+}
+
+// Keeps the log lines of every test in this binary
+struct CapturingLogger {
+    lines: Mutex<Vec<String>>,
+}
+
+impl log::Log for CapturingLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.lines.lock().unwrap().push(format!(
+            "{} {}: {}",
+            record.level(),
+            record.target(),
+            record.args()
+        ));
+    }
+
+    fn flush(&self) {}
+}
+
+fn capturing_logger() -> &'static CapturingLogger {
+    static LOGGER: CapturingLogger = CapturingLogger {
+        lines: Mutex::new(Vec::new()),
+    };
+    static INSTALL: std::sync::Once = std::sync::Once::new();
+
+    INSTALL.call_once(|| {
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+    });
+
+    &LOGGER
+}
+
+#[tokio::test]
+async fn log_lines_never_contain_the_auth_token() {
+    let logger = capturing_logger();
+    let test_app = TestApp::new();
+    test_app.store_auth_token("stored-secret-token");
+
+    let client = MockSecureLinkClient::new();
+    client.queue_start(MockOutcome::Unauthorized);
+    test_app.factory.prepare(client);
+
+    assert!(start(test_app.state(), None).await.is_err());
+
+    let replacing_auth_token = AuthToken::new("replacing-secret-token".to_string());
+    let fingerprint = replacing_auth_token.fingerprint();
+
+    assert_eq!(
+        update_auth_token(test_app.state(), replacing_auth_token).await,
+        Ok(())
+    );
+    assert_eq!(start(test_app.state(), None).await, Ok(()));
+    assert!(stop(test_app.state()).await.is_ok());
+
+    let lines = logger.lines.lock().unwrap();
+
+    assert!(lines.iter().any(|line| line.contains(&fingerprint)));
+    assert!(!lines.iter().any(
+        |line| line.contains("stored-secret-token") || line.contains("replacing-secret-token")
+    ));
+}
+
+#[tokio::test]
+async fn managed_policy_rejects_disconnecting_and_token_editing() {
+    let test_app = TestApp::with_policy(ManagedPolicy {
+        forbid_disconnect: true,
+        forbid_token_editing: true,
+        ..Default::default()
+    });
+    test_app.store_auth_token("token-1");
+    test_app.factory.prepare(MockSecureLinkClient::new());
+
+    assert_eq!(start(test_app.state(), None).await, Ok(()));
+
+    let stop_error = stop(test_app.state()).await.unwrap_err();
+    assert!(stop_error.starts_with("PolicyViolation: "));
+    assert!(!test_app.factory.created_clients()[0]
+        .1
+        .calls()
+        .contains(&MockCall::Stop));
+
+    let update_error = update_auth_token(test_app.state(), AuthToken::new("token-2".to_string()))
+        .await
+        .unwrap_err();
+    assert!(update_error.starts_with("PolicyViolation: "));
+    assert_eq!(test_app.stored_auth_token(), "token-1");
+
+    let policy_status = get_managed_policy(test_app.state()).await.unwrap();
+    assert_eq!(
+        policy_status.locked_settings,
+        vec![
+            managed_policy::LockedSetting::Disconnect,
+            managed_policy::LockedSetting::AuthToken
+        ]
+    );
+}
+
+#[tokio::test]
+async fn managed_policy_refuses_idle_timeouts_and_schedules_while_disconnecting_is_forbidden() {
+    let test_app = TestApp::with_policy(ManagedPolicy {
+        forbid_disconnect: true,
+        ..Default::default()
+    });
+    let app = test_app.app.handle().clone();
+
+    let update_error = update_server_config(
+        app.clone(),
+        test_app.state(),
+        ServerConfig {
+            idle_timeout_minutes: Some(30),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap_err();
+    assert!(update_error.starts_with("PolicyViolation: "));
+
+    let update_error =
+        update_schedule_config(app.clone(), test_app.state(), always_blocked_schedule())
+            .await
+            .unwrap_err();
+    assert!(update_error.starts_with("PolicyViolation: "));
+
+    let update_error = update_settings(
+        app.clone(),
+        test_app.state(),
+        serde_json::json!({"server": {"idleTimeoutMinutes": 30}}),
+    )
+    .await
+    .unwrap_err();
+    assert!(update_error.starts_with("PolicyViolation: "));
+    assert_eq!(
+        test_app.state().settings.get().server.idle_timeout_minutes,
+        None
+    );
+    assert!(!test_app.state().settings.get().schedule.enabled);
+
+    // Everything else stays editable
+    assert!(update_server_config(
+        app,
+        test_app.state(),
+        ServerConfig {
+            stop_timeout_ms: Some(1000),
+            ..Default::default()
+        },
+    )
+    .await
+    .is_ok());
+}
+
+#[tokio::test]
+async fn changes_of_where_tokens_go_need_a_native_confirmation() {
+    let test_app = TestApp::new();
+    let app = test_app.app.handle().clone();
+    test_app.confirms.store(false, Ordering::SeqCst);
+
+    let update_error = update_settings(
+        app.clone(),
+        test_app.state(),
+        serde_json::json!({"endpoint": {"host": "attacker.example", "port": 443}}),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(update_error, "ChangeCancelled");
+    assert_eq!(test_app.state().settings.get().endpoint, None);
+
+    let update_error = update_server_config(
+        app.clone(),
+        test_app.state(),
+        serde_json::from_value(serde_json::json!({
+            "trust": {"caBundlePath": "/tmp/attacker-ca.pem"}
+        }))
+        .unwrap(),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(update_error, "ChangeCancelled");
+    assert_eq!(test_app.confirm_questions.lock().unwrap().len(), 2);
+
+    // Nothing to confirm for the rest of the settings
+    assert!(update_settings(
+        app.clone(),
+        test_app.state(),
+        serde_json::json!({"notifications": false})
+    )
+    .await
+    .is_ok());
+    assert_eq!(test_app.confirm_questions.lock().unwrap().len(), 2);
+
+    test_app.confirms.store(true, Ordering::SeqCst);
+
+    let settings = update_settings(
+        app,
+        test_app.state(),
+        serde_json::json!({"endpoint": {"host": "link.example.com", "port": 443}}),
+    )
+    .await
+    .unwrap();
+    assert_eq!(settings.endpoint.unwrap().host, "link.example.com");
+}
+
+#[tokio::test]
+async fn managed_policy_pins_the_endpoint() {
+    let pinned_endpoint = EndpointSettings {
+        host: "link.corp.example".to_string(),
+        port: 8443,
+        priority: 0,
+        weight: 1,
+    };
+    let test_app = TestApp::with_policy(ManagedPolicy {
+        endpoint: Some(pinned_endpoint.clone()),
+        ..Default::default()
+    });
+
+    assert_eq!(
+        link_endpoints(&test_app.state(), DEFAULT_PROFILE_ID),
+        vec![pinned_endpoint]
+    );
+
+    let app = test_app.app.handle().clone();
+    let update_error = update_settings(
+        app.clone(),
+        test_app.state(),
+        serde_json::json!({"endpoint": {"host": "other.example", "port": 443}}),
+    )
+    .await
+    .unwrap_err();
+    assert!(update_error.starts_with("PolicyViolation: "));
+
+    // The proxy and the trust could send the link elsewhere
+    let update_error = update_server_config(
+        app.clone(),
+        test_app.state(),
+        ServerConfig {
+            proxy: server_config::ProxyConfig::HttpConnect(server_config::ProxyServerConfig {
+                host: "proxy.example".to_string(),
+                port: 3128,
+                username: None,
+                password: None,
+            }),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap_err();
+    assert!(update_error.starts_with("PolicyViolation: "));
+
+    let update_error = update_settings(
+        app.clone(),
+        test_app.state(),
+        serde_json::json!({"server": {"trust": {
+            "spkiSha256Pins": ["AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="],
+        }}}),
+    )
+    .await
+    .unwrap_err();
+    assert!(update_error.starts_with("PolicyViolation: "));
+    assert_eq!(
+        test_app.state().settings.get().server,
+        ServerConfig::default()
+    );
+
+    // Everything else stays editable
+    assert!(update_settings(
+        app,
+        test_app.state(),
+        serde_json::json!({"notifications": false})
+    )
+    .await
+    .is_ok());
+}
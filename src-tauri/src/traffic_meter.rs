@@ -0,0 +1,214 @@
+// Counts and rate limits the bytes moved through the stream under the secure
+// link. This happens below TLS, so handshakes and heartbeats are included.
+
+use crate::bandwidth_limit::BandwidthLimiter;
+use crate::secure_link_client::SecureLinkTraffic;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Sleep;
+
+// Throttled reads and writes check the limiter again at least this often, so
+// a raised limit applies right away
+const MAX_THROTTLE_WAIT: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Default)]
+pub struct TrafficCounters {
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+}
+
+impl TrafficCounters {
+    pub fn reset(&self) {
+        self.bytes_sent.store(0, Ordering::Relaxed);
+        self.bytes_received.store(0, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> SecureLinkTraffic {
+        SecureLinkTraffic {
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+        }
+    }
+}
+
+pub struct MeteredStream<S> {
+    inner: S,
+    counters: Arc<TrafficCounters>,
+    limiter: Arc<BandwidthLimiter>,
+    read_delay: Option<Pin<Box<Sleep>>>,
+    write_delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> MeteredStream<S> {
+    pub fn new(inner: S, counters: Arc<TrafficCounters>, limiter: Arc<BandwidthLimiter>) -> Self {
+        Self {
+            inner,
+            counters,
+            limiter,
+            read_delay: None,
+            write_delay: None,
+        }
+    }
+}
+
+// Waits until `available` lets at least one byte through, and returns how many
+fn poll_allowance(
+    delay: &mut Option<Pin<Box<Sleep>>>,
+    cx: &mut Context<'_>,
+    available: impl Fn(Instant) -> Result<usize, Duration>,
+) -> Poll<usize> {
+    loop {
+        if let Some(sleep) = delay {
+            ready!(sleep.as_mut().poll(cx));
+            *delay = None;
+        }
+
+        match available(Instant::now()) {
+            Ok(allowed) => return Poll::Ready(allowed),
+            Err(wait) => *delay = Some(Box::pin(tokio::time::sleep(wait.min(MAX_THROTTLE_WAIT)))),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for MeteredStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let limiter = this.limiter.clone();
+
+        let allowed = ready!(poll_allowance(&mut this.read_delay, cx, |now| {
+            limiter.download_available(now)
+        }));
+
+        let (poll, received) = if allowed >= buf.remaining() {
+            let filled_before = buf.filled().len();
+            let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+
+            (poll, buf.filled().len() - filled_before)
+        } else {
+            let mut limited_buf = ReadBuf::new(buf.initialize_unfilled_to(allowed));
+            let poll = Pin::new(&mut this.inner).poll_read(cx, &mut limited_buf);
+            let received = limited_buf.filled().len();
+            buf.advance(received);
+
+            (poll, received)
+        };
+
+        if let Poll::Ready(Ok(())) = poll {
+            this.limiter.consume_download(received);
+            this.counters
+                .bytes_received
+                .fetch_add(received as u64, Ordering::Relaxed);
+        }
+
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for MeteredStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let limiter = this.limiter.clone();
+
+        let allowed = ready!(poll_allowance(&mut this.write_delay, cx, |now| {
+            limiter.upload_available(now)
+        }));
+
+        let buf = &buf[..buf.len().min(allowed)];
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+
+        if let Poll::Ready(Ok(written)) = poll {
+            this.limiter.consume_upload(written);
+            this.counters
+                .bytes_sent
+                .fetch_add(written as u64, Ordering::Relaxed);
+        }
+
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bandwidth_limit::BandwidthLimit;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn counts_both_directions() {
+        let counters = Arc::new(TrafficCounters::default());
+        let (local, mut remote) = tokio::io::duplex(64);
+        let mut stream = MeteredStream::new(local, counters.clone(), Default::default());
+
+        stream.write_all(b"hello").await.unwrap();
+        remote.write_all(b"hi").await.unwrap();
+
+        let mut received = [0u8; 2];
+        stream.read_exact(&mut received).await.unwrap();
+
+        assert_eq!(
+            counters.snapshot(),
+            SecureLinkTraffic {
+                bytes_sent: 5,
+                bytes_received: 2,
+            }
+        );
+
+        counters.reset();
+
+        assert_eq!(counters.snapshot(), SecureLinkTraffic::default());
+    }
+
+    #[tokio::test]
+    async fn limits_upload_and_download() {
+        let limiter = Arc::new(BandwidthLimiter::new(&BandwidthLimit {
+            upload_bytes_per_second: Some(20_000),
+            download_bytes_per_second: Some(10_000),
+        }));
+        let (local, mut remote) = tokio::io::duplex(64 * 1024);
+        let mut stream = MeteredStream::new(local, Default::default(), limiter.clone());
+
+        // One second worth of burst, then half a second at the limit
+        let started_at = Instant::now();
+        stream.write_all(&[0u8; 30_000]).await.unwrap();
+
+        assert!(started_at.elapsed() >= Duration::from_millis(450));
+
+        remote.write_all(&[0u8; 15_000]).await.unwrap();
+
+        let started_at = Instant::now();
+        let mut received = vec![0u8; 15_000];
+        stream.read_exact(&mut received).await.unwrap();
+
+        assert!(started_at.elapsed() >= Duration::from_millis(450));
+
+        // Lifting the limit applies to the open stream
+        limiter.set_limit(&BandwidthLimit::default());
+
+        let started_at = Instant::now();
+        stream.write_all(&[0u8; 20_000]).await.unwrap();
+
+        assert!(started_at.elapsed() < Duration::from_millis(200));
+    }
+}
@@ -0,0 +1,760 @@
+// Self-update from a signed release manifest. The manifest names the artifact
+// of every platform together with its SHA-256 and is signed with the Ed25519
+// release key the app was built with, so neither the manifest nor an artifact
+// can be swapped on the release server without that key.
+//
+// The executable that is replaced stays next to the new one until the new
+// version confirms it got through startup. A start that never confirms makes
+// the next start put the previous executable back.
+
+use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+use base64::Engine;
+use ed25519_dalek::{Signature, VerifyingKey};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+const MAX_MANIFEST_LEN: usize = 64 * 1024;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+const INSTALL_MARKER_FILE_NAME: &str = "update_install.json";
+// Starts of a new version that did not get through startup before the next
+// start rolls it back
+const MAX_UNCONFIRMED_STARTS: u32 = 1;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateSettings {
+    // Overrides the manifest URL the app was built with
+    #[serde(default)]
+    pub manifest_url: Option<String>,
+    // Rolled back after it failed to start, so it is not offered again
+    #[serde(default)]
+    pub skipped_version: Option<String>,
+}
+
+impl UpdateSettings {
+    pub fn is_valid(&self) -> bool {
+        match &self.manifest_url {
+            Some(manifest_url) => reqwest::Url::parse(manifest_url)
+                .is_ok_and(|url| matches!(url.scheme(), "http" | "https")),
+            None => true,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum UpdateError {
+    #[error("Release server can not be reached: {0}")]
+    Network(#[from] reqwest::Error),
+
+    #[error("Release server answered with status {0}")]
+    UnexpectedStatus(u16),
+
+    #[error("Release manifest is malformed: {0}")]
+    MalformedManifest(String),
+
+    #[error("Release manifest signature is not valid")]
+    InvalidSignature,
+
+    #[error("Release {0} has no artifact for {1}")]
+    MissingArtifact(String, String),
+
+    #[error("Downloaded artifact does not match the manifest hash")]
+    ArtifactHashMismatch,
+
+    #[error("No update is ready to install")]
+    NothingToInstall,
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+// What the release server serves, `manifest` is the base64 of the
+// `ReleaseManifest` JSON and `signature` the base64 Ed25519 signature over
+// exactly those bytes
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignedReleaseManifest {
+    pub manifest: String,
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReleaseManifest {
+    pub version: String,
+    #[serde(default)]
+    pub notes: Option<String>,
+    // Keyed by `<os>-<arch>` as in `current_platform`
+    pub artifacts: BTreeMap<String, ReleaseArtifact>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReleaseArtifact {
+    pub url: String,
+    // Hex encoded
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AvailableUpdate {
+    pub version: Version,
+    pub notes: Option<String>,
+    pub artifact: ReleaseArtifact,
+}
+
+// Verified against the manifest hash, waiting to be installed
+#[derive(Debug, Clone, PartialEq)]
+pub struct DownloadedUpdate {
+    pub version: Version,
+    pub artifact_path: PathBuf,
+}
+
+// Shown in the UI
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum UpdateStatus {
+    NotConfigured,
+    Idle,
+    Checking,
+    UpToDate,
+    Downloading {
+        version: String,
+    },
+    // Downloaded and verified, installed on restart
+    Ready {
+        version: String,
+        notes: Option<String>,
+    },
+    Failed {
+        error: String,
+    },
+    // The update to `version` did not start and was rolled back
+    RolledBack {
+        version: String,
+    },
+}
+
+pub fn current_platform() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+// The release key is a base64 encoded 32 byte Ed25519 public key
+pub fn decode_public_key(public_key: &str) -> Option<VerifyingKey> {
+    let bytes: [u8; 32] = BASE64_STANDARD
+        .decode(public_key.trim())
+        .ok()?
+        .try_into()
+        .ok()?;
+
+    VerifyingKey::from_bytes(&bytes).ok()
+}
+
+pub fn verify_release_manifest(
+    body: &[u8],
+    public_key: &VerifyingKey,
+) -> Result<ReleaseManifest, UpdateError> {
+    let signed_manifest: SignedReleaseManifest =
+        serde_json::from_slice(body).map_err(|e| UpdateError::MalformedManifest(e.to_string()))?;
+
+    let manifest_bytes = BASE64_STANDARD
+        .decode(&signed_manifest.manifest)
+        .map_err(|e| UpdateError::MalformedManifest(e.to_string()))?;
+
+    let signature = BASE64_STANDARD
+        .decode(&signed_manifest.signature)
+        .ok()
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        .ok_or(UpdateError::InvalidSignature)?;
+
+    public_key
+        .verify_strict(&manifest_bytes, &signature)
+        .map_err(|_| UpdateError::InvalidSignature)?;
+
+    // Nothing in the manifest is looked at before the signature checked out
+    let manifest: ReleaseManifest = serde_json::from_slice(&manifest_bytes)
+        .map_err(|e| UpdateError::MalformedManifest(e.to_string()))?;
+
+    Version::parse(&manifest.version).map_err(|e| UpdateError::MalformedManifest(e.to_string()))?;
+
+    for (platform, artifact) in &manifest.artifacts {
+        if decode_sha256(&artifact.sha256).is_none() {
+            return Err(UpdateError::MalformedManifest(format!(
+                "artifact hash of {} is not a hex SHA-256",
+                platform
+            )));
+        }
+    }
+
+    Ok(manifest)
+}
+
+fn decode_sha256(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut hash = [0u8; 32];
+
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+
+    Some(hash)
+}
+
+pub struct Updater {
+    http_client: reqwest::Client,
+    public_key: VerifyingKey,
+    download_dir: PathBuf,
+}
+
+impl Updater {
+    pub fn new(public_key: VerifyingKey, download_dir: PathBuf) -> Self {
+        let http_client = reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .read_timeout(READ_TIMEOUT)
+            .build()
+            .expect("the HTTP client has a valid default configuration");
+
+        Self {
+            http_client,
+            public_key,
+            download_dir,
+        }
+    }
+
+    // `None` when the release is not newer than `current_version`
+    pub async fn check(
+        &self,
+        manifest_url: &str,
+        current_version: &Version,
+        platform: &str,
+    ) -> Result<Option<AvailableUpdate>, UpdateError> {
+        let mut response = self.get(manifest_url).await?;
+        let mut body = Vec::new();
+
+        while let Some(chunk) = response.chunk().await? {
+            if body.len() + chunk.len() > MAX_MANIFEST_LEN {
+                return Err(UpdateError::MalformedManifest(
+                    "manifest is too large".to_string(),
+                ));
+            }
+
+            body.extend_from_slice(&chunk);
+        }
+
+        let manifest = verify_release_manifest(&body, &self.public_key)?;
+        let version = Version::parse(&manifest.version)
+            .map_err(|e| UpdateError::MalformedManifest(e.to_string()))?;
+
+        if version <= *current_version {
+            return Ok(None);
+        }
+
+        let artifact = manifest.artifacts.get(platform).cloned().ok_or_else(|| {
+            UpdateError::MissingArtifact(manifest.version.clone(), platform.to_string())
+        })?;
+
+        Ok(Some(AvailableUpdate {
+            version,
+            notes: manifest.notes,
+            artifact,
+        }))
+    }
+
+    // A download that does not match the manifest hash is deleted
+    pub async fn download(
+        &self,
+        update: &AvailableUpdate,
+    ) -> Result<DownloadedUpdate, UpdateError> {
+        let expected_sha256 =
+            decode_sha256(&update.artifact.sha256).ok_or(UpdateError::ArtifactHashMismatch)?;
+
+        let artifact_path = self
+            .download_dir
+            .join(format!("secure_link_app-{}.update", update.version));
+        let partial_path = artifact_path.with_extension("part");
+
+        let mut response = self.get(&update.artifact.url).await?;
+        let mut file = tokio::fs::File::create(&partial_path).await?;
+        let mut hasher = Sha256::new();
+
+        let downloaded: Result<(), UpdateError> = async {
+            while let Some(chunk) = response.chunk().await? {
+                hasher.update(&chunk);
+                file.write_all(&chunk).await?;
+            }
+
+            file.flush().await?;
+            Ok(())
+        }
+        .await;
+
+        drop(file);
+
+        if let Err(e) = downloaded {
+            let _ = tokio::fs::remove_file(&partial_path).await;
+            return Err(e);
+        }
+
+        if hasher.finalize().as_slice() != expected_sha256 {
+            let _ = tokio::fs::remove_file(&partial_path).await;
+            return Err(UpdateError::ArtifactHashMismatch);
+        }
+
+        tokio::fs::rename(&partial_path, &artifact_path).await?;
+
+        Ok(DownloadedUpdate {
+            version: update.version.clone(),
+            artifact_path,
+        })
+    }
+
+    async fn get(&self, url: &str) -> Result<reqwest::Response, UpdateError> {
+        let response = self.http_client.get(url).send().await?;
+
+        if !response.status().is_success() {
+            return Err(UpdateError::UnexpectedStatus(response.status().as_u16()));
+        }
+
+        Ok(response)
+    }
+}
+
+// Written next to the settings when an update is installed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InstallMarker {
+    version: String,
+    previous_version: String,
+    unconfirmed_starts: u32,
+    #[serde(default)]
+    rolled_back: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstalledUpdateCheck {
+    // No update was installed since the last start
+    None,
+    // A newly installed version is starting, confirm once it is up
+    Unconfirmed,
+    // The installed version did not start before, the previous executable is
+    // back in place and has to be started instead
+    RestartAfterRollback,
+    // The previous version runs again after the update to `version` was
+    // rolled back
+    RolledBack { version: String },
+}
+
+fn sibling_path(exe_path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = exe_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(suffix);
+    exe_path.with_file_name(file_name)
+}
+
+fn backup_path(exe_path: &Path) -> PathBuf {
+    sibling_path(exe_path, ".previous")
+}
+
+fn read_install_marker(data_dir: &Path) -> Result<Option<InstallMarker>, UpdateError> {
+    match std::fs::read(data_dir.join(INSTALL_MARKER_FILE_NAME)) {
+        Ok(contents) => Ok(serde_json::from_slice(&contents).ok()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn write_install_marker(data_dir: &Path, marker: &InstallMarker) -> Result<(), UpdateError> {
+    let contents = serde_json::to_vec_pretty(marker).map_err(std::io::Error::other)?;
+    std::fs::write(data_dir.join(INSTALL_MARKER_FILE_NAME), contents)?;
+    Ok(())
+}
+
+fn remove_file_if_exists(path: &Path) -> Result<(), UpdateError> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+// Puts the downloaded artifact in place of `exe_path`, the replaced
+// executable is kept until the new version confirmed its start
+pub fn install_update(
+    update: &DownloadedUpdate,
+    exe_path: &Path,
+    data_dir: &Path,
+    current_version: &Version,
+) -> Result<(), UpdateError> {
+    // Copied next to the executable first, so the swap below only renames
+    // within one directory
+    let staged_path = sibling_path(exe_path, ".new");
+    std::fs::copy(&update.artifact_path, &staged_path)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&staged_path, std::fs::Permissions::from_mode(0o755))?;
+    }
+
+    write_install_marker(
+        data_dir,
+        &InstallMarker {
+            version: update.version.to_string(),
+            previous_version: current_version.to_string(),
+            unconfirmed_starts: 0,
+            rolled_back: false,
+        },
+    )?;
+
+    let backup_path = backup_path(exe_path);
+    remove_file_if_exists(&backup_path)?;
+
+    // A running executable can be renamed but not overwritten on Windows
+    let swapped = std::fs::rename(exe_path, &backup_path).and_then(|_| {
+        let renamed = std::fs::rename(&staged_path, exe_path);
+        if renamed.is_err() {
+            let _ = std::fs::rename(&backup_path, exe_path);
+        }
+        renamed
+    });
+
+    if let Err(e) = swapped {
+        let _ = std::fs::remove_file(&staged_path);
+        let _ = std::fs::remove_file(data_dir.join(INSTALL_MARKER_FILE_NAME));
+        return Err(e.into());
+    }
+
+    let _ = std::fs::remove_file(&update.artifact_path);
+
+    Ok(())
+}
+
+// Runs first thing on every start
+pub fn check_installed_update(
+    exe_path: &Path,
+    data_dir: &Path,
+    current_version: &Version,
+) -> Result<InstalledUpdateCheck, UpdateError> {
+    let Some(mut marker) = read_install_marker(data_dir)? else {
+        return Ok(InstalledUpdateCheck::None);
+    };
+
+    let failed_path = sibling_path(exe_path, ".failed");
+
+    if marker.rolled_back {
+        remove_file_if_exists(&failed_path)?;
+        remove_file_if_exists(&data_dir.join(INSTALL_MARKER_FILE_NAME))?;
+
+        return Ok(InstalledUpdateCheck::RolledBack {
+            version: marker.version,
+        });
+    }
+
+    // Another version was installed by other means since
+    if marker.version != current_version.to_string() {
+        remove_file_if_exists(&backup_path(exe_path))?;
+        remove_file_if_exists(&data_dir.join(INSTALL_MARKER_FILE_NAME))?;
+
+        return Ok(InstalledUpdateCheck::None);
+    }
+
+    if marker.unconfirmed_starts >= MAX_UNCONFIRMED_STARTS {
+        let backup_path = backup_path(exe_path);
+
+        // Without the previous executable there is nothing to go back to
+        if !backup_path.exists() {
+            remove_file_if_exists(&data_dir.join(INSTALL_MARKER_FILE_NAME))?;
+            return Ok(InstalledUpdateCheck::None);
+        }
+
+        remove_file_if_exists(&failed_path)?;
+        std::fs::rename(exe_path, &failed_path)?;
+        std::fs::rename(&backup_path, exe_path)?;
+
+        marker.rolled_back = true;
+        write_install_marker(data_dir, &marker)?;
+
+        return Ok(InstalledUpdateCheck::RestartAfterRollback);
+    }
+
+    marker.unconfirmed_starts += 1;
+    write_install_marker(data_dir, &marker)?;
+
+    Ok(InstalledUpdateCheck::Unconfirmed)
+}
+
+// The new version got through startup, the previous executable is no longer
+// needed
+pub fn confirm_installed_update(exe_path: &Path, data_dir: &Path) -> Result<(), UpdateError> {
+    remove_file_if_exists(&backup_path(exe_path))?;
+    remove_file_if_exists(&data_dir.join(INSTALL_MARKER_FILE_NAME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stand_in_release_server::StandInReleaseServer;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    const PLATFORM: &str = "windows-x86_64";
+    const ARTIFACT: &[u8] = b"new secure link app executable";
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_bytes(&[7u8; 32])
+    }
+
+    fn sha256_hex(contents: &[u8]) -> String {
+        Sha256::digest(contents)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    fn release_manifest(version: &str, artifact_url: &str, artifact: &[u8]) -> ReleaseManifest {
+        ReleaseManifest {
+            version: version.to_string(),
+            notes: Some("Fixes".to_string()),
+            artifacts: BTreeMap::from([(
+                PLATFORM.to_string(),
+                ReleaseArtifact {
+                    url: artifact_url.to_string(),
+                    sha256: sha256_hex(artifact),
+                },
+            )]),
+        }
+    }
+
+    fn sign(manifest: &ReleaseManifest, signing_key: &SigningKey) -> Vec<u8> {
+        let manifest_bytes = serde_json::to_vec(manifest).unwrap();
+
+        serde_json::to_vec(&SignedReleaseManifest {
+            manifest: BASE64_STANDARD.encode(&manifest_bytes),
+            signature: BASE64_STANDARD.encode(signing_key.sign(&manifest_bytes).to_bytes()),
+        })
+        .unwrap()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "secure-link-updater-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn updater(dir: &Path) -> Updater {
+        Updater::new(signing_key().verifying_key(), dir.to_path_buf())
+    }
+
+    fn version(version: &str) -> Version {
+        Version::parse(version).unwrap()
+    }
+
+    #[tokio::test]
+    async fn downloads_a_signed_newer_release() {
+        let dir = temp_dir("download");
+        let server = StandInReleaseServer::start().await;
+        server.serve("/app.exe", ARTIFACT);
+        let manifest = release_manifest("0.4.0", &server.url("/app.exe"), ARTIFACT);
+        server.serve("/manifest.json", &sign(&manifest, &signing_key()));
+
+        let updater = updater(&dir);
+
+        let update = updater
+            .check(&server.url("/manifest.json"), &version("0.3.2"), PLATFORM)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(update.version, version("0.4.0"));
+        assert_eq!(update.notes.as_deref(), Some("Fixes"));
+
+        let downloaded = updater.download(&update).await.unwrap();
+        assert_eq!(downloaded.version, version("0.4.0"));
+        assert_eq!(std::fs::read(downloaded.artifact_path).unwrap(), ARTIFACT);
+
+        // Not newer than the running version
+        assert_eq!(
+            updater
+                .check(&server.url("/manifest.json"), &version("0.4.0"), PLATFORM)
+                .await
+                .unwrap(),
+            None
+        );
+
+        assert!(matches!(
+            updater
+                .check(
+                    &server.url("/manifest.json"),
+                    &version("0.3.2"),
+                    "linux-aarch64"
+                )
+                .await,
+            Err(UpdateError::MissingArtifact(_, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_tampered_manifests() {
+        let dir = temp_dir("tampered");
+        let server = StandInReleaseServer::start().await;
+        let manifest = release_manifest("0.4.0", &server.url("/app.exe"), ARTIFACT);
+
+        // Signed with another key
+        server.serve(
+            "/foreign.json",
+            &sign(&manifest, &SigningKey::from_bytes(&[8u8; 32])),
+        );
+
+        // The artifact hash was changed after signing
+        let mut signed: SignedReleaseManifest =
+            serde_json::from_slice(&sign(&manifest, &signing_key())).unwrap();
+        let mut tampered = manifest.clone();
+        tampered.artifacts.get_mut(PLATFORM).unwrap().sha256 = sha256_hex(b"malware");
+        signed.manifest = BASE64_STANDARD.encode(serde_json::to_vec(&tampered).unwrap());
+        server.serve("/tampered.json", &serde_json::to_vec(&signed).unwrap());
+
+        // The signature is missing
+        signed.signature = String::new();
+        server.serve("/unsigned.json", &serde_json::to_vec(&signed).unwrap());
+
+        server.serve("/garbage.json", b"<html>not a manifest</html>");
+
+        let updater = updater(&dir);
+
+        for path in ["/foreign.json", "/tampered.json", "/unsigned.json"] {
+            assert!(
+                matches!(
+                    updater
+                        .check(&server.url(path), &version("0.3.2"), PLATFORM)
+                        .await,
+                    Err(UpdateError::InvalidSignature)
+                ),
+                "{} was accepted",
+                path
+            );
+        }
+
+        assert!(matches!(
+            updater
+                .check(&server.url("/garbage.json"), &version("0.3.2"), PLATFORM)
+                .await,
+            Err(UpdateError::MalformedManifest(_))
+        ));
+
+        assert!(matches!(
+            updater
+                .check(&server.url("/missing.json"), &version("0.3.2"), PLATFORM)
+                .await,
+            Err(UpdateError::UnexpectedStatus(404))
+        ));
+    }
+
+    #[tokio::test]
+    async fn deletes_a_tampered_artifact() {
+        let dir = temp_dir("artifact");
+        let server = StandInReleaseServer::start().await;
+        server.serve("/app.exe", b"malware");
+        let manifest = release_manifest("0.4.0", &server.url("/app.exe"), ARTIFACT);
+        server.serve("/manifest.json", &sign(&manifest, &signing_key()));
+
+        let updater = updater(&dir);
+
+        let update = updater
+            .check(&server.url("/manifest.json"), &version("0.3.2"), PLATFORM)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(matches!(
+            updater.download(&update).await,
+            Err(UpdateError::ArtifactHashMismatch)
+        ));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn rolls_back_a_version_that_does_not_start() {
+        let dir = temp_dir("rollback");
+        let exe_path = dir.join("secure_link_app.exe");
+        let artifact_path = dir.join("download.update");
+        std::fs::write(&exe_path, "old").unwrap();
+        std::fs::write(&artifact_path, "new").unwrap();
+
+        install_update(
+            &DownloadedUpdate {
+                version: version("0.4.0"),
+                artifact_path,
+            },
+            &exe_path,
+            &dir,
+            &version("0.3.2"),
+        )
+        .unwrap();
+        assert_eq!(std::fs::read_to_string(&exe_path).unwrap(), "new");
+
+        // The new version starts but never confirms
+        assert_eq!(
+            check_installed_update(&exe_path, &dir, &version("0.4.0")).unwrap(),
+            InstalledUpdateCheck::Unconfirmed
+        );
+        assert_eq!(
+            check_installed_update(&exe_path, &dir, &version("0.4.0")).unwrap(),
+            InstalledUpdateCheck::RestartAfterRollback
+        );
+        assert_eq!(std::fs::read_to_string(&exe_path).unwrap(), "old");
+
+        assert_eq!(
+            check_installed_update(&exe_path, &dir, &version("0.3.2")).unwrap(),
+            InstalledUpdateCheck::RolledBack {
+                version: "0.4.0".to_string()
+            }
+        );
+        assert_eq!(
+            check_installed_update(&exe_path, &dir, &version("0.3.2")).unwrap(),
+            InstalledUpdateCheck::None
+        );
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn keeps_a_confirmed_version() {
+        let dir = temp_dir("confirm");
+        let exe_path = dir.join("secure_link_app.exe");
+        let artifact_path = dir.join("download.update");
+        std::fs::write(&exe_path, "old").unwrap();
+        std::fs::write(&artifact_path, "new").unwrap();
+
+        install_update(
+            &DownloadedUpdate {
+                version: version("0.4.0"),
+                artifact_path,
+            },
+            &exe_path,
+            &dir,
+            &version("0.3.2"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            check_installed_update(&exe_path, &dir, &version("0.4.0")).unwrap(),
+            InstalledUpdateCheck::Unconfirmed
+        );
+        confirm_installed_update(&exe_path, &dir).unwrap();
+
+        assert_eq!(
+            check_installed_update(&exe_path, &dir, &version("0.4.0")).unwrap(),
+            InstalledUpdateCheck::None
+        );
+        assert_eq!(std::fs::read_to_string(&exe_path).unwrap(), "new");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    }
+}
@@ -1,9 +1,182 @@
 import { useState, useEffect, useRef } from "react";
 import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
 import "./App.css";
 import backgroundUrl from './assets/background.png'
 
-type ConnectionState = 'notConnected' | 'connecting' | 'connected';
+type ConnectionState = 'notConnected' | 'connecting' | 'connected' | 'disconnecting';
+
+type ScheduleWindow = {
+    start: number | null;
+    end: number | null;
+};
+
+type ScheduleStatus = {
+    enabled: boolean;
+    allowed: boolean;
+    currentWindow: ScheduleWindow | null;
+    nextWindow: ScheduleWindow | null;
+};
+
+type IdleStatus =
+    | { kind: 'active' }
+    | { kind: 'warning'; disconnectInMs: number }
+    | { kind: 'timedOut' };
+
+type Throughput = {
+    uploadBytesPerSecond: number;
+    downloadBytesPerSecond: number;
+};
+
+type BandwidthStatus = {
+    limit: {
+        uploadBytesPerSecond: number | null;
+        downloadBytesPerSecond: number | null;
+    };
+    throughput: Throughput | null;
+};
+
+// Links besides the default one, see `list_links`
+type LinkStatus = {
+    profileId: string;
+    name: string;
+    address: string;
+    state: 'Running' | 'Pending' | 'Stopping' | 'Stopped';
+};
+
+const DEFAULT_PROFILE_ID = 'default';
+
+type UpdateStatus =
+    | { kind: 'notConfigured' }
+    | { kind: 'idle' }
+    | { kind: 'checking' }
+    | { kind: 'upToDate' }
+    | { kind: 'downloading'; version: string }
+    | { kind: 'ready'; version: string; notes: string | null }
+    | { kind: 'failed'; error: string }
+    | { kind: 'rolledBack'; version: string };
+
+type ShortcutRegistration =
+    | { kind: 'unset' }
+    | { kind: 'registered' }
+    | { kind: 'invalid'; error: string }
+    | { kind: 'conflict'; error: string };
+
+type ShortcutStatus = {
+    toggleLink: ShortcutRegistration;
+    showWindow: ShortcutRegistration;
+};
+
+// Settings the managed policy of the organization locks, see `get_managed_policy`
+type LockedSetting = 'endpoint' | 'autoConnect' | 'disconnect' | 'authToken' | 'logLevel';
+
+type ManagedPolicyStatus = {
+    source: string | null;
+    lockedSettings: LockedSetting[];
+    // Set when the policy could not be read, nothing is locked then
+    error: string | null;
+};
+
+// The token itself stays in the backend, see `get_auth_token_info`
+type AuthTokenInfo = {
+    present: boolean;
+    fingerprint: string | null;
+    maskedPreview: string | null;
+    storedAt: number | null;
+    expiresAt: number | null;
+};
+
+// See `get_recent_errors`, new ones arrive as `link-error` events
+type ClientErrorRecord = {
+    id: number;
+    at: number;
+    category: 'serverClosed' | 'network' | 'unauthorized' | 'certificatePinMismatch' | 'trustConfig' | 'authToken' | 'other';
+    message: string;
+};
+
+type LinkErrorEvent = {
+    profileId: string;
+    error: ClientErrorRecord;
+};
+
+const describeClientError = (error: ClientErrorRecord): string => {
+    const time = new Date(error.at).toLocaleTimeString([], { hour: '2-digit', minute: '2-digit' });
+
+    switch (error.category) {
+        case 'serverClosed': return `Отключено: сервер закрыл соединение в ${time}`;
+        case 'network': return `Отключено: ошибка сети (${error.message}) в ${time}`;
+        case 'unauthorized': return `Сервер отклонил токен в ${time}`;
+        case 'certificatePinMismatch': return `Сертификат сервера не совпал с закрепленным в ${time}`;
+        case 'trustConfig': return `Отключено: ошибка настроек доверия серверу (${error.message}) в ${time}`;
+        case 'authToken': return `Нет токена подключения`;
+        case 'other': return `Отключено: ${error.message} в ${time}`;
+    }
+};
+
+// See `check_connection`, stages arrive one by one as
+// `connectivity-check-progress` events
+type CheckStage = 'dns' | 'tcpConnect' | 'tlsHandshake' | 'clockSkew' | 'tokenAuth' | 'proxyDetection';
+
+type StageResult = {
+    stage: CheckStage;
+    status: 'passed' | 'failed' | 'skipped';
+    detail: string;
+    advice: string | null;
+    durationMs: number;
+};
+
+type ConnectivityCheckProgress = {
+    profileId: string;
+    result: StageResult;
+};
+
+const CHECK_STAGE_NAMES: Record<CheckStage, string> = {
+    dns: 'DNS',
+    tcpConnect: 'TCP-подключение',
+    tlsHandshake: 'TLS',
+    clockSkew: 'Часы',
+    tokenAuth: 'Токен',
+    proxyDetection: 'Прокси',
+};
+
+type EnrollmentError = {
+    kind: 'notConfigured' | 'malformedCode' | 'unknownCode' | 'expiredCode' | 'codeAlreadyUsed'
+        | 'network' | 'certificatePinMismatch' | 'unexpectedStatus' | 'malformedResponse' | 'apply' | 'policyViolation';
+    message?: string | number;
+};
+
+const ENROLLMENT_ERROR_TEXTS: Record<EnrollmentError['kind'], string> = {
+    notConfigured: 'Сервер регистрации не настроен',
+    malformedCode: 'Неверный формат кода',
+    unknownCode: 'Код не найден',
+    expiredCode: 'Срок действия кода истёк',
+    codeAlreadyUsed: 'Код уже использован',
+    network: 'Сервер регистрации недоступен',
+    certificatePinMismatch: 'Сертификат сервера регистрации не совпал с закрепленным',
+    unexpectedStatus: 'Ошибка сервера регистрации',
+    malformedResponse: 'Некорректный ответ сервера регистрации',
+    apply: 'Не удалось сохранить токен',
+    policyViolation: 'Запрещено политикой организации',
+};
+
+// Policy violations display as "PolicyViolation: ..."
+const formatError = (e: unknown): string =>
+    String(e).startsWith('PolicyViolation') ? 'Запрещено политикой организации' : String(e);
+
+const formatUnixSeconds = (unixTimeSeconds: number): string =>
+    new Date(unixTimeSeconds * 1000).toLocaleString();
+
+const formatRate = (bytesPerSecond: number): string =>
+    bytesPerSecond >= 1024 * 1024
+        ? `${(bytesPerSecond / (1024 * 1024)).toFixed(1)} МБ/с`
+        : `${Math.round(bytesPerSecond / 1024)} КБ/с`;
+
+// Effective rate, followed by the limit when there is one
+const formatRateWithLimit = (bytesPerSecond: number, limit: number | null): string =>
+    limit === null ? formatRate(bytesPerSecond) : `${formatRate(bytesPerSecond)} / ${formatRate(limit)}`;
+
+const formatScheduleTime = (unixTimeMillis: number): string =>
+    new Date(unixTimeMillis).toLocaleString([], { weekday: 'short', hour: '2-digit', minute: '2-digit' });
 
 function App() {
     const contextMenuRef = useRef<HTMLDivElement | null>(null);
@@ -11,9 +184,20 @@ function App() {
     const [error, setError] = useState<string | null>(null);
     const [showTokenModal, setShowTokenModal] = useState<boolean>(false);
     const [showContextMenu, setShowContextMenu] = useState<boolean>(false);
+    // Only what is typed, pasted or revealed in the token modal
     const [token, setToken] = useState<string | null>(null);
-    const [savedToken, setSavedToken] = useState<string>('');
+    const [tokenInfo, setTokenInfo] = useState<AuthTokenInfo | null>(null);
     const [pasteSuccess, setPasteSuccess] = useState<boolean>(false);
+    const [enrollmentCode, setEnrollmentCode] = useState<string>('');
+    const [lockedSettings, setLockedSettings] = useState<LockedSetting[]>([]);
+    const [scheduleStatus, setScheduleStatus] = useState<ScheduleStatus | null>(null);
+    const [idleStatus, setIdleStatus] = useState<IdleStatus | null>(null);
+    const [bandwidthStatus, setBandwidthStatus] = useState<BandwidthStatus | null>(null);
+    const [profileLinks, setProfileLinks] = useState<LinkStatus[]>([]);
+    const [updateStatus, setUpdateStatus] = useState<UpdateStatus | null>(null);
+    // `null` while the check dialog is closed
+    const [checkResults, setCheckResults] = useState<StageResult[] | null>(null);
+    const [checkRunning, setCheckRunning] = useState<boolean>(false);
     const pollingIntervalRef = useRef<number | null>(null);
     const pasteTimeoutRef = useRef<number | null>(null);
 
@@ -21,7 +205,7 @@ function App() {
     const checkServiceStatus = async (): Promise<void> => {
         try {
 
-            const currentState: 'Running' | 'Pending' | 'Stopped' = await invoke("current_state");
+            const currentState: 'Running' | 'Pending' | 'Stopping' | 'Stopped' = await invoke("current_state");
 
             if (currentState === 'Running') {
                 setConnectionState('connected');
@@ -30,6 +214,9 @@ function App() {
             {
                 setConnectionState('connecting');
                 setError(null);
+            }else if (currentState === 'Stopping')
+            {
+                setConnectionState('disconnecting');
             }else if (currentState === 'Stopped') {
                 setConnectionState('notConnected');
             }
@@ -60,16 +247,200 @@ function App() {
         };
     }, []);
 
+    // The schedule only changes at window edges, poll it less often
     useEffect(() => {
+        const checkScheduleStatus = async (): Promise<void> => {
+            try {
+                setScheduleStatus(await invoke("get_schedule_status"));
+            } catch (e) {
+                setScheduleStatus(null);
+            }
+        };
 
-        (async () => {
+        checkScheduleStatus();
+
+        const scheduleIntervalId = setInterval(checkScheduleStatus, 5000);
+
+        return () => clearInterval(scheduleIntervalId);
+    }, []);
+
+    // The idle warning only lasts a minute, poll it every second
+    useEffect(() => {
+        const checkIdleStatus = async (): Promise<void> => {
+            try {
+                setIdleStatus(await invoke("get_idle_status"));
+            } catch (e) {
+                setIdleStatus(null);
+            }
+        };
+
+        checkIdleStatus();
+
+        const idleIntervalId = setInterval(checkIdleStatus, 1000);
+
+        return () => clearInterval(idleIntervalId);
+    }, []);
+
+    // Throughput is averaged between these polls
+    useEffect(() => {
+        const checkBandwidthStatus = async (): Promise<void> => {
+            try {
+                setBandwidthStatus(await invoke("get_bandwidth_status"));
+            } catch (e) {
+                setBandwidthStatus(null);
+            }
+        };
+
+        checkBandwidthStatus();
 
-            const token: string | null = await invoke("get_auth_token");
+        const bandwidthIntervalId = setInterval(checkBandwidthStatus, 1000);
 
-            token && setToken(token)
+        return () => clearInterval(bandwidthIntervalId);
+    }, []);
+
+    useEffect(() => {
+        const checkProfileLinks = async (): Promise<void> => {
+            try {
+                const links: LinkStatus[] = await invoke("list_links");
+                setProfileLinks(links.filter((link) => link.profileId !== DEFAULT_PROFILE_ID));
+            } catch (e) {
+                setProfileLinks([]);
+            }
+        };
 
+        checkProfileLinks();
+
+        const linksIntervalId = setInterval(checkProfileLinks, 1000);
+
+        return () => clearInterval(linksIntervalId);
+    }, []);
+
+    // Updates are checked and downloaded in the background
+    useEffect(() => {
+        const checkUpdateStatus = async (): Promise<void> => {
+            try {
+                setUpdateStatus(await invoke("get_update_status"));
+            } catch (e) {
+                setUpdateStatus(null);
+            }
+        };
+
+        checkUpdateStatus();
+
+        const updateIntervalId = setInterval(checkUpdateStatus, 5000);
+
+        return () => clearInterval(updateIntervalId);
+    }, []);
+
+    // Shortcuts are registered on startup, report the ones that did not work
+    useEffect(() => {
+        (async () => {
+            try {
+                const shortcutStatus: ShortcutStatus = await invoke("get_shortcut_status");
+
+                for (const registration of [shortcutStatus.toggleLink, shortcutStatus.showWindow]) {
+                    if (registration.kind === 'invalid' || registration.kind === 'conflict') {
+                        setError(`Сочетание клавиш не работает: ${registration.error}`);
+                    }
+                }
+            } catch (e) {
+                setError(String(e));
+            }
+        })()
+    }, []);
+
+    // The policy is read once at startup, so it is fetched once too
+    useEffect(() => {
+        (async () => {
+            try {
+                const policyStatus: ManagedPolicyStatus = await invoke("get_managed_policy");
+                setLockedSettings(policyStatus.lockedSettings);
+                if (policyStatus.error) {
+                    setError(`Не удалось прочитать политику организации: ${policyStatus.error}`);
+                }
+            } catch (e) {
+                setError(String(e));
+            }
         })()
+    }, []);
+
+    const isDisconnectLocked = lockedSettings.includes('disconnect');
+    const isAuthTokenLocked = lockedSettings.includes('authToken');
+
+    const handleRestartToUpdateClick = async (): Promise<void> => {
+        try {
+            await invoke("restart_to_update");
+        } catch (e) {
+            setError(String(e));
+        }
+    };
+
+    const handleProfileLinkClick = async (link: LinkStatus): Promise<void> => {
+        try {
+            if (link.state === 'Stopped') {
+                await invoke("start_link", { profileId: link.profileId });
+            } else {
+                await invoke("stop_link", { profileId: link.profileId });
+            }
+            setError(null);
+        } catch (e) {
+            setError(`${link.name}: ${formatError(e)}`);
+        }
+    };
+
+    const getProfileLinkStateText = (state: LinkStatus['state']): string => {
+        switch (state) {
+            case 'Running': return 'подключено';
+            case 'Pending': return 'подключение…';
+            case 'Stopping': return 'отключение…';
+            case 'Stopped': return 'отключено';
+        }
+    };
+
+    const handleKeepConnectedClick = async (): Promise<void> => {
+        try {
+            await invoke("keep_secure_link_connected");
+            setIdleStatus({ kind: 'active' });
+        } catch (e) {
+            setError(String(e));
+        }
+    };
+
+    const refreshTokenInfo = async (): Promise<void> => {
+        try {
+            setTokenInfo(await invoke<AuthTokenInfo>("get_auth_token_info"));
+        } catch (e) {
+            setError(String(e));
+        }
+    };
+
+    useEffect(() => {
+        refreshTokenInfo();
+    }, []);
+
+    // The polling only sees the link stop, the reason comes with the event
+    useEffect(() => {
+        const unlisten = listen<LinkErrorEvent>("link-error", (event) => {
+            if (event.payload.profileId === DEFAULT_PROFILE_ID) {
+                setError(describeClientError(event.payload.error));
+            }
+        });
+
+        return () => {
+            unlisten.then((unlistenFn) => unlistenFn());
+        };
+    }, []);
 
+    useEffect(() => {
+        const unlisten = listen<ConnectivityCheckProgress>("connectivity-check-progress", (event) => {
+            if (event.payload.profileId === DEFAULT_PROFILE_ID) {
+                setCheckResults((results) => results && [...results, event.payload.result]);
+            }
+        });
+
+        return () => {
+            unlisten.then((unlistenFn) => unlistenFn());
+        };
     }, []);
 
     // Close context menu when clicking outside
@@ -95,13 +466,13 @@ function App() {
 
             try {
 
-                if (!token) {
+                if (!tokenInfo?.present) {
                     setShowTokenModal(true)
                     return
                 }
 
                 setConnectionState('connecting');
-                await invoke("start");
+                await startWithScheduleOverride();
                 setConnectionState('connected');
                 setError(null);
 
@@ -112,11 +483,13 @@ function App() {
 
         } else if (connectionState === "connected") {
             try {
+                setConnectionState('disconnecting');
+                // Resolves once the link is down, "forced" when it had to be aborted
                 await invoke("stop");
                 setConnectionState('notConnected');
                 setError(null);
             } catch (e) {
-                setError(String(e));
+                setError(formatError(e));
             }
         } else if (connectionState === "connecting") {
             try {
@@ -124,11 +497,42 @@ function App() {
                 setConnectionState('notConnected');
                 setError(null);
             } catch (e) {
-                setError(String(e));
+                setError(formatError(e));
             }
         }
     };
 
+    // Outside of the schedule windows a connect needs an explicit confirmation
+    const startWithScheduleOverride = async (): Promise<void> => {
+        try {
+            await invoke("start");
+        } catch (e) {
+            if (String(e) !== 'OutsideScheduleWindow') {
+                throw e;
+            }
+
+            if (!window.confirm('Сейчас подключение запрещено расписанием. Подключиться всё равно?')) {
+                throw 'Подключение запрещено расписанием';
+            }
+
+            await invoke("start", { overrideSchedule: true });
+        }
+    };
+
+    const getScheduleText = (): string | null => {
+        if (!scheduleStatus || !scheduleStatus.enabled) {
+            return null;
+        }
+
+        if (scheduleStatus.allowed) {
+            const windowEnd = scheduleStatus.currentWindow?.end;
+            return windowEnd ? `Окно подключения до ${formatScheduleTime(windowEnd)}` : 'Окно подключения открыто';
+        }
+
+        const nextWindowStart = scheduleStatus.nextWindow?.start;
+        return nextWindowStart ? `Следующее окно: ${formatScheduleTime(nextWindowStart)}` : 'Нет окон подключения';
+    };
+
     const handleSettingsClick = (): void => {
         setShowContextMenu(!showContextMenu);
     };
@@ -160,6 +564,21 @@ function App() {
         }
     };
 
+    const handleConnectivityCheckClick = async (): Promise<void> => {
+        setShowContextMenu(false);
+        setCheckResults([]);
+        setCheckRunning(true);
+
+        try {
+            setCheckResults(await invoke<StageResult[]>("check_connection"));
+        } catch (e) {
+            setCheckResults(null);
+            setError(String(e));
+        } finally {
+            setCheckRunning(false);
+        }
+    };
+
     const handlePasteClick = async (): Promise<void> => {
         try {
             // Check if clipboard API is available
@@ -193,6 +612,12 @@ function App() {
         }
     };
 
+    const closeTokenModal = (): void => {
+        setToken(null);
+        setEnrollmentCode('');
+        setShowTokenModal(false);
+    };
+
     const handleTokenSave = async (): Promise<void> => {
 
         if (!token) {
@@ -201,11 +626,57 @@ function App() {
 
         try {
             await invoke("update_auth_token", { authToken: token });
-            setSavedToken(token);
-            setShowTokenModal(false);
+            closeTokenModal();
             setError(null);
+            await refreshTokenInfo();
         } catch (e) {
-            setError(String(e));
+            setError(formatError(e));
+        }
+    };
+
+    // The backend picks the file in a native dialog. Enrollment files may
+    // also set the server or belong to another profile.
+    const handleTokenImport = async (): Promise<void> => {
+        try {
+            await invoke("import_auth_token_from_file");
+            closeTokenModal();
+            setError(null);
+            await refreshTokenInfo();
+        } catch (e) {
+            if (String(e) !== 'ImportCancelled') {
+                setError(String(e));
+            }
+        }
+    };
+
+    const handleEnrollment = async (): Promise<void> => {
+        if (!enrollmentCode.trim()) {
+            return;
+        }
+
+        try {
+            await invoke("enroll_with_code", { code: enrollmentCode });
+            closeTokenModal();
+            setError(null);
+            await refreshTokenInfo();
+        } catch (e) {
+            const enrollmentError = e as EnrollmentError;
+            const text = ENROLLMENT_ERROR_TEXTS[enrollmentError.kind] ?? String(e);
+            setError(enrollmentError.message !== undefined ? `${text}: ${enrollmentError.message}` : text);
+        }
+    };
+
+    // The backend asks for confirmation in a native dialog first. Builds that
+    // do not grant allow-reveal-auth-token refuse it outright.
+    const handleTokenReveal = async (): Promise<void> => {
+        try {
+            setToken(await invoke<string>("reveal_auth_token"));
+        } catch (e) {
+            if (String(e).includes('not allowed')) {
+                setError('Просмотр токена не разрешён');
+            } else if (String(e) !== 'RevealCancelled') {
+                setError(String(e));
+            }
         }
     };
 
@@ -214,6 +685,7 @@ function App() {
             case "connected": return "DISCONNECT";
             case "notConnected": return "CONNECT";
             case "connecting": return "CONNECTING...";
+            case "disconnecting": return "DISCONNECTING...";
             default: return "CONNECT";
         }
     };
@@ -223,6 +695,7 @@ function App() {
             case "connected": return "connected";
             case "notConnected": return "not-connected";
             case "connecting": return "connecting";
+            case "disconnecting": return "connecting";
             default: return "not-connected";
         }
     };
@@ -230,6 +703,53 @@ function App() {
     return (
         <div className="app-container" style={{backgroundImage: `url(${backgroundUrl})`}}>
             <div className="error-display">{error}</div>
+            {getScheduleText() && <div className="schedule-status">{getScheduleText()}</div>}
+            {connectionState === 'connected' && bandwidthStatus?.throughput && (
+                <div className="bandwidth-status">
+                    ↑ {formatRateWithLimit(bandwidthStatus.throughput.uploadBytesPerSecond, bandwidthStatus.limit.uploadBytesPerSecond)}
+                    {' · '}
+                    ↓ {formatRateWithLimit(bandwidthStatus.throughput.downloadBytesPerSecond, bandwidthStatus.limit.downloadBytesPerSecond)}
+                </div>
+            )}
+            {idleStatus?.kind === 'warning' && (
+                <div className="idle-warning">
+                    <span>Нет трафика, отключение через {Math.ceil(idleStatus.disconnectInMs / 1000)} с</span>
+                    <button onClick={handleKeepConnectedClick} className="idle-warning-button">
+                        Оставаться подключённым
+                    </button>
+                </div>
+            )}
+
+            {updateStatus?.kind === 'ready' && (
+                <div className="update-banner" title={updateStatus.notes ?? undefined}>
+                    <span>Доступна версия {updateStatus.version}</span>
+                    <button onClick={handleRestartToUpdateClick} className="update-banner-button">
+                        Перезапустить для обновления
+                    </button>
+                </div>
+            )}
+            {updateStatus?.kind === 'rolledBack' && (
+                <div className="update-banner">
+                    Версия {updateStatus.version} не запустилась, возвращена предыдущая версия
+                </div>
+            )}
+
+            {profileLinks.length > 0 && (
+                <div className="profile-links">
+                    {profileLinks.map((link) => (
+                        <div key={link.profileId} className="profile-link" title={link.address}>
+                            <span>{link.name}: {getProfileLinkStateText(link.state)}</span>
+                            <button
+                                onClick={() => handleProfileLinkClick(link)}
+                                disabled={link.state === 'Stopping' || (link.state !== 'Stopped' && isDisconnectLocked)}
+                                className="profile-link-button"
+                            >
+                                {link.state === 'Stopped' ? 'Подключить' : 'Отключить'}
+                            </button>
+                        </div>
+                    ))}
+                </div>
+            )}
 
             {/* Settings Button with Context Menu */}
             <div className="settings-container">
@@ -249,6 +769,12 @@ function App() {
                         >
                             Настройка подключения
                         </button>
+                        <button
+                            onClick={handleConnectivityCheckClick}
+                            className="context-menu-item"
+                        >
+                            Проверить подключение
+                        </button>
                         <button
                             onClick={handleServiceLogClick}
                             className="context-menu-item"
@@ -262,6 +788,8 @@ function App() {
             {/* Main Button */}
             <button
                 onClick={handleButtonClick}
+                disabled={connectionState !== 'notConnected' && isDisconnectLocked}
+                title={connectionState !== 'notConnected' && isDisconnectLocked ? 'Отключение запрещено политикой организации' : undefined}
                 className={`main-button ${getButtonStateClass()}`}
             >
                 <span className="button-text">{getButtonText()}</span>
@@ -273,6 +801,43 @@ function App() {
                 )}
             </button>
 
+            {/* Connectivity Check Modal */}
+            {checkResults && (
+                <div className="modal-overlay">
+                    <div className="modal-content">
+                        <div className="modal-header">
+                            <h2 className="modal-title">Проверка подключения</h2>
+                        </div>
+
+                        <div className="modal-body">
+                            {checkResults.map((result) => (
+                                <div key={result.stage} className={`check-stage check-stage-${result.status}`}>
+                                    <div className="check-stage-title">
+                                        {result.status === 'passed' ? '✓' : result.status === 'failed' ? '✗' : '–'}{' '}
+                                        {CHECK_STAGE_NAMES[result.stage]}
+                                    </div>
+                                    <div className="check-stage-detail">{result.detail}</div>
+                                    {result.advice && (
+                                        <div className="check-stage-advice">{result.advice}</div>
+                                    )}
+                                </div>
+                            ))}
+                            {checkRunning && <div className="check-stage-detail">Проверяем...</div>}
+                        </div>
+
+                        <div className="modal-actions">
+                            <button
+                                onClick={() => setCheckResults(null)}
+                                disabled={checkRunning}
+                                className="modal-button modal-button-secondary"
+                            >
+                                Закрыть
+                            </button>
+                        </div>
+                    </div>
+                </div>
+            )}
+
             {/* Token Modal */}
             {showTokenModal && (
                 <div className="modal-overlay">
@@ -282,40 +847,83 @@ function App() {
                         </div>
 
                         <div className="modal-body">
+                            {isAuthTokenLocked && (
+                                <p className="policy-lock-note">Токен задан политикой организации</p>
+                            )}
                             <label className="modal-label">Токен</label>
                             <div className="token-input-container">
                                 <input
                                     type="text"
                                     value={token || ''}
                                     onChange={(e: React.ChangeEvent<HTMLInputElement>) => setToken(e.target.value)}
-                                    placeholder="Введите ваш токен..."
+                                    placeholder={tokenInfo?.maskedPreview ?? "Введите ваш токен..."}
+                                    disabled={isAuthTokenLocked}
                                     className="modal-input"
                                 />
                                 <button
                                     onClick={handlePasteClick}
+                                    disabled={isAuthTokenLocked}
                                     className={`paste-button ${pasteSuccess ? 'paste-success' : ''}`}
                                     title="Вставьте из буфера обмена"
                                 >
                                     {pasteSuccess ? '✓' : '⎘'}
                                 </button>
                             </div>
+                            <button onClick={handleTokenImport} disabled={isAuthTokenLocked} className="token-import-button">
+                                Импорт из файла или QR-кода
+                            </button>
+
+                            <label className="modal-label">Код регистрации</label>
+                            <div className="token-input-container">
+                                <input
+                                    type="text"
+                                    value={enrollmentCode}
+                                    onChange={(e: React.ChangeEvent<HTMLInputElement>) => setEnrollmentCode(e.target.value)}
+                                    placeholder="XXXX-XXXX"
+                                    disabled={isAuthTokenLocked}
+                                    className="modal-input"
+                                />
+                                <button
+                                    onClick={handleEnrollment}
+                                    disabled={isAuthTokenLocked || !enrollmentCode.trim()}
+                                    className="enrollment-button"
+                                >
+                                    Получить токен
+                                </button>
+                            </div>
 
-                            {savedToken && (
+                            {tokenInfo?.present && (
                                 <div className="token-status">
-                                    <span className="token-status-text">✓ Токен сохранен</span>
+                                    <span className="token-status-text">
+                                        ✓ Токен сохранен{tokenInfo.storedAt !== null && ` ${formatUnixSeconds(tokenInfo.storedAt)}`}
+                                    </span>
+                                    <span className="token-status-text" title="Отпечаток токена">
+                                        {tokenInfo.fingerprint}
+                                    </span>
+                                    {tokenInfo.expiresAt !== null && (
+                                        <span className="token-status-text">
+                                            Действует до {formatUnixSeconds(tokenInfo.expiresAt)}
+                                        </span>
+                                    )}
+                                    {!token && (
+                                        <button onClick={handleTokenReveal} className="token-reveal-button">
+                                            Показать токен
+                                        </button>
+                                    )}
                                 </div>
                             )}
                         </div>
 
                         <div className="modal-actions">
                             <button
-                                onClick={() => setShowTokenModal(false)}
+                                onClick={closeTokenModal}
                                 className="modal-button modal-button-secondary"
                             >
                                 Cancel
                             </button>
                             <button
                                 onClick={handleTokenSave}
+                                disabled={isAuthTokenLocked}
                                 className="modal-button modal-button-primary"
                             >
                                 Save